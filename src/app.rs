@@ -1,17 +1,70 @@
 use pixels::{Pixels, SurfaceTexture};
 use std::sync::{Arc, Mutex, mpsc};
 use winit::application::ApplicationHandler;
-use winit::event::{KeyEvent, WindowEvent};
+use winit::event::{ElementState, KeyEvent, WindowEvent};
 use winit::event_loop::ActiveEventLoop;
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::{Window, WindowId};
 
+// Events sent from the emulator thread to the window
+#[derive(Debug)]
+pub enum UserEvent {
+    SetTitle(String),
+}
+
+// Emulator controls that aren't part of the Chip-8 keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    SpeedUp,
+    SlowDown,
+    ResetSpeed,
+    SaveState,
+    LoadState,
+    PreviousSlot,
+    NextSlot,
+    // sent when the rewind key goes down, StopRewind when it comes back up
+    Rewind,
+    StopRewind,
+    // tool-assisted mode only
+    Pause,
+    FrameAdvance,
+}
+
+impl Hotkey {
+    fn from_key(physical_key: PhysicalKey) -> Option<Self> {
+        match physical_key {
+            PhysicalKey::Code(KeyCode::Equal) => Some(Hotkey::SpeedUp),
+            PhysicalKey::Code(KeyCode::Minus) => Some(Hotkey::SlowDown),
+            PhysicalKey::Code(KeyCode::Digit0) => Some(Hotkey::ResetSpeed),
+            PhysicalKey::Code(KeyCode::F5) => Some(Hotkey::SaveState),
+            PhysicalKey::Code(KeyCode::F9) => Some(Hotkey::LoadState),
+            PhysicalKey::Code(KeyCode::F6) => Some(Hotkey::PreviousSlot),
+            PhysicalKey::Code(KeyCode::F7) => Some(Hotkey::NextSlot),
+            PhysicalKey::Code(KeyCode::Backspace) => Some(Hotkey::Rewind),
+            PhysicalKey::Code(KeyCode::KeyP) => Some(Hotkey::Pause),
+            PhysicalKey::Code(KeyCode::Space) => Some(Hotkey::FrameAdvance),
+            _ => None,
+        }
+    }
+
+    // the hotkey to send when the key is released, only rewinding is held down
+    fn on_release(self) -> Option<Self> {
+        match self {
+            Hotkey::Rewind => Some(Hotkey::StopRewind),
+            _ => None,
+        }
+    }
+}
+
 pub struct App {
     width: u32,
     height: u32,
     window_title: String,
     key_event_tx: mpsc::Sender<KeyEvent>,
+    hotkey_tx: mpsc::Sender<Hotkey>,
     pixel_buffer_tx: mpsc::Sender<Arc<Mutex<Pixels<'static>>>>,
     pixel_buffer: Option<Arc<Mutex<Pixels<'static>>>>,
+    window: Option<Arc<Window>>,
 }
 
 impl App {
@@ -20,20 +73,23 @@ impl App {
         height: u32,
         window_title: String,
         key_event_tx: mpsc::Sender<KeyEvent>,
+        hotkey_tx: mpsc::Sender<Hotkey>,
         pixel_buffer_tx: mpsc::Sender<Arc<Mutex<Pixels<'static>>>>,
     ) -> Self {
         Self {
-            width: width,
-            height: height,
-            window_title: window_title,
-            key_event_tx: key_event_tx,
-            pixel_buffer_tx: pixel_buffer_tx, 
+            width,
+            height,
+            window_title,
+            key_event_tx,
+            hotkey_tx,
+            pixel_buffer_tx,
             pixel_buffer: None,
+            window: None,
         }
     }
 }
 
-impl ApplicationHandler for App {
+impl ApplicationHandler<UserEvent> for App {
     // We create our window and frame_buffer on resume because the docs say:
     // "It’s recommended that applications should only initialize their graphics context and create a window after they have received
     // their first Resumed event. Some systems (specifically Android) won’t allow applications to create a render surface until they are resumed."
@@ -53,20 +109,27 @@ impl ApplicationHandler for App {
 
         let thread_safe_pixels = Arc::new(Mutex::new(pixels));
         self.pixel_buffer = Some(thread_safe_pixels.clone());
+        self.window = Some(window);
 
         if let Err(e) = self.pixel_buffer_tx.send(thread_safe_pixels.clone()) {
             eprintln!("failed to send pixel_buffer to channel: {}", e);
         }
     }
 
-    // fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: crate::UserEvent) {
-    //     println!("User event received: {event:?}");
-    // }
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: UserEvent) {
+        match event {
+            UserEvent::SetTitle(title) => {
+                if let Some(window) = &self.window {
+                    window.set_title(&title);
+                }
+            }
+        }
+    }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
         match event {
             WindowEvent::CloseRequested => {
-                println!("The close button was pressed; stopping");
+                eprintln!("The close button was pressed; stopping");
                 event_loop.exit();
             }
 
@@ -95,6 +158,20 @@ impl ApplicationHandler for App {
             }
 
             WindowEvent::KeyboardInput { event, .. } => {
+                if let Some(hotkey) = Hotkey::from_key(event.physical_key) {
+                    let hotkey = match event.state {
+                        ElementState::Pressed if !event.repeat => Some(hotkey),
+                        ElementState::Released => hotkey.on_release(),
+                        ElementState::Pressed => None,
+                    };
+                    if let Some(hotkey) = hotkey
+                        && let Err(e) = self.hotkey_tx.send(hotkey)
+                    {
+                        eprintln!("failed to send hotkey to channel: {}", e);
+                    }
+                    return;
+                }
+
                 if let Err(e) = self.key_event_tx.send(event) {
                     eprintln!("failed to send device event to channel: {}", e);
                 }
@@ -0,0 +1,291 @@
+use std::f64::consts::TAU;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Sound generation shared by every audio backend. The Chip-8 beep is synthesized as a tone
+// (or loops a sample loaded by the frontend) and XO-CHIP programs load a 16 byte pattern of
+// 1-bit samples with F002 and pick the speed it is played back at with FX3A.
+// Either way the sound plays for as long as the sound timer is not 0.
+
+// sample rate the buzzer renders at
+pub const SAMPLE_RATE: u32 = 44100;
+
+// bytes in the pattern buffer, 128 1-bit samples
+pub const PATTERN_SIZE: usize = 16;
+
+const PATTERN_BITS: f64 = (PATTERN_SIZE * 8) as f64;
+
+// how long the sound takes to fade in and out, so starting and stopping doesn't pop
+const FADE_SECONDS: f32 = 0.005;
+
+// Shape of the synthesized beep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 3] = [Waveform::Square, Waveform::Sine, Waveform::Triangle];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+        }
+    }
+
+    // the value at phase, which goes from 0.0 to 1.0 over one period
+    fn sample(self, phase: f64) -> f32 {
+        let value = match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        };
+        value as f32
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Waveform::ALL
+            .into_iter()
+            .find(|waveform| waveform.name() == s)
+            .ok_or_else(|| format!("unknown waveform: {}", s))
+    }
+}
+
+// Mono audio loaded by a frontend to use as the beep, looped while the sound timer runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+// What plain Chip-8 programs hear while the sound timer is not 0
+#[derive(Debug, Clone, PartialEq)]
+pub enum Beep {
+    Tone { waveform: Waveform, frequency: f32 },
+    Sample(Arc<Sample>),
+}
+
+impl Beep {
+    pub const DEFAULT_FREQUENCY: f32 = 440.0;
+}
+
+impl Default for Beep {
+    fn default() -> Self {
+        Beep::Tone {
+            waveform: Waveform::Square,
+            frequency: Self::DEFAULT_FREQUENCY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AudioPattern {
+    // played from the most significant bit of the first byte to the least significant bit of the last
+    pub bits: [u8; PATTERN_SIZE],
+
+    // 64 plays the pattern at 4000 bits per second, every 48 up or down doubles or halves that
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    pub const DEFAULT_PITCH: u8 = 64;
+
+    // playback rate in bits per second
+    pub fn bit_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        (self.bits[index / 8] >> (7 - index % 8)) & 1 == 1
+    }
+}
+
+// until a program loads its own pattern, a 250 Hz square wave at the default pitch
+impl Default for AudioPattern {
+    fn default() -> Self {
+        Self {
+            bits: [
+                0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+                0xFF, 0x00,
+            ],
+            pitch: Self::DEFAULT_PITCH,
+        }
+    }
+}
+
+// Renders the sound one sample at a time. Backends feed it whether the sound timer is running
+// and it fades in and out around that, so it sounds the same on a speaker and in a recording.
+#[derive(Debug, Clone)]
+pub struct Buzzer {
+    beep: Beep,
+
+    // set once a program plays XO-CHIP audio, it then replaces the beep
+    pattern: Option<AudioPattern>,
+
+    // 0.0 to 1.0
+    volume: f32,
+
+    sample_rate: u32,
+
+    // where playback is: the phase of a tone from 0.0 to 1.0,
+    // the index into a sample beep or the bit of a pattern
+    position: f64,
+
+    // the fade envelope, 0.0 silent to 1.0 full volume
+    gain: f32,
+}
+
+impl Buzzer {
+    pub fn new(beep: Beep, volume: f32, sample_rate: u32) -> Self {
+        Self {
+            beep,
+            pattern: None,
+            volume,
+            sample_rate,
+            position: 0.0,
+            gain: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // switch to XO-CHIP pattern audio. Playback carries on from the same position
+    // so changing the pitch doesn't click
+    pub fn set_pattern(&mut self, pattern: AudioPattern) {
+        if self.pattern.is_none() {
+            self.position = 0.0;
+        }
+        self.pattern = Some(pattern);
+    }
+
+    pub fn next_sample(&mut self, playing: bool) -> f32 {
+        let fade_step = 1.0 / (FADE_SECONDS * self.sample_rate as f32);
+        self.gain = if playing {
+            (self.gain + fade_step).min(1.0)
+        } else {
+            (self.gain - fade_step).max(0.0)
+        };
+
+        if self.gain == 0.0 {
+            return 0.0;
+        }
+
+        self.next_raw_sample() * self.gain * self.volume
+    }
+
+    // the sound at full volume, between -1.0 and 1.0
+    fn next_raw_sample(&mut self) -> f32 {
+        if let Some(pattern) = &self.pattern {
+            let sample = if pattern.bit(self.position as usize) {
+                1.0
+            } else {
+                -1.0
+            };
+            self.position =
+                (self.position + pattern.bit_rate() / self.sample_rate as f64) % PATTERN_BITS;
+            return sample;
+        }
+
+        match &self.beep {
+            Beep::Tone {
+                waveform,
+                frequency,
+            } => {
+                let sample = waveform.sample(self.position);
+                self.position = (self.position + *frequency as f64 / self.sample_rate as f64) % 1.0;
+                sample
+            }
+            Beep::Sample(sample) => {
+                if sample.samples.is_empty() {
+                    return 0.0;
+                }
+                let value = sample.samples[self.position as usize];
+                self.position = (self.position
+                    + sample.sample_rate as f64 / self.sample_rate as f64)
+                    % sample.samples.len() as f64;
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_bit_rate() {
+        let rate = |pitch| {
+            AudioPattern {
+                pitch,
+                ..AudioPattern::default()
+            }
+            .bit_rate()
+        };
+        assert_eq!(rate(64), 4000.0);
+        assert_eq!(rate(112), 8000.0);
+        assert_eq!(rate(16), 2000.0);
+    }
+
+    #[test]
+    fn pattern_plays_at_its_bit_rate() {
+        // at 4000 samples per second every sample is one bit of the default pattern,
+        // which alternates 8 set and 8 clear bits
+        let mut buzzer = Buzzer::new(Beep::default(), 1.0, 4000);
+        buzzer.set_pattern(AudioPattern::default());
+        let signs: Vec<bool> = (0..16).map(|_| buzzer.next_sample(true) > 0.0).collect();
+        assert_eq!(signs, [[true; 8], [false; 8]].concat());
+
+        // twice the pitch skips every other bit
+        buzzer.set_pattern(AudioPattern {
+            pitch: 112,
+            ..AudioPattern::default()
+        });
+        let signs: Vec<bool> = (0..8).map(|_| buzzer.next_sample(true) > 0.0).collect();
+        assert_eq!(signs, [[true; 4], [false; 4]].concat());
+    }
+
+    #[test]
+    fn fades_in_and_out() {
+        let mut buzzer = Buzzer::new(Beep::default(), 0.5, SAMPLE_RATE);
+        let fade_samples = (FADE_SECONDS * SAMPLE_RATE as f32).ceil() as usize;
+
+        let first = buzzer.next_sample(true);
+        assert!(first > 0.0 && first < 0.01);
+        for _ in 1..fade_samples {
+            buzzer.next_sample(true);
+        }
+        // the square wave is at full volume once the fade is over
+        assert_eq!(buzzer.next_sample(true).abs(), 0.5);
+
+        let stopping = buzzer.next_sample(false).abs();
+        assert!(stopping > 0.0 && stopping < 0.5);
+        for _ in 1..fade_samples {
+            buzzer.next_sample(false);
+        }
+        assert_eq!(buzzer.next_sample(false), 0.0);
+    }
+}
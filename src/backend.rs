@@ -0,0 +1,90 @@
+use crate::audio::AudioPattern;
+use crate::display::DisplayBuffer;
+
+// Backends connect the emulator core to the outside world. The core owns all of the
+// machine state and only talks to a window, keyboard or speaker through these traits,
+// so it can run headless (tests, CI, other frontends) by plugging in the no-op versions below.
+
+// A change to the state of one of the 16 keys on the Chip-8 keypad
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    // the hex value of the key, 0x0 through 0xF
+    pub key: u8,
+    pub pressed: bool,
+}
+
+pub trait Display: Send {
+    // called whenever the screen contents changed during a frame
+    fn present(&mut self, buffer: &DisplayBuffer);
+}
+
+pub trait Input: Send {
+    // returns the next pending key event, or None if nothing happened since the last poll.
+    // The emulator polls at the start of every frame until it gets None, exactly once per frame,
+    // so backends can count frames by it
+    fn poll(&mut self) -> Option<KeyEvent>;
+}
+
+pub trait Audio: Send {
+    // called once per frame, playing is true for as long as the sound timer is not 0
+    fn set_playing(&mut self, playing: bool);
+
+    // silence the sound while no frames run, e.g while paused or rewinding.
+    // Unlike set_playing it doesn't stand for a frame, backends that count frames ignore it
+    fn stop(&mut self) {
+        self.set_playing(false);
+    }
+
+    // XO-CHIP programs play their own audio pattern instead of a beep. Called with the default
+    // pattern when the emulator is created, and again whenever F002 or FX3A change it.
+    // Backends that only know how to beep can ignore it
+    fn set_pattern(&mut self, _pattern: &AudioPattern) {}
+}
+
+// Display that throws every frame away
+#[derive(Debug, Default)]
+pub struct NullDisplay;
+
+impl Display for NullDisplay {
+    fn present(&mut self, _buffer: &DisplayBuffer) {}
+}
+
+// Input where no key is ever pressed
+#[derive(Debug, Default)]
+pub struct NullInput;
+
+impl Input for NullInput {
+    fn poll(&mut self) -> Option<KeyEvent> {
+        None
+    }
+}
+
+// Audio that stays silent
+#[derive(Debug, Default)]
+pub struct NullAudio;
+
+impl Audio for NullAudio {
+    fn set_playing(&mut self, _playing: bool) {}
+}
+
+// so a backend picked at runtime can be handed to the builder or wrapped by another backend
+impl Input for Box<dyn Input> {
+    fn poll(&mut self) -> Option<KeyEvent> {
+        (**self).poll()
+    }
+}
+
+// so a backend picked at runtime can be handed to the builder or wrapped by another backend
+impl Audio for Box<dyn Audio> {
+    fn set_playing(&mut self, playing: bool) {
+        (**self).set_playing(playing);
+    }
+
+    fn stop(&mut self) {
+        (**self).stop();
+    }
+
+    fn set_pattern(&mut self, pattern: &AudioPattern) {
+        (**self).set_pattern(pattern);
+    }
+}
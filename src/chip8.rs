@@ -1,44 +1,84 @@
-use pixels::Pixels;
-use rand::Rng;
-use rodio::Decoder;
-use std::collections::HashSet;
-use std::io::Cursor;
-use std::sync::{Arc, Mutex, mpsc};
-use std::thread;
-use std::time::{Duration, Instant};
-use winit::event::KeyEvent;
-
-pub const DEFAULT_CYCLE_RATE: u16 = 700;
-pub const DISPLAY_WIDTH: u8 = 64;
-pub const DISPLAY_HEIGHT: u8 = 32;
+use crate::audio::{AudioPattern, PATTERN_SIZE};
+use crate::backend::{Audio, Display, Input, NullAudio, NullDisplay, NullInput};
+use crate::display::{DisplayBuffer, PLANE_COUNT, Resolution};
+use crate::error::{EmulatorError, Fault, StateError};
+use crate::instruction::{Instruction, decode};
+use crate::quirks::{IndexIncrement, Platform, Quirks};
+use crate::random::Random;
+use crate::scheduler::{Scheduler, Speed};
+use crate::state::{Reader, Snapshot, Writer};
+use std::fmt;
+
+pub const DEFAULT_CYCLE_RATE: u32 = 700;
+// the timers count down and the screen refreshes at 60 Hz
+pub const FRAME_RATE: u32 = 60;
 
 // convention is to store fonts in memory in addresses 050 - 09F
 const FONT_PC: usize = 0x50;
 
-const LOW_4_BITS_MASK: u16 = 0x000F;
-const LOW_8_BITS_MASK: u16 = 0x00FF;
-const LOW_12_BITS_MASK: u16 = 0x0FFF;
+// the SUPER-CHIP big font goes right after it, in 0A0 - 13F
+const BIG_FONT_PC: usize = 0xA0;
 
 // CHIP-8 programs start at address 0x200 (512 in decimal)
 const PC_START: u16 = 512;
 
 // 4KB of ram
-const RAM_SIZE: usize = 4096;
+pub const RAM_SIZE: usize = 4096;
+
+// XO-CHIP programs can address 64KB
+pub const XO_CHIP_RAM_SIZE: usize = 0x10000;
+
+// how many nested subroutine calls fit on the stack
+pub(crate) const STACK_SIZE: usize = 16;
+
+// number of RPL user flags FX75 and FX85 can save and restore
+pub(crate) const RPL_FLAGS_SIZE: usize = 16;
+
+// How an instruction touched memory, see Emulator::track_memory_accesses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessKind {
+    // the bytes of the instruction itself
+    Fetch,
+    // loads and sprite data
+    Read,
+    Write,
+}
+
+impl fmt::Display for AccessKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccessKind::Fetch => write!(f, "fetch"),
+            AccessKind::Read => write!(f, "read"),
+            AccessKind::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MemoryAccess {
+    pub address: usize,
+    pub kind: AccessKind,
+}
 
 pub struct Emulator {
-    key_event_rx: mpsc::Receiver<KeyEvent>,
+    display: Box<dyn Display>,
+
+    input: Box<dyn Input>,
 
-    pixel_buffer: Arc<Mutex<Pixels<'static>>>,
+    audio: Box<dyn Audio>,
+
+    // The contents of the screen, one bit per pixel, handed to the display backend when it changes
+    display_buffer: DisplayBuffer,
 
     // Should the frame be redrawn this cycle
     should_draw: bool,
 
-    // cycle_rate is the number of instruction cycles to run per second
-    // one cycle is defined as a full fetch/decode/execute loop
+    // decides how many instruction cycles run each frame
     // the standard rate is 700 per second
-    cycle_rate: u16,
+    scheduler: Scheduler,
 
-    memory: [u8; RAM_SIZE],
+    // sized by the platform, see Platform::memory_size
+    memory: Vec<u8>,
 
     // Program counter, often called just “PC”, which points at the current instruction in memory
     pc: u16,
@@ -63,48 +103,70 @@ pub struct Emulator {
     // but which also gives off a beeping sound as long as it’s not 0
     sound_timer: u8,
 
-    // whether to run the SHIFT instructions as per the original spec or not
-    op_shift_original: bool,
+    // the machine being emulated, it decides the memory size and whether there is an audio pattern
+    platform: Platform,
+
+    // how the instructions that differ between platforms behave
+    quirks: Quirks,
+
+    // keep track of which keys are currently pressed, indexed by the hex value of the key
+    pressed_keys: [bool; 16],
+
+    // with the key_wait_release quirk, the key FX0A saw go down and is now waiting to be released
+    key_waiting_for_release: Option<u8>,
+
+    // with the display_wait quirk a draw ends the frame, the rest of the instructions
+    // wait for the next vertical blank
+    waiting_for_vblank: bool,
+
+    // SUPER-CHIP's RPL user flags, registers saved with FX75 that survive until FX85 reads them back
+    rpl_flags: [u8; RPL_FLAGS_SIZE],
 
-    // whether to run the JUMP WITH OFFSET instructions as per the original spec or not
-    // sensible default: true
-    op_jump_with_offset_original: bool,
+    // set once the program runs 00FD, no more instructions are executed after that
+    exited: bool,
 
-    // whether to run the STORE AND LOAD instructions as per the original spec or not
-    // sensible default: false
-    op_store_and_load_original: bool,
+    // the XO-CHIP bitplanes that drawing, clearing and scrolling affect, selected with FN01.
+    // Bit 0 is the first plane, the only one Chip-8 and SUPER-CHIP programs use
+    selected_planes: u8,
 
-    // keep track of which keys are currently pressed, each key is a single hex character
-    pressed_keys: HashSet<u8>,
+    // XO-CHIP's audio pattern buffer and pitch register, played while the sound timer is not 0
+    audio_pattern: AudioPattern,
 
-    audio_sink: rodio::Sink,
+    // where CXNN gets its random numbers, seeded so runs can be reproduced
+    random: Random,
 
-    audio_sink_initialized: bool,
+    // frames run since the program started, input recordings are indexed by it
+    frame: u64,
 
-    beep_audio_bytes: Vec<u8>,
+    // every byte the last instruction fetched, read or wrote, None unless a debugger asked for it
+    memory_accesses: Option<Vec<MemoryAccess>>,
 }
 
 impl Emulator {
     pub fn new(
-        pixel_buffer: Arc<Mutex<Pixels<'static>>>,
-        key_event_rx: mpsc::Receiver<KeyEvent>,
-        cycle_rate: u16,
-        op_shift_original: bool,
-        op_jump_with_offset_original: bool,
-        op_store_and_load_original: bool,
-        audio_sink: rodio::Sink,
-        beep_audio_bytes: Vec<u8>,
+        display: Box<dyn Display>,
+        input: Box<dyn Input>,
+        mut audio: Box<dyn Audio>,
+        scheduler: Scheduler,
+        platform: Platform,
+        quirks: Quirks,
+        random: Random,
     ) -> Self {
-        let mut mem: [u8; RAM_SIZE] = [0; RAM_SIZE];
+        let mut mem = vec![0; platform.memory_size()];
         load_fonts(&mut mem);
 
-        // let sink = rodio::Sink::connect_new(&output_stream.mixer());
+        let audio_pattern = AudioPattern::default();
+        if platform.has_audio_pattern() {
+            audio.set_pattern(&audio_pattern);
+        }
 
-        return Self {
-            key_event_rx: key_event_rx,
-            pixel_buffer: pixel_buffer,
+        Self {
+            display,
+            input,
+            audio,
+            display_buffer: DisplayBuffer::new(),
             should_draw: false,
-            cycle_rate: cycle_rate,
+            scheduler,
             memory: mem,
             pc: PC_START,
             stack: Vec::new(),
@@ -112,86 +174,379 @@ impl Emulator {
             var_registers: [0; 16],
             delay_timer: 60,
             sound_timer: 60,
-            op_shift_original: op_shift_original,
-            op_jump_with_offset_original: op_jump_with_offset_original,
-            op_store_and_load_original: op_store_and_load_original,
-            pressed_keys: HashSet::new(),
-            audio_sink: audio_sink,
-            audio_sink_initialized: false,
-            beep_audio_bytes: beep_audio_bytes,
-        };
+            platform,
+            quirks,
+            pressed_keys: [false; 16],
+            key_waiting_for_release: None,
+            waiting_for_vblank: false,
+            rpl_flags: [0; RPL_FLAGS_SIZE],
+            exited: false,
+            selected_planes: 1,
+            audio_pattern,
+            random,
+            frame: 0,
+            memory_accesses: None,
+        }
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        for (idx, instruction) in rom.iter().enumerate() {
-            let pc: usize = PC_START as usize + idx;
-            self.memory[pc] = *instruction;
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), EmulatorError> {
+        let max_size = self.memory.len() - PC_START as usize;
+        if rom.len() > max_size {
+            return Err(EmulatorError::RomTooLarge {
+                size: rom.len(),
+                max_size,
+            });
         }
+
+        self.memory[PC_START as usize..PC_START as usize + rom.len()].copy_from_slice(&rom);
+        Ok(())
     }
 
-    pub fn run(&mut self) {
-        let target_fps = 60;
-        let frame_duration = Duration::from_secs_f64(1.0 / target_fps as f64);
+    pub fn builder() -> EmulatorBuilder {
+        EmulatorBuilder::new()
+    }
 
-        let mut last_frame_time = Instant::now();
+    // the current contents of the screen
+    pub fn display_buffer(&self) -> &DisplayBuffer {
+        &self.display_buffer
+    }
 
-        let cycle_start = Instant::now();
-        let mut cycles_completed: u64 = 0;
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
 
-        loop {
-            // Calculate the time elapsed since the last frame
-            let elapsed = last_frame_time.elapsed();
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
 
-            // If the elapsed time is less than the desired frame duration, sleep
-            if elapsed < frame_duration {
-                thread::sleep(frame_duration - elapsed);
-            }
+    pub fn var_registers(&self) -> &[u8; 16] {
+        &self.var_registers
+    }
 
-            // Update the last frame time for the next iteration
-            last_frame_time = Instant::now();
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
 
-            self.handle_key_event();
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
 
-            // Calculate how many cycles should have been completed by now
-            // by comparing seconds elapsed * cycle_rate and cycles_completed
-            let cycles_missing =
-                (cycle_start.elapsed().as_secs() * self.cycle_rate as u64) - cycles_completed;
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
 
-            self.execute_cycles(cycles_missing);
+    pub fn audio_pattern(&self) -> &AudioPattern {
+        &self.audio_pattern
+    }
 
-            cycles_completed += cycles_missing;
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
 
-            self.update_sound_timer();
-            self.update_delay_timer();
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
 
-            if self.should_draw {
-                self.render();
-                self.should_draw = false
-            }
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn random(&self) -> &Random {
+        &self.random
+    }
+
+    // the number of the next frame to run, the first frame is 0
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn pressed_keys(&self) -> &[bool; 16] {
+        &self.pressed_keys
+    }
+
+    // press or release a key directly, for frontends that drive the keypad themselves
+    // instead of through the input backend
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        self.pressed_keys[(key & 0xF) as usize] = pressed;
+    }
+
+    // the registers can be changed in between instructions, e.g by a debugger
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_index_register(&mut self, index_register: u16) {
+        self.index_register = index_register;
+    }
+
+    pub fn set_var_register(&mut self, x: u8, val: u8) {
+        self.var_registers[(x & 0xF) as usize] = val;
+    }
+
+    pub fn set_delay_timer(&mut self, delay_timer: u8) {
+        self.delay_timer = delay_timer;
+    }
+
+    pub fn set_sound_timer(&mut self, sound_timer: u8) {
+        self.sound_timer = sound_timer;
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.scheduler.speed()
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.scheduler.set_speed(speed);
+    }
+
+    pub fn frame_rate(&self) -> u32 {
+        self.scheduler.frame_rate()
+    }
+
+    // whether the program exited with 00FD
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    // a copy of the whole machine, call in between frames
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            platform: self.platform,
+            quirks: self.quirks,
+            memory: self.memory.clone(),
+            pc: self.pc,
+            stack: self.stack.clone(),
+            index_register: self.index_register,
+            var_registers: self.var_registers,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            cycle_remainder: self.scheduler.cycle_remainder(),
+            display_buffer: self.display_buffer.clone(),
+            pressed_keys: self.pressed_keys,
+            key_waiting_for_release: self.key_waiting_for_release,
+            rpl_flags: self.rpl_flags,
+            exited: self.exited,
+            selected_planes: self.selected_planes,
+            audio_pattern: self.audio_pattern,
+            random: self.random.clone(),
+            frame: self.frame,
+        }
+    }
+
+    // put the machine back the way it was when the snapshot was taken, platform and quirks included.
+    // The screen is presented again at the end of the next frame
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.platform = snapshot.platform;
+        self.quirks = snapshot.quirks;
+        self.memory.clone_from(&snapshot.memory);
+        self.pc = snapshot.pc;
+        self.stack.clone_from(&snapshot.stack);
+        self.index_register = snapshot.index_register;
+        self.var_registers = snapshot.var_registers;
+        self.delay_timer = snapshot.delay_timer;
+        self.sound_timer = snapshot.sound_timer;
+        self.scheduler.set_cycle_remainder(snapshot.cycle_remainder);
+        self.display_buffer.clone_from(&snapshot.display_buffer);
+        self.pressed_keys = snapshot.pressed_keys;
+        self.key_waiting_for_release = snapshot.key_waiting_for_release;
+        self.rpl_flags = snapshot.rpl_flags;
+        self.exited = snapshot.exited;
+        self.selected_planes = snapshot.selected_planes;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.random.clone_from(&snapshot.random);
+        self.frame = snapshot.frame;
+
+        self.waiting_for_vblank = false;
+        self.should_draw = true;
+        if self.platform.has_audio_pattern() {
+            self.audio.set_pattern(&self.audio_pattern);
+        }
+    }
+
+    // Everything a snapshot holds but the platform, quirks, memory and screen, which
+    // instructions leave alone or change a few bytes of at a time, and also the parts of a
+    // frame in progress that a snapshot leaves out. The debugger's history keeps one of
+    // these before every instruction
+    pub(crate) fn save_registers(&self, data: &mut Writer) {
+        data.u16(self.pc);
+        data.u8(self.stack.len() as u8);
+        for address in &self.stack {
+            data.u16(*address);
+        }
+        data.u16(self.index_register);
+        data.bytes(&self.var_registers);
+        data.u8(self.delay_timer);
+        data.u8(self.sound_timer);
+        data.u32(self.scheduler.cycle_remainder());
+        for pressed in self.pressed_keys {
+            data.bool(pressed);
+        }
+        data.u8(self.key_waiting_for_release.unwrap_or(0xFF));
+        data.bytes(&self.rpl_flags);
+        data.bool(self.exited);
+        data.u8(self.selected_planes);
+        data.bytes(&self.audio_pattern.bits);
+        data.u8(self.audio_pattern.pitch);
+        data.u64(self.random.seed());
+        data.u64(self.random.state());
+        data.u64(self.frame);
+        data.bool(self.waiting_for_vblank);
+        data.bool(self.should_draw);
+    }
+
+    // put back registers written by save_registers
+    pub(crate) fn load_registers(&mut self, data: &mut Reader) -> Result<(), StateError> {
+        self.pc = data.u16()?;
+        self.stack.clear();
+        for _ in 0..data.u8()? {
+            self.stack.push(data.u16()?);
+        }
+        self.index_register = data.u16()?;
+        self.var_registers = data.array()?;
+        self.delay_timer = data.u8()?;
+        self.sound_timer = data.u8()?;
+        self.scheduler.set_cycle_remainder(data.u32()?);
+        for pressed in self.pressed_keys.iter_mut() {
+            *pressed = data.bool()?;
+        }
+        self.key_waiting_for_release = Some(data.u8()?).filter(|key| *key != 0xFF);
+        self.rpl_flags = data.array()?;
+        self.exited = data.bool()?;
+        self.selected_planes = data.u8()?;
+        self.audio_pattern.bits = data.array()?;
+        self.audio_pattern.pitch = data.u8()?;
+        self.random.set_state(data.u64()?, data.u64()?);
+        self.frame = data.u64()?;
+        self.waiting_for_vblank = data.bool()?;
+        self.should_draw = data.bool()?;
+
+        if self.platform.has_audio_pattern() {
+            self.audio.set_pattern(&self.audio_pattern);
+        }
+        Ok(())
+    }
+
+    // change memory without an instruction, e.g to undo one
+    pub(crate) fn set_memory(&mut self, address: usize, val: u8) {
+        self.memory[address] = val;
+    }
+
+    pub(crate) fn set_display_buffer(&mut self, display_buffer: DisplayBuffer) {
+        self.display_buffer = display_buffer;
+    }
+
+    // run the emulator in real time until the program exits or hits an error
+    pub fn run(&mut self) -> Result<(), EmulatorError> {
+        while !self.exited {
+            self.run_due_frames()?;
+        }
+        Ok(())
+    }
+
+    // sleep until the next frame is due, then run it.
+    // After a stall this runs a bounded number of frames back to back to catch up.
+    pub fn run_due_frames(&mut self) -> Result<(), EmulatorError> {
+        let frames = self.wait_for_frames();
+        for _ in 0..frames {
+            self.run_frame()?;
+        }
+        Ok(())
+    }
+
+    // sleep until the next frame is due and return how many frames to run now,
+    // for frontends that do something in between frames
+    pub fn wait_for_frames(&mut self) -> u32 {
+        self.scheduler.wait_for_frames()
+    }
+
+    // present the screen and stop the sound without running a frame,
+    // for when frames aren't running, e.g while rewinding
+    pub fn present_paused(&mut self) {
+        self.display.present(&self.display_buffer);
+        self.should_draw = false;
+        self.audio.stop();
+    }
+
+    // run a single frame as fast as possible: read input, execute one frame's worth
+    // of instructions, tick the timers and present the screen if it changed.
+    // Stops at the first instruction that fails.
+    pub fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        let cycles = self.begin_frame();
+        self.execute_cycles(cycles)?;
+        self.end_frame();
+        Ok(())
+    }
+
+    // The two halves of run_frame, for debuggers that execute a frame's instructions one at a
+    // time with step. begin_frame reads input and returns how many instructions the frame runs,
+    // fewer if frame_is_over says so first
+    pub fn begin_frame(&mut self) -> u32 {
+        let cycles = self.scheduler.cycles_for_frame();
+        self.handle_input();
+        self.waiting_for_vblank = false;
+        cycles
+    }
+
+    // tick the timers and present the screen if it changed
+    pub fn end_frame(&mut self) {
+        self.update_sound_timer();
+        self.update_delay_timer();
+
+        if self.should_draw {
+            self.display.present(&self.display_buffer);
+            self.should_draw = false
+        }
+
+        self.frame += 1;
+    }
+
+    // no more instructions run this frame, the program exited or is waiting for the vertical blank
+    pub fn frame_is_over(&self) -> bool {
+        self.exited || self.waiting_for_vblank
+    }
+
+    // forget about the time that passed since the last frame, e.g after sitting in a debugger
+    pub fn resync(&mut self) {
+        self.scheduler.resync();
+    }
+
+    // execute exactly one instruction, without touching the timers, input or display backends
+    pub fn step(&mut self) -> Result<(), EmulatorError> {
+        if let Some(accesses) = &mut self.memory_accesses {
+            accesses.clear();
         }
+        let instruction = self.fetch()?;
+        self.execute(&instruction)
     }
 
-    fn render(&self) {
-        let locked_buffer = self.pixel_buffer.as_ref().lock().unwrap();
+    // keep a log of the memory the last instruction touched, for watchpoints.
+    // Off by default, it costs a little on every memory access
+    pub fn track_memory_accesses(&mut self, track: bool) {
+        self.memory_accesses = track.then(Vec::new);
+    }
+
+    pub fn is_tracking_memory_accesses(&self) -> bool {
+        self.memory_accesses.is_some()
+    }
 
-        if let Err(e) = locked_buffer.render() {
-            eprintln!("failed to render to pixel buffer in emulator: {}", e);
+    // the bytes the last instruction stepped fetched, read and wrote, in that order.
+    // Empty unless track_memory_accesses is on
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        self.memory_accesses.as_deref().unwrap_or_default()
+    }
+
+    fn record_access(&mut self, address: usize, kind: AccessKind) {
+        if let Some(accesses) = &mut self.memory_accesses {
+            accesses.push(MemoryAccess { address, kind });
         }
     }
 
     fn update_sound_timer(&mut self) {
         if self.sound_timer > 0 {
-            if !self.audio_sink_initialized {
-                let cursor = Cursor::new(self.beep_audio_bytes.clone());
-                let source = Decoder::new_looped(cursor).unwrap();
-                self.audio_sink.append(source);
-                self.audio_sink_initialized = true;
-            }
-
             self.sound_timer -= 1;
-            self.audio_sink.play();
+            self.audio.set_playing(true);
         } else {
-            self.audio_sink.pause();
+            self.audio.set_playing(false);
         }
     }
 
@@ -201,224 +556,452 @@ impl Emulator {
         }
     }
 
-    // Chip8 keypad     QWERTY Keyboard mapping
-    // 1 | 2 | 3 | C        1 | 2 | 3 | 4
-    // 4 | 5 | 6 | D  <=>   Q | W | E | R
-    // 7 | 8 | 9 | E  <=>   A | S | D | F
-    // A | 0 | B | F        Z | X | C | V
-    fn handle_key_event(&mut self) {
-        let event = match self.key_event_rx.try_recv() {
-            Ok(e) => e,
-            Err(_) => return, // no event in channel
-        };
-
-        let chip8_key: u8 = match event.physical_key {
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::Digit1) => 0x1,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::Digit2) => 0x2,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::Digit3) => 0x3,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::Digit4) => 0xC,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyQ) => 0x4,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyW) => 0x5,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyE) => 0x6,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyR) => 0xD,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyA) => 0x7,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyS) => 0x8,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyD) => 0x9,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyF) => 0xE,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyZ) => 0xA,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyX) => 0x0,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyC) => 0xB,
-            winit::keyboard::PhysicalKey::Code(winit::keyboard::KeyCode::KeyV) => 0xF,
-            _ => return, // not a key on the keyboard
-        };
-
-        match event.state {
-            winit::event::ElementState::Released => {
-                self.pressed_keys.remove(&chip8_key);
-            }
-            winit::event::ElementState::Pressed => {
-                self.pressed_keys.insert(chip8_key);
-            }
+    // drain every key event the input backend has queued up since the last frame
+    fn handle_input(&mut self) {
+        while let Some(event) = self.input.poll() {
+            self.pressed_keys[(event.key & 0xF) as usize] = event.pressed;
         }
     }
 
-    fn execute_cycles(&mut self, cycles: u64) {
+    fn execute_cycles(&mut self, cycles: u32) -> Result<(), EmulatorError> {
         for _ in 0..cycles {
-            let instruction: u16 = self.fetch();
-            self.decode_and_execute(instruction);
+            if self.frame_is_over() {
+                break;
+            }
+            self.step()?;
         }
+        Ok(())
     }
 
-    fn fetch(&mut self) -> u16 {
+    fn fetch(&mut self) -> Result<Instruction, EmulatorError> {
         // Read the instruction that PC is currently pointing at from memory.
-        // An instruction is two bytes, read two successive bytes from memory
-        // and combine them into one 16-bit instruction.
-        let mut inst: u16 = 0;
-
-        inst |= self.memory[self.pc as usize] as u16;
-        inst = inst << 8;
-        inst |= self.memory[(self.pc + 1) as usize] as u16;
-
-        self.pc += 2;
-
-        return inst;
-    }
-
-    fn decode_and_execute(&mut self, instruction: u16) {
-        // first nibble that tells you what kind of instruction it is
-        let first_nibble: u16 = (instruction >> 12) & LOW_4_BITS_MASK;
-
-        // The second nibble. Used to look up one of the 16 registers (VX) from V0 through VF
-        let x: u16 = (instruction >> 8) & LOW_4_BITS_MASK;
-
-        // The third nibble. Also used to look up one of the 16 registers (VY) from V0 through VF.
-        let y: u16 = (instruction >> 4) & LOW_4_BITS_MASK;
-
-        // The fourth nibble. A 4-bit number.
-        let n: u16 = instruction & LOW_4_BITS_MASK;
-
-        let nibbles = (first_nibble, x, y, n);
-
-        // The second byte (third and fourth nibbles). An 8-bit immediate number.
-        let nn: u16 = instruction & LOW_8_BITS_MASK;
-
-        // The second, third and fourth nibbles. A 12-bit immediate memory address.
-        let nnn: u16 = instruction & LOW_12_BITS_MASK;
-
-        match nibbles {
-            (0x0, 0x0, 0xE, 0x0) => self.exec_00e0(),
-            (0x0, 0x0, 0xE, 0xE) => self.exec_00ee(),
-            (0x1, _, _, _) => self.exec_1nnn(nnn),
-            (0x2, _, _, _) => self.exec_2nnn(nnn),
-            (0x3, _, _, _) => self.exec_3xnn(x, nn),
-            (0x4, _, _, _) => self.exec_4xnn(x, nn),
-            (0x5, _, _, 0x0) => self.exec_5xy0(x, y),
-            (0x6, _, _, _) => self.exec_6xnn(x, nn),
-            (0x7, _, _, _) => self.exec_7xnn(x, nn),
-            (0x8, _, _, 0x0) => self.exec_8xy0(x, y),
-            (0x8, _, _, 0x1) => self.exec_8xy1(x, y),
-            (0x8, _, _, 0x2) => self.exec_8xy2(x, y),
-            (0x8, _, _, 0x3) => self.exec_8xy3(x, y),
-            (0x8, _, _, 0x4) => self.exec_8xy4(x, y),
-            (0x8, _, _, 0x5) => self.exec_8xy5(x, y),
-            (0x8, _, _, 0x6) => self.exec_8xy6(x, y),
-            (0x8, _, _, 0x7) => self.exec_8xy7(x, y),
-            (0x8, _, _, 0xE) => self.exec_8xye(x, y),
-            (0x9, _, _, 0x0) => self.exec_9xy0(x, y),
-            (0xA, _, _, _) => self.exec_annn(nnn),
-            (0xB, _, _, _) => self.exec_bnnn(x, nnn),
-            (0xC, _, _, _) => self.exec_cxnn(x, nn),
-            (0xD, _, _, _) => self.exec_dxyn(x, y, n),
-            (0xE, _, 0x9, 0xE) => self.exec_ex9e(x),
-            (0xE, _, 0xA, 0x1) => self.exec_exa1(x),
-            (0xF, _, 0x0, 0x7) => self.exec_fx07(x),
-            (0xF, _, 0x1, 0x5) => self.exec_fx15(x),
-            (0xF, _, 0x1, 0x8) => self.exec_fx18(x),
-            (0xF, _, 0x1, 0xE) => self.exec_fx1e(x),
-            (0xF, _, 0x0, 0xA) => self.exec_fx0a(x),
-            (0xF, _, 0x2, 0x9) => self.exec_fx29(x),
-            (0xF, _, 0x3, 0x3) => self.exec_fx33(x),
-            (0xF, _, 0x5, 0x5) => self.exec_fx55(x),
-            (0xF, _, 0x6, 0x5) => self.exec_fx65(x),
-            _ => eprint!("unknown instruction: {:x}", first_nibble),
+        let instruction = self
+            .instruction_at(self.pc)
+            .ok_or(EmulatorError::PcOutOfBounds { pc: self.pc })?;
+
+        for offset in 0..instruction.size() as usize {
+            self.record_access(self.pc as usize + offset, AccessKind::Fetch);
+        }
+        self.pc = self.pc.wrapping_add(instruction.size());
+
+        Ok(instruction)
+    }
+
+    // the instruction stored at an address, None if it runs past the end of memory.
+    // An instruction is two bytes, except for XO-CHIP's F000 NNNN which also
+    // needs the two bytes after it.
+    pub fn instruction_at(&self, address: u16) -> Option<Instruction> {
+        let opcode = self.read_word(address as usize)?;
+        Some(decode(
+            opcode,
+            self.read_word(address as usize + 2).unwrap_or(0),
+        ))
+    }
+
+    // combine two successive bytes into one 16-bit word, None if it runs past the end of memory
+    fn read_word(&self, address: usize) -> Option<u16> {
+        let high = *self.memory.get(address)?;
+        let low = *self.memory.get(address + 1)?;
+        Some((high as u16) << 8 | low as u16)
+    }
+
+    // skip the instruction pc points at, which is 4 bytes long if it is F000 NNNN
+    fn skip_instruction(&mut self) {
+        let opcode = self.read_word(self.pc as usize).unwrap_or(0);
+        self.pc = self.pc.wrapping_add(decode(opcode, 0).size());
+    }
+
+    fn read_memory(&mut self, address: usize) -> Result<u8, Fault> {
+        let val = self
+            .memory
+            .get(address)
+            .copied()
+            .ok_or(Fault::MemoryOutOfBounds(address))?;
+        self.record_access(address, AccessKind::Read);
+        Ok(val)
+    }
+
+    fn write_memory(&mut self, address: usize, val: u8) -> Result<(), Fault> {
+        let cell = self
+            .memory
+            .get_mut(address)
+            .ok_or(Fault::MemoryOutOfBounds(address))?;
+        *cell = val;
+        self.record_access(address, AccessKind::Write);
+        Ok(())
+    }
+
+    // execute an already decoded instruction, the pc has to point past it already
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<(), EmulatorError> {
+        let pc = self.pc.wrapping_sub(instruction.size());
+        self.execute_instruction(instruction)
+            .map_err(|fault| fault.at(pc, instruction.opcode()))
+    }
+
+    fn execute_instruction(&mut self, instruction: &Instruction) -> Result<(), Fault> {
+        match *instruction {
+            Instruction::ClearScreen => self.exec_00e0(),
+            Instruction::Return => self.exec_00ee(),
+            Instruction::ScrollDown { n } => self.exec_00cn(n),
+            Instruction::ScrollUp { n } => self.exec_00dn(n),
+            Instruction::ScrollRight => self.exec_00fb(),
+            Instruction::ScrollLeft => self.exec_00fc(),
+            Instruction::Exit => self.exec_00fd(),
+            Instruction::LowResolution => self.exec_00fe(),
+            Instruction::HighResolution => self.exec_00ff(),
+            Instruction::Jump { nnn } => self.exec_1nnn(nnn),
+            Instruction::Call { nnn } => self.exec_2nnn(nnn),
+            Instruction::SkipIfEqual { x, nn } => self.exec_3xnn(x, nn),
+            Instruction::SkipIfNotEqual { x, nn } => self.exec_4xnn(x, nn),
+            Instruction::SkipIfRegistersEqual { x, y } => self.exec_5xy0(x, y),
+            Instruction::StoreRegisterRange { x, y } => self.exec_5xy2(x, y),
+            Instruction::LoadRegisterRange { x, y } => self.exec_5xy3(x, y),
+            Instruction::SetRegister { x, nn } => self.exec_6xnn(x, nn),
+            Instruction::AddToRegister { x, nn } => self.exec_7xnn(x, nn),
+            Instruction::CopyRegister { x, y } => self.exec_8xy0(x, y),
+            Instruction::Or { x, y } => self.exec_8xy1(x, y),
+            Instruction::And { x, y } => self.exec_8xy2(x, y),
+            Instruction::Xor { x, y } => self.exec_8xy3(x, y),
+            Instruction::Add { x, y } => self.exec_8xy4(x, y),
+            Instruction::Subtract { x, y } => self.exec_8xy5(x, y),
+            Instruction::ShiftRight { x, y } => self.exec_8xy6(x, y),
+            Instruction::SubtractReversed { x, y } => self.exec_8xy7(x, y),
+            Instruction::ShiftLeft { x, y } => self.exec_8xye(x, y),
+            Instruction::SkipIfRegistersNotEqual { x, y } => self.exec_9xy0(x, y),
+            Instruction::SetIndex { nnn } => self.exec_annn(nnn),
+            Instruction::JumpWithOffset { x, nnn } => self.exec_bnnn(x, nnn),
+            Instruction::Random { x, nn } => self.exec_cxnn(x, nn),
+            Instruction::Draw { x, y, n } => self.exec_dxyn(x, y, n),
+            Instruction::SkipIfKeyPressed { x } => self.exec_ex9e(x),
+            Instruction::SkipIfKeyNotPressed { x } => self.exec_exa1(x),
+            Instruction::SetIndexLong { nnnn } => self.exec_f000(nnnn),
+            Instruction::SelectPlanes { n } => self.exec_fn01(n),
+            Instruction::LoadAudioPattern => self.exec_f002(),
+            Instruction::GetDelayTimer { x } => self.exec_fx07(x),
+            Instruction::WaitForKey { x } => self.exec_fx0a(x),
+            Instruction::SetDelayTimer { x } => self.exec_fx15(x),
+            Instruction::SetSoundTimer { x } => self.exec_fx18(x),
+            Instruction::AddToIndex { x } => self.exec_fx1e(x),
+            Instruction::FontCharacter { x } => self.exec_fx29(x),
+            Instruction::BigFontCharacter { x } => self.exec_fx30(x),
+            Instruction::BinaryCodedDecimal { x } => self.exec_fx33(x),
+            Instruction::SetPitch { x } => self.exec_fx3a(x),
+            Instruction::StoreRegisters { x } => self.exec_fx55(x),
+            Instruction::LoadRegisters { x } => self.exec_fx65(x),
+            Instruction::StoreFlags { x } => self.exec_fx75(x),
+            Instruction::LoadFlags { x } => self.exec_fx85(x),
+            Instruction::Unknown(_) => Err(Fault::UnknownOpcode),
         }
     }
 }
 
-impl Emulator {
-    // clear screen
-    fn exec_00e0(&mut self) {
-        let mut locked_buffer = self.pixel_buffer.as_ref().lock().unwrap();
-        let frame = locked_buffer.frame_mut();
-        for pixel in frame.chunks_exact_mut(4) {
-            pixel[0] = 0x00; // R
-            pixel[1] = 0x00; // G
-            pixel[2] = 0x00; // B
-            pixel[3] = 0xff; // A
+// Builds an Emulator, anything that isn't set falls back to the defaults below
+// and the backends default to the headless no-op versions
+pub struct EmulatorBuilder {
+    rom: Vec<u8>,
+    speed: Speed,
+    frame_rate: u32,
+    platform: Platform,
+    quirks: Quirks,
+    // None picks a seed at random
+    seed: Option<u64>,
+    // the COSMAC VIP interpreter CXNN reads from, None for the standard generator
+    vip_interpreter: Option<Vec<u8>>,
+    display: Box<dyn Display>,
+    input: Box<dyn Input>,
+    audio: Box<dyn Audio>,
+}
+
+impl Default for EmulatorBuilder {
+    fn default() -> Self {
+        Self {
+            rom: Vec::new(),
+            speed: Speed::CyclesPerSecond(DEFAULT_CYCLE_RATE),
+            frame_rate: FRAME_RATE,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            seed: None,
+            vip_interpreter: None,
+            display: Box::new(NullDisplay),
+            input: Box::new(NullInput),
+            audio: Box::new(NullAudio),
         }
+    }
+}
+
+impl EmulatorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the program to load at 0x200
+    pub fn rom(mut self, rom: Vec<u8>) -> Self {
+        self.rom = rom;
+        self
+    }
+
+    pub fn speed(mut self, speed: Speed) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    // number of instructions to execute per second
+    pub fn cycle_rate(self, cycles_per_second: u32) -> Self {
+        self.speed(Speed::CyclesPerSecond(cycles_per_second))
+    }
+
+    // frames per second, the timers tick and the screen is presented once per frame
+    pub fn frame_rate(mut self, frame_rate: u32) -> Self {
+        self.frame_rate = frame_rate;
+        self
+    }
+
+    // emulate a platform, with its memory size, audio and quirks
+    pub fn platform(mut self, platform: Platform) -> Self {
+        self.platform = platform;
+        self.quirks(Quirks::for_platform(platform))
+    }
+
+    // override the quirks of the platform, call after platform
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    // start the random number generator from seed, so runs can be reproduced
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // generate random numbers the way the COSMAC VIP interpreter does, which reads them from
+    // its own code. Takes a dump of the interpreter, the emulator doesn't come with one
+    pub fn vip_random(mut self, interpreter: Vec<u8>) -> Self {
+        self.vip_interpreter = Some(interpreter);
+        self
+    }
 
-        self.should_draw = true
+    pub fn display(mut self, display: impl Display + 'static) -> Self {
+        self.display = Box::new(display);
+        self
+    }
+
+    pub fn input(mut self, input: impl Input + 'static) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    pub fn audio(mut self, audio: impl Audio + 'static) -> Self {
+        self.audio = Box::new(audio);
+        self
+    }
+
+    // fails if the rom doesn't fit in memory or the VIP interpreter isn't the right size
+    pub fn build(self) -> Result<Emulator, EmulatorError> {
+        let seed = self.seed.unwrap_or_else(rand::random);
+        let random = match self.vip_interpreter {
+            None => Random::new(seed),
+            Some(interpreter) => {
+                let interpreter = interpreter.try_into().map_err(|interpreter: Vec<u8>| {
+                    EmulatorError::InvalidVipInterpreter {
+                        size: interpreter.len(),
+                    }
+                })?;
+                Random::cosmac_vip(seed, &interpreter)
+            }
+        };
+        let mut emulator = Emulator::new(
+            self.display,
+            self.input,
+            self.audio,
+            Scheduler::new(self.speed, self.frame_rate),
+            self.platform,
+            self.quirks,
+            random,
+        );
+        emulator.load_rom(self.rom)?;
+        Ok(emulator)
+    }
+}
+
+impl Emulator {
+    // clear screen
+    fn exec_00e0(&mut self) -> Result<(), Fault> {
+        self.display_buffer.clear_planes(self.selected_planes);
+        self.should_draw = true;
+        Ok(())
     }
 
     // return from a subroutine
     // i.e pop the last address from the stack and set the pc to it
-    fn exec_00ee(&mut self) {
-        self.pc = self.stack.pop().unwrap();
+    fn exec_00ee(&mut self) -> Result<(), Fault> {
+        self.pc = self.stack.pop().ok_or(Fault::StackUnderflow)?;
+        Ok(())
+    }
+
+    // scroll the display down n pixels
+    fn exec_00cn(&mut self, n: u8) -> Result<(), Fault> {
+        self.display_buffer
+            .scroll_down(self.selected_planes, n as usize);
+        self.should_draw = true;
+        Ok(())
+    }
+
+    // scroll the display up n pixels
+    fn exec_00dn(&mut self, n: u8) -> Result<(), Fault> {
+        self.display_buffer
+            .scroll_up(self.selected_planes, n as usize);
+        self.should_draw = true;
+        Ok(())
+    }
+
+    // scroll the display right 4 pixels
+    fn exec_00fb(&mut self) -> Result<(), Fault> {
+        self.display_buffer.scroll_right(self.selected_planes, 4);
+        self.should_draw = true;
+        Ok(())
+    }
+
+    // scroll the display left 4 pixels
+    fn exec_00fc(&mut self) -> Result<(), Fault> {
+        self.display_buffer.scroll_left(self.selected_planes, 4);
+        self.should_draw = true;
+        Ok(())
+    }
+
+    // exit the interpreter
+    fn exec_00fd(&mut self) -> Result<(), Fault> {
+        self.exited = true;
+        Ok(())
+    }
+
+    // switch to the 64x32 low resolution mode
+    fn exec_00fe(&mut self) -> Result<(), Fault> {
+        self.display_buffer.set_resolution(Resolution::Low);
+        self.should_draw = true;
+        Ok(())
+    }
+
+    // switch to the 128x64 high resolution mode
+    fn exec_00ff(&mut self) -> Result<(), Fault> {
+        self.display_buffer.set_resolution(Resolution::High);
+        self.should_draw = true;
+        Ok(())
     }
 
     // jump, set program counter to nnn
-    fn exec_1nnn(&mut self, nnn: u16) {
+    fn exec_1nnn(&mut self, nnn: u16) -> Result<(), Fault> {
         self.pc = nnn;
+        Ok(())
     }
 
     // call the subroutine at memory location nnn
     // push current pc to stack first so we can return
-    fn exec_2nnn(&mut self, nnn: u16) {
+    fn exec_2nnn(&mut self, nnn: u16) -> Result<(), Fault> {
+        if self.stack.len() == STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
         self.stack.push(self.pc);
         self.pc = nnn;
+        Ok(())
     }
 
     // skip one instruction if the value in vx is equal to nn
-    fn exec_3xnn(&mut self, x: u16, nn: u16) {
-        if self.var_registers[x as usize] as u16 == nn {
-            self.pc += 2;
+    fn exec_3xnn(&mut self, x: u8, nn: u8) -> Result<(), Fault> {
+        if self.var_registers[x as usize] == nn {
+            self.skip_instruction();
         }
+        Ok(())
     }
 
     // skip one instruction if the value in vx is NOT equal to nn
-    fn exec_4xnn(&mut self, x: u16, nn: u16) {
-        if self.var_registers[x as usize] as u16 != nn {
-            self.pc += 2;
+    fn exec_4xnn(&mut self, x: u8, nn: u8) -> Result<(), Fault> {
+        if self.var_registers[x as usize] != nn {
+            self.skip_instruction();
         }
+        Ok(())
     }
 
     // skip one instruction if the values in vx and vy are equal
-    fn exec_5xy0(&mut self, x: u16, y: u16) {
+    fn exec_5xy0(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         if self.var_registers[x as usize] == self.var_registers[y as usize] {
-            self.pc += 2;
+            self.skip_instruction();
+        }
+        Ok(())
+    }
+
+    // save the registers vx to vy inclusive in memory starting at the index register,
+    // in reverse order if x is bigger than y. The index register is left alone
+    fn exec_5xy2(&mut self, x: u8, y: u8) -> Result<(), Fault> {
+        for (offset, register) in register_range(x, y).enumerate() {
+            let address = self.index_register as usize + offset;
+            self.write_memory(address, self.var_registers[register])?;
         }
+        Ok(())
+    }
+
+    // load the registers vx to vy inclusive from memory starting at the index register,
+    // the opposite of 5xy2
+    fn exec_5xy3(&mut self, x: u8, y: u8) -> Result<(), Fault> {
+        for (offset, register) in register_range(x, y).enumerate() {
+            let address = self.index_register as usize + offset;
+            self.var_registers[register] = self.read_memory(address)?;
+        }
+        Ok(())
     }
 
     // set vx reg to nn
-    fn exec_6xnn(&mut self, x: u16, nn: u16) {
-        self.var_registers[x as usize] = nn as u8;
+    fn exec_6xnn(&mut self, x: u8, nn: u8) -> Result<(), Fault> {
+        self.var_registers[x as usize] = nn;
+        Ok(())
     }
 
     // add value nn to vx reg
-    fn exec_7xnn(&mut self, x: u16, nn: u16) {
-        let val: u8 = self.var_registers[x as usize].wrapping_add(nn as u8);
+    fn exec_7xnn(&mut self, x: u8, nn: u8) -> Result<(), Fault> {
+        let val: u8 = self.var_registers[x as usize].wrapping_add(nn);
         self.var_registers[x as usize] = val;
+        Ok(())
     }
 
     // set vx to value of vy
-    fn exec_8xy0(&mut self, x: u16, y: u16) {
+    fn exec_8xy0(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         self.var_registers[x as usize] = self.var_registers[y as usize];
+        Ok(())
     }
 
     // set vx to the binary OR of vx and vy
-    // cosmac vip also resets VF
-    fn exec_8xy1(&mut self, x: u16, y: u16) {
+    // cosmac vip also resets VF (vf_reset quirk)
+    fn exec_8xy1(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         self.var_registers[x as usize] |= self.var_registers[y as usize];
-        self.var_registers[0xf] = 0;
+        if self.quirks.vf_reset {
+            self.var_registers[0xf] = 0;
+        }
+        Ok(())
     }
 
     // set vx to the binary AND of vx and vy
-    // cosmac vip also resets VF
-    fn exec_8xy2(&mut self, x: u16, y: u16) {
+    // cosmac vip also resets VF (vf_reset quirk)
+    fn exec_8xy2(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         self.var_registers[x as usize] &= self.var_registers[y as usize];
-        self.var_registers[0xf] = 0;
+        if self.quirks.vf_reset {
+            self.var_registers[0xf] = 0;
+        }
+        Ok(())
     }
 
     // set vx to the binary XOR of vx and vy
-    // cosmac vip also resets VF
-    fn exec_8xy3(&mut self, x: u16, y: u16) {
+    // cosmac vip also resets VF (vf_reset quirk)
+    fn exec_8xy3(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         self.var_registers[x as usize] ^= self.var_registers[y as usize];
-        self.var_registers[0xf] = 0;
+        if self.quirks.vf_reset {
+            self.var_registers[0xf] = 0;
+        }
+        Ok(())
     }
 
     // set vx to the sume of vx and vy
     // if it overflows, set vf to 1 otherwise set it to 0
-    fn exec_8xy4(&mut self, x: u16, y: u16) {
+    fn exec_8xy4(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         let vx = self.var_registers[x as usize];
         let vy = self.var_registers[y as usize];
         let (result, overflow) = vx.overflowing_add(vy);
@@ -427,12 +1010,14 @@ impl Emulator {
         if overflow {
             self.var_registers[0xf] = 1;
         }
+
+        Ok(())
     }
 
     // sets vx to result of vx - vy
     // If the minuend (the first operand) is larger than the subtrahend (second operand),
     // VF will be set to 1. If the subtrahend is larger, and we “underflow” the result, VF is set to 0
-    fn exec_8xy5(&mut self, x: u16, y: u16) {
+    fn exec_8xy5(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         let vx = self.var_registers[x as usize];
         let vy = self.var_registers[y as usize];
         let (result, overflow) = vx.overflowing_sub(vy);
@@ -442,26 +1027,30 @@ impl Emulator {
         } else {
             self.var_registers[0xf] = 1;
         }
+
+        Ok(())
     }
 
     // put the value of VY into VX, and then shift the value in VX 1 bit to the right
     // the flag register VF would be set to the bit that was shifted out.
     // However, starting with CHIP-48 and SUPER-CHIP in the early 1990s,
     // this instruction was changed so that they shifted VX in place, and ignored the Y completely.
-    fn exec_8xy6(&mut self, x: u16, y: u16) {
+    fn exec_8xy6(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         let mut val = self.var_registers[y as usize];
-        if !self.op_shift_original {
+        if !self.quirks.shift_uses_vy {
             val = self.var_registers[x as usize];
         }
         let new_val = val >> 1;
         self.var_registers[x as usize] = new_val;
         self.var_registers[0xf] = val & 1;
+
+        Ok(())
     }
 
     // sets vx to result of vy - vx
     // If the minuend (the first operand) is larger than the subtrahend (second operand),
     // VF will be set to 1. If the subtrahend is larger, and we “underflow” the result, VF is set to 0
-    fn exec_8xy7(&mut self, x: u16, y: u16) {
+    fn exec_8xy7(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         let vx = self.var_registers[x as usize];
         let vy = self.var_registers[y as usize];
         let (result, overflow) = vy.overflowing_sub(vx);
@@ -471,52 +1060,60 @@ impl Emulator {
         } else {
             self.var_registers[0xf] = 1;
         }
+
+        Ok(())
     }
 
     // put the value of VY into VX, and then shift the value in VX 1 bit to the left
     // the flag register VF would be set to the bit that was shifted out.
     // However, starting with CHIP-48 and SUPER-CHIP in the early 1990s,
     // this instruction was changed so that they shifted VX in place, and ignored the Y completely.
-    fn exec_8xye(&mut self, x: u16, y: u16) {
+    fn exec_8xye(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         let mut val = self.var_registers[y as usize];
-        if !self.op_shift_original {
+        if !self.quirks.shift_uses_vy {
             val = self.var_registers[x as usize];
         }
         let new_val = val << 1;
         self.var_registers[x as usize] = new_val;
         self.var_registers[0xf] = (val & 0b1000_0000) >> 7;
+
+        Ok(())
     }
 
     // skip one instruction if the values in vx and vy are NOT equal
-    fn exec_9xy0(&mut self, x: u16, y: u16) {
+    fn exec_9xy0(&mut self, x: u8, y: u8) -> Result<(), Fault> {
         if self.var_registers[x as usize] != self.var_registers[y as usize] {
-            self.pc += 2;
+            self.skip_instruction();
         }
+        Ok(())
     }
 
     // set index register to nnn
-    fn exec_annn(&mut self, nnn: u16) {
+    fn exec_annn(&mut self, nnn: u16) -> Result<(), Fault> {
         self.index_register = nnn;
+        Ok(())
     }
 
     // jump to the address nnn plus the value in the register v0
     //
     // Starting with CHIP-48 and SUPER-CHIP, it was changed to work as bxnn:
     // It will jump to the address xnn, plus the value in the register vx
-    fn exec_bnnn(&mut self, x: u16, nnn: u16) {
+    fn exec_bnnn(&mut self, x: u8, nnn: u16) -> Result<(), Fault> {
         let mut val = self.var_registers[0] as u16;
-        if !self.op_jump_with_offset_original {
+        if self.quirks.jump_uses_vx {
             val = self.var_registers[x as usize] as u16;
         }
         val += nnn;
         self.pc = val;
+
+        Ok(())
     }
 
     // generates a random number, binary ANDs it with the value nn, and puts the result in x
-    fn exec_cxnn(&mut self, x: u16, nn: u16) {
-        let mut rng = rand::rng();
-        let val: u16 = rng.random();
-        self.var_registers[x as usize] = (val & nn) as u8;
+    fn exec_cxnn(&mut self, x: u8, nn: u8) -> Result<(), Fault> {
+        let val = self.random.next_byte();
+        self.var_registers[x as usize] = val & nn;
+        Ok(())
     }
 
     // draw an "n" pixels tall sprite from the memory location that the I index register
@@ -524,171 +1121,291 @@ impl Emulator {
     // All the pixels that are “on” in the sprite will flip the pixels on the screen that it is drawn to
     // (from left to right, from most to least significant bit).
     // If any pixels on the screen were turned “off” by this, the VF flag register is set to 1. Otherwise, it’s set to 0.
-    fn exec_dxyn(&mut self, x: u16, y: u16, n: u16) {
+    //
+    // SUPER-CHIP draws a 16x16 sprite when n is 0, each row is two bytes.
+    // In high resolution mode SUPER-CHIP 1.1 sets VF to the number of rows that collided
+    // or were clipped at the bottom of the screen instead (collision_row_count quirk).
+    fn exec_dxyn(&mut self, x: u8, y: u8, n: u8) -> Result<(), Fault> {
         self.should_draw = true;
 
-        let mut locked_buffer = self.pixel_buffer.as_ref().lock().unwrap();
-        let frame = locked_buffer.frame_mut();
+        let width = self.display_buffer.width() as u16;
+        let height = self.display_buffer.height() as u16;
 
         // The starting position of the sprite will wrap. Another way of saying it is that the coordinates are modulo
         // (or binary AND) the size of the display (when counting from 0).
         //
-        // However, the actual drawing of the sprite should not wrap. If a sprite is drawn near the edge of the screen,
-        // it should be clipped, and not wrap. The sprite should be partly drawn near the edge,
-        // and the other part should not reappear on the opposite side of the screen.
-        let vx: u16 = self.var_registers[x as usize] as u16 % 64;
-        let vy: u16 = self.var_registers[y as usize] as u16 % 32;
-
-        self.var_registers[0xF] = 0;
-
-        // how many rows tall
-        for i in 0..n {
-            // stop drawing if we reached the bottom row
-            if vy + i == DISPLAY_HEIGHT as u16 {
-                break;
+        // Whether the actual drawing of the sprite wraps depends on the clip_sprites quirk. When clipping, a sprite
+        // drawn near the edge of the screen is only partly drawn, and the other part does not reappear on the
+        // opposite side of the screen.
+        let vx: u16 = self.var_registers[x as usize] as u16 % width;
+        let vy: u16 = self.var_registers[y as usize] as u16 % height;
+        let wrap = !self.quirks.clip_sprites;
+
+        let (sprite_width, sprite_height): (usize, u16) =
+            if n == 0 { (16, 16) } else { (8, n as u16) };
+        let bytes_per_row = sprite_width / 8;
+
+        let mut collided_rows: u8 = 0;
+        let mut clipped_rows: u8 = 0;
+
+        // XO-CHIP draws to every selected plane, the sprite data for the second plane
+        // comes right after the data for the first one
+        let mut sprite_address = self.index_register as usize;
+        for plane in 0..PLANE_COUNT {
+            if self.selected_planes & (1 << plane) == 0 {
+                continue;
             }
 
-            let sprite_data = self.memory[(self.index_register + i) as usize];
+            // how many rows tall
+            for i in 0..sprite_height {
+                let mut row = vy + i;
+                if row >= height {
+                    // stop drawing if we reached the bottom row
+                    if !wrap {
+                        clipped_rows += (sprite_height - i) as u8;
+                        break;
+                    }
+                    row %= height;
+                }
 
-            for j in 0..8 {
-                // stop drawing if we reached the right edge
-                if vx + j == DISPLAY_WIDTH as u16 {
-                    break;
+                let row_address = sprite_address + i as usize * bytes_per_row;
+                let mut sprite_data: u16 = 0;
+                for byte in 0..bytes_per_row {
+                    sprite_data = sprite_data << 8 | self.read_memory(row_address + byte)? as u16;
                 }
 
-                // go from most significant bit to least
-                let sprite_pixel_on = ((sprite_data >> (7 - j)) & 1) == 1;
-
-                // The frame buffer is a 1D array representing a 2D space
-                let frame_x = (vx + j) as usize;
-                let frame_y = (vy + i) as usize;
-                let mut frame_pixel_idx = frame_x + (frame_y * DISPLAY_WIDTH as usize);
-
-                // The frame buffer is of length W x L x 4. 4 because each pixel is an RGBA value,
-                // i.e each "pixel" is 4 consecutive elements in the buffer. So we must multiple our index by 4
-                // to get the correct starting index of the pixel.
-                frame_pixel_idx *= 4;
-                let display_pixel_on = frame[frame_pixel_idx] != 0;
-
-                if display_pixel_on && sprite_pixel_on {
-                    // turn pixel off (R, G, B)
-                    frame[frame_pixel_idx] = 0x00;
-                    frame[frame_pixel_idx + 1] = 0x00;
-                    frame[frame_pixel_idx + 2] = 0x00;
-                    self.var_registers[0xF] = 1;
-                } else if !display_pixel_on && sprite_pixel_on {
-                    // turn pixel on (R, G, B)
-                    frame[frame_pixel_idx] = 0xFF;
-                    frame[frame_pixel_idx + 1] = 0xFF;
-                    frame[frame_pixel_idx + 2] = 0xFF;
+                // each row of the sprite is drawn from most significant bit to least.
+                // Pixels past the right edge are clipped or wrapped by the display buffer.
+                if self.display_buffer.draw_sprite_row(
+                    plane,
+                    vx as usize,
+                    row as usize,
+                    sprite_data,
+                    sprite_width,
+                    wrap,
+                ) {
+                    collided_rows += 1;
                 }
             }
+
+            sprite_address += sprite_height as usize * bytes_per_row;
         }
+
+        let hires = self.display_buffer.resolution() == Resolution::High;
+        self.var_registers[0xF] = if self.quirks.collision_row_count && hires {
+            collided_rows + clipped_rows
+        } else {
+            (collided_rows > 0) as u8
+        };
+
+        // SUPER-CHIP only waits for the vertical blank in low resolution mode
+        if self.quirks.display_wait && !hires {
+            self.waiting_for_vblank = true;
+        }
+
+        Ok(())
     }
 
     // skip one instruction (increment PC by 2) if the key corresponding to the value in vx is pressed
-    fn exec_ex9e(&mut self, x: u16) {
-        let key = self.var_registers[x as usize];
-        if self.pressed_keys.contains(&key) {
-            self.pc += 2;
+    fn exec_ex9e(&mut self, x: u8) -> Result<(), Fault> {
+        let key = self.var_registers[x as usize] & 0xF;
+        if self.pressed_keys[key as usize] {
+            self.skip_instruction();
         }
+
+        Ok(())
     }
 
     // skip one instruction (increment PC by 2) if the key corresponding to the value in vx NOT is pressed
-    fn exec_exa1(&mut self, x: u16) {
-        let key = self.var_registers[x as usize];
-        if !self.pressed_keys.contains(&key) {
-            self.pc += 2;
+    fn exec_exa1(&mut self, x: u8) -> Result<(), Fault> {
+        let key = self.var_registers[x as usize] & 0xF;
+        if !self.pressed_keys[key as usize] {
+            self.skip_instruction();
+        }
+
+        Ok(())
+    }
+
+    // set the index register to the 16-bit address nnnn in the two bytes after the instruction
+    fn exec_f000(&mut self, nnnn: u16) -> Result<(), Fault> {
+        self.index_register = nnnn;
+        Ok(())
+    }
+
+    // select the bitplanes that drawing, clearing and scrolling affect, n is a bit mask
+    fn exec_fn01(&mut self, n: u8) -> Result<(), Fault> {
+        self.selected_planes = n & ((1 << PLANE_COUNT) - 1);
+        Ok(())
+    }
+
+    // load the 16 byte audio pattern from memory starting at the index register
+    fn exec_f002(&mut self) -> Result<(), Fault> {
+        for i in 0..PATTERN_SIZE {
+            self.audio_pattern.bits[i] = self.read_memory(self.index_register as usize + i)?;
         }
+        self.audio.set_pattern(&self.audio_pattern);
+        Ok(())
+    }
+
+    // set the pitch the audio pattern is played back at to the value in vx
+    fn exec_fx3a(&mut self, x: u8) -> Result<(), Fault> {
+        self.audio_pattern.pitch = self.var_registers[x as usize];
+        self.audio.set_pattern(&self.audio_pattern);
+        Ok(())
     }
 
     // set vx to the current value of the delay timer
-    fn exec_fx07(&mut self, x: u16) {
+    fn exec_fx07(&mut self, x: u8) -> Result<(), Fault> {
         self.var_registers[x as usize] = self.delay_timer;
+        Ok(())
     }
 
     // set the delay timer to the value in vx
-    fn exec_fx15(&mut self, x: u16) {
-        self.delay_timer = self.var_registers[x as usize] as u8;
+    fn exec_fx15(&mut self, x: u8) -> Result<(), Fault> {
+        self.delay_timer = self.var_registers[x as usize];
+        Ok(())
     }
 
     // set the sound timer to the value in vx
-    fn exec_fx18(&mut self, x: u16) {
-        self.sound_timer = self.var_registers[x as usize] as u8;
+    fn exec_fx18(&mut self, x: u8) -> Result<(), Fault> {
+        self.sound_timer = self.var_registers[x as usize];
+        Ok(())
     }
 
     // add to index, add the value of vx to the index register
-    fn exec_fx1e(&mut self, x: u16) {
-        self.index_register += self.var_registers[x as usize] as u16;
+    fn exec_fx1e(&mut self, x: u8) -> Result<(), Fault> {
+        self.index_register = self
+            .index_register
+            .wrapping_add(self.var_registers[x as usize] as u16);
+        Ok(())
     }
 
     // “blocks”; it stops executing instructions and waits for key input
     // (or loops forever, unless a key is pressed).
+    // On the COSMAC VIP the key also has to be released again (key_wait_release quirk).
     // PC is decremented here since it is incremented in the fetch phase
-    fn exec_fx0a(&mut self, x: u16) {
-        if let Some(key) = self.pressed_keys.iter().next() {
-            self.var_registers[x as usize] = *key;
-        } else {
-            self.pc -= 2;
+    fn exec_fx0a(&mut self, x: u8) -> Result<(), Fault> {
+        let pressed_key = self.pressed_keys.iter().position(|pressed| *pressed);
+
+        if !self.quirks.key_wait_release {
+            match pressed_key {
+                Some(key) => self.var_registers[x as usize] = key as u8,
+                None => self.repeat_instruction(),
+            }
+            return Ok(());
+        }
+
+        match self.key_waiting_for_release {
+            Some(key) if !self.pressed_keys[key as usize] => {
+                self.var_registers[x as usize] = key;
+                self.key_waiting_for_release = None;
+            }
+            Some(_) => self.repeat_instruction(),
+            None => {
+                self.key_waiting_for_release = pressed_key.map(|key| key as u8);
+                self.repeat_instruction();
+            }
         }
+
+        Ok(())
+    }
+
+    // point pc back at the 2 byte instruction that was just fetched. Fetching the last
+    // one in 64KB of memory wraps pc around to 0, which wraps back here
+    fn repeat_instruction(&mut self) {
+        self.pc = self.pc.wrapping_sub(2);
     }
 
     // The index register is set to the address of the hexadecimal character in vx
-    fn exec_fx29(&mut self, x: u16) {
+    fn exec_fx29(&mut self, x: u8) -> Result<(), Fault> {
+        let vx = self.var_registers[x as usize];
+        self.index_register = font_digit_address(vx);
+        Ok(())
+    }
+
+    // The index register is set to the address of the SUPER-CHIP 8x10 big font character in vx
+    fn exec_fx30(&mut self, x: u8) -> Result<(), Fault> {
         let vx = self.var_registers[x as usize];
-        let char_address = font_digit_address(vx);
-        self.index_register = char_address as u16;
+        self.index_register = big_font_digit_address(vx);
+        Ok(())
     }
 
     // Binary-coded decimal conversion,
     // It takes the number in vx (which is one byte, so it can be any number from 0 to 255)
     // and converts it to three decimal digits, storing these digits in memory at
     // the address in the index register
-    fn exec_fx33(&mut self, x: u16) {
+    fn exec_fx33(&mut self, x: u8) -> Result<(), Fault> {
         let vx = self.var_registers[x as usize];
         let three_digit_vx = format!("{:03}", vx);
         let radix: u32 = 10;
         for (idx, c) in three_digit_vx.chars().enumerate() {
-            let address = self.index_register + idx as u16;
+            let address = self.index_register as usize + idx;
             let digit: u8 = c.to_digit(radix).unwrap() as u8;
-            self.memory[address as usize] = digit;
+            self.write_memory(address, digit)?;
         }
+
+        Ok(())
+    }
+
+    // original COSMAC VIP behaviour leaves I pointing just past the last register,
+    // later interpreters increment it by one less or leave it alone
+    fn increment_index_after_load_store(&mut self, x: u8) {
+        let increment = match self.quirks.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => x as u16,
+            IndexIncrement::ByXPlusOne => x as u16 + 1,
+        };
+        self.index_register = self.index_register.wrapping_add(increment);
     }
 
     // store and load, the value of each variable register from V0 to VX inclusive
     // (if X is 0, then only V0) will be stored in successive memory addresses,
     // starting with the one that’s stored in index_register
-    fn exec_fx55(&mut self, x: u16) {
+    fn exec_fx55(&mut self, x: u8) -> Result<(), Fault> {
         for i in 0..=x {
             let val = self.var_registers[i as usize];
-            let address: usize = (self.index_register + i) as usize;
-            self.memory[address] = val;
+            let address: usize = self.index_register as usize + i as usize;
+            self.write_memory(address, val)?;
         }
 
-        if self.op_store_and_load_original {
-            self.index_register += x + 1;
-        }
+        self.increment_index_after_load_store(x);
+
+        Ok(())
     }
 
     // store and load, opposite of fx55
     // it takes the value stored at the memory addresses and
     // loads them into the variable registers instead.
-    fn exec_fx65(&mut self, x: u16) {
+    fn exec_fx65(&mut self, x: u8) -> Result<(), Fault> {
         for i in 0..=x {
-            let address: usize = (self.index_register + i) as usize;
-            self.var_registers[i as usize] = self.memory[address];
+            let address: usize = self.index_register as usize + i as usize;
+            self.var_registers[i as usize] = self.read_memory(address)?;
         }
 
-        if self.op_store_and_load_original {
-            self.index_register += x + 1;
-        }
+        self.increment_index_after_load_store(x);
+
+        Ok(())
+    }
+
+    // save V0 to VX inclusive in the RPL user flags
+    fn exec_fx75(&mut self, x: u8) -> Result<(), Fault> {
+        let count = x as usize + 1;
+        self.rpl_flags[..count].copy_from_slice(&self.var_registers[..count]);
+        Ok(())
+    }
+
+    // restore V0 to VX inclusive from the RPL user flags
+    fn exec_fx85(&mut self, x: u8) -> Result<(), Fault> {
+        let count = x as usize + 1;
+        self.var_registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+        Ok(())
     }
 }
 
 // The CHIP-8 emulator should have a built-in font, with sprite data representing the hexadecimal numbers from 0 through F.
 // Each font character should be 4 pixels wide by 5 pixels tall.
 // These font sprites are drawn just like regular sprites.
-fn load_fonts(memory: &mut [u8; 4096]) {
+fn load_fonts(memory: &mut [u8]) {
     let font = [
         0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
         0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -708,16 +1425,552 @@ fn load_fonts(memory: &mut [u8; 4096]) {
         0xF0, 0x80, 0xF0, 0x80, 0x80, // F
     ];
 
-    let mut index = FONT_PC;
+    memory[FONT_PC..FONT_PC + font.len()].copy_from_slice(&font);
+
+    // SUPER-CHIP's big font, 8 pixels wide by 10 pixels tall
+    let big_font = [
+        0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+        0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+        0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+        0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+        0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+        0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+        0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+        0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+        0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+        0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+        0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+    ];
+
+    memory[BIG_FONT_PC..BIG_FONT_PC + big_font.len()].copy_from_slice(&big_font);
+}
 
-    for val in font {
-        memory[index] = val;
-        index += 1;
+// the registers from x to y inclusive, counting down if x is bigger than y
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
     }
 }
 
-// returns the starting address of a hex character in the emulator memory
-// each digit is 5 bytes long
-fn font_digit_address(digit: u8) -> u8 {
-    return FONT_PC as u8 + (digit * 5);
+// returns the starting address of a hex character in the emulator memory,
+// only the low nibble of the digit is used and each digit is 5 bytes long
+fn font_digit_address(digit: u8) -> u16 {
+    FONT_PC as u16 + (digit & 0xF) as u16 * 5
+}
+
+// same for the big font, where each digit is 10 bytes long
+fn big_font_digit_address(digit: u8) -> u16 {
+    BIG_FONT_PC as u16 + (digit & 0xF) as u16 * 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::VIP_INTERPRETER_SIZE;
+
+    // an emulator for platform with the opcodes loaded at 0x200
+    fn load(platform: Platform, opcodes: &[u16]) -> Emulator {
+        let rom = opcodes
+            .iter()
+            .flat_map(|opcode| opcode.to_be_bytes())
+            .collect();
+        Emulator::builder()
+            .platform(platform)
+            .seed(0)
+            .rom(rom)
+            .build()
+            .unwrap()
+    }
+
+    // run the opcodes on platform until the pc leaves them
+    fn run(platform: Platform, opcodes: &[u16]) -> Emulator {
+        let mut emulator = load(platform, opcodes);
+        let end = PC_START + opcodes.len() as u16 * 2;
+        while (PC_START..end).contains(&emulator.pc()) {
+            emulator.step().unwrap();
+        }
+        emulator
+    }
+
+    #[test]
+    fn call_and_return() {
+        let mut emulator = load(Platform::Chip48, &[0x2204, 0x1202, 0x00EE]);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x204);
+        assert_eq!(emulator.stack(), &[0x202]);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x202);
+        assert!(emulator.stack().is_empty());
+    }
+
+    #[test]
+    fn stack_errors() {
+        let mut emulator = load(Platform::Chip48, &[0x00EE]);
+        assert_eq!(
+            emulator.step(),
+            Err(EmulatorError::StackUnderflow {
+                pc: 0x200,
+                opcode: 0x00EE
+            })
+        );
+
+        let mut emulator = load(Platform::Chip48, &[0x2200]);
+        for _ in 0..STACK_SIZE {
+            emulator.step().unwrap();
+        }
+        assert_eq!(
+            emulator.step(),
+            Err(EmulatorError::StackOverflow {
+                pc: 0x200,
+                opcode: 0x2200
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let mut emulator = load(Platform::Chip48, &[0x5001]);
+        assert_eq!(
+            emulator.step(),
+            Err(EmulatorError::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0x5001
+            })
+        );
+    }
+
+    #[test]
+    fn skips() {
+        let emulator = run(Platform::Chip48, &[0x6005, 0x3005]);
+        assert_eq!(emulator.pc(), 0x206);
+        let emulator = run(Platform::Chip48, &[0x6005, 0x4005]);
+        assert_eq!(emulator.pc(), 0x204);
+        let emulator = run(Platform::Chip48, &[0x6005, 0x6105, 0x5010]);
+        assert_eq!(emulator.pc(), 0x208);
+        let emulator = run(Platform::Chip48, &[0x6005, 0x6105, 0x9010]);
+        assert_eq!(emulator.pc(), 0x206);
+    }
+
+    #[test]
+    fn skip_over_long_instruction() {
+        let emulator = run(Platform::XoChip, &[0x6001, 0x3001, 0xF000, 0x1234]);
+        assert_eq!(emulator.pc(), 0x208);
+    }
+
+    #[test]
+    fn add_immediate_leaves_vf_alone() {
+        let emulator = run(Platform::Chip48, &[0x60FF, 0x7002]);
+        assert_eq!(emulator.var_registers()[0], 1);
+        assert_eq!(emulator.var_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn arithmetic_flags() {
+        let emulator = run(Platform::Chip48, &[0x60FF, 0x6102, 0x8014]);
+        assert_eq!(emulator.var_registers()[0], 1);
+        assert_eq!(emulator.var_registers()[0xF], 1);
+
+        let emulator = run(Platform::Chip48, &[0x6001, 0x6102, 0x8015]);
+        assert_eq!(emulator.var_registers()[0], 0xFF);
+        assert_eq!(emulator.var_registers()[0xF], 0);
+
+        let emulator = run(Platform::Chip48, &[0x6001, 0x6102, 0x8017]);
+        assert_eq!(emulator.var_registers()[0], 1);
+        assert_eq!(emulator.var_registers()[0xF], 1);
+    }
+
+    #[test]
+    fn flag_wins_over_result_in_vf() {
+        let emulator = run(Platform::Chip48, &[0x6FFF, 0x6101, 0x8F14]);
+        assert_eq!(emulator.var_registers()[0xF], 1);
+    }
+
+    #[test]
+    fn shift_quirk() {
+        let vip = run(Platform::CosmacVip, &[0x6001, 0x6103, 0x8016]);
+        assert_eq!(vip.var_registers()[0], 1);
+        assert_eq!(vip.var_registers()[0xF], 1);
+
+        let chip48 = run(Platform::Chip48, &[0x6001, 0x6103, 0x8016]);
+        assert_eq!(chip48.var_registers()[0], 0);
+        assert_eq!(chip48.var_registers()[0xF], 1);
+
+        let vip = run(Platform::CosmacVip, &[0x6001, 0x6181, 0x801E]);
+        assert_eq!(vip.var_registers()[0], 2);
+        assert_eq!(vip.var_registers()[0xF], 1);
+
+        let chip48 = run(Platform::Chip48, &[0x6001, 0x6181, 0x801E]);
+        assert_eq!(chip48.var_registers()[0], 2);
+        assert_eq!(chip48.var_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        for opcode in [0x8011, 0x8012, 0x8013] {
+            let vip = run(Platform::CosmacVip, &[0x6F05, opcode]);
+            assert_eq!(vip.var_registers()[0xF], 0);
+            let chip48 = run(Platform::Chip48, &[0x6F05, opcode]);
+            assert_eq!(chip48.var_registers()[0xF], 5);
+        }
+    }
+
+    #[test]
+    fn jump_quirk() {
+        let vip = run(Platform::CosmacVip, &[0x6004, 0x6310, 0xB300]);
+        assert_eq!(vip.pc(), 0x304);
+        let chip48 = run(Platform::Chip48, &[0x6004, 0x6310, 0xB300]);
+        assert_eq!(chip48.pc(), 0x310);
+    }
+
+    #[test]
+    fn index_increment_quirk() {
+        let store = [0xA300, 0x6001, 0x6102, 0xF155];
+        for (platform, index) in [
+            (Platform::CosmacVip, 0x302),
+            (Platform::Chip48, 0x301),
+            (Platform::Schip11, 0x300),
+        ] {
+            let emulator = run(platform, &store);
+            assert_eq!(emulator.index_register(), index, "{}", platform);
+            assert_eq!(emulator.memory()[0x300..0x302], [1, 2]);
+        }
+    }
+
+    #[test]
+    fn default_platform_keeps_the_old_quirks() {
+        let shift = run(Platform::default(), &[0x6001, 0x6103, 0x8016]);
+        assert_eq!(shift.var_registers()[0], 0);
+        let jump = run(Platform::default(), &[0x6004, 0x6310, 0xB300]);
+        assert_eq!(jump.pc(), 0x310);
+        let store = run(Platform::default(), &[0xA300, 0x6001, 0x6102, 0xF155]);
+        assert_eq!(store.index_register(), 0x300);
+        let reset = run(Platform::default(), &[0x6F05, 0x8011]);
+        assert_eq!(reset.var_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn store_and_load_registers() {
+        let emulator = run(
+            Platform::Schip11,
+            &[0xA300, 0x6001, 0x6102, 0xF155, 0x6000, 0x6100, 0xF165],
+        );
+        assert_eq!(emulator.var_registers()[..2], [1, 2]);
+    }
+
+    #[test]
+    fn register_ranges() {
+        let emulator = run(Platform::XoChip, &[0xA300, 0x6001, 0x6102, 0x5012]);
+        assert_eq!(emulator.memory()[0x300..0x302], [1, 2]);
+        assert_eq!(emulator.index_register(), 0x300);
+
+        let emulator = run(Platform::XoChip, &[0xA300, 0x6001, 0x6102, 0x5102]);
+        assert_eq!(emulator.memory()[0x300..0x302], [2, 1]);
+
+        let emulator = run(
+            Platform::XoChip,
+            &[0xA300, 0x6001, 0x6102, 0x5012, 0x6000, 0x6100, 0x5013],
+        );
+        assert_eq!(emulator.var_registers()[..2], [1, 2]);
+    }
+
+    #[test]
+    fn binary_coded_decimal() {
+        let emulator = run(Platform::Chip48, &[0x60FE, 0xA300, 0xF033]);
+        assert_eq!(emulator.memory()[0x300..0x303], [2, 5, 4]);
+    }
+
+    #[test]
+    fn add_to_index() {
+        let emulator = run(Platform::Chip48, &[0xAFFF, 0x6002, 0xF01E]);
+        assert_eq!(emulator.index_register(), 0x1001);
+    }
+
+    #[test]
+    fn font_character() {
+        let emulator = run(Platform::Chip48, &[0x600A, 0xF029]);
+        assert_eq!(emulator.index_register(), FONT_PC as u16 + 50);
+        let emulator = run(Platform::Schip11, &[0x600A, 0xF030]);
+        assert_eq!(emulator.index_register(), BIG_FONT_PC as u16 + 100);
+    }
+
+    #[test]
+    fn font_character_uses_the_low_nibble() {
+        let emulator = run(Platform::Chip48, &[0x60FF, 0xF029]);
+        assert_eq!(emulator.index_register(), FONT_PC as u16 + 75);
+        let emulator = run(Platform::Schip11, &[0x60FF, 0xF030]);
+        assert_eq!(emulator.index_register(), BIG_FONT_PC as u16 + 150);
+    }
+
+    #[test]
+    fn timers() {
+        let mut emulator = run(Platform::Chip48, &[0x6005, 0xF015, 0xF018, 0xF207]);
+        assert_eq!(emulator.delay_timer(), 5);
+        assert_eq!(emulator.sound_timer(), 5);
+        assert_eq!(emulator.var_registers()[2], 5);
+        emulator.end_frame();
+        assert_eq!(emulator.delay_timer(), 4);
+        assert_eq!(emulator.sound_timer(), 4);
+    }
+
+    #[test]
+    fn draw_sets_collision_flag() {
+        let draw = [0x6000, 0xF029, 0xD005, 0xD005];
+        let mut emulator = load(Platform::Chip48, &draw);
+        for _ in 0..3 {
+            emulator.step().unwrap();
+        }
+        assert_eq!(emulator.var_registers()[0xF], 0);
+        assert_eq!(emulator.display_buffer().pixel(0, 0), 1);
+        emulator.step().unwrap();
+        assert_eq!(emulator.var_registers()[0xF], 1);
+        assert_eq!(emulator.display_buffer().pixel(0, 0), 0);
+    }
+
+    #[test]
+    fn clip_sprites_quirk() {
+        // the top row of the 0 is 4 pixels wide, drawn 2 pixels from the right edge
+        let draw = [0x603E, 0xF129, 0xD015];
+        let chip48 = run(Platform::Chip48, &draw);
+        assert_eq!(chip48.display_buffer().pixel(63, 0), 1);
+        assert_eq!(chip48.display_buffer().pixel(0, 0), 0);
+        let xo_chip = run(Platform::XoChip, &draw);
+        assert_eq!(xo_chip.display_buffer().pixel(63, 0), 1);
+        assert_eq!(xo_chip.display_buffer().pixel(0, 0), 1);
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        let vip = run(Platform::CosmacVip, &[0xD005]);
+        assert!(vip.frame_is_over());
+        let chip48 = run(Platform::Chip48, &[0xD005]);
+        assert!(!chip48.frame_is_over());
+    }
+
+    #[test]
+    fn clear_screen() {
+        let emulator = run(Platform::Chip48, &[0xD005, 0x00E0]);
+        assert_eq!(emulator.display_buffer(), &DisplayBuffer::new());
+    }
+
+    #[test]
+    fn resolution() {
+        let emulator = run(Platform::Schip11, &[0x00FF]);
+        assert_eq!(emulator.display_buffer().resolution(), Resolution::High);
+        let emulator = run(Platform::Schip11, &[0x00FF, 0x00FE]);
+        assert_eq!(emulator.display_buffer().resolution(), Resolution::Low);
+    }
+
+    #[test]
+    fn collision_row_count_quirk() {
+        // a 16x16 sprite drawn twice in high resolution, every one of its rows collides
+        let draw = [0x00FF, 0xA050, 0xD000, 0xD000];
+        let schip = run(Platform::Schip11, &draw);
+        assert_eq!(schip.var_registers()[0xF], 16);
+        let modern = run(Platform::SchipModern, &draw);
+        assert_eq!(modern.var_registers()[0xF], 1);
+    }
+
+    #[test]
+    fn collision_row_count_adds_up_the_clipped_rows_of_every_plane() {
+        // a 16x16 sprite 4 rows above the bottom in high resolution, 12 of its rows are clipped on each plane
+        let rom = [0x00FF, 0xF301, 0x613C, 0xA050, 0xD010]
+            .iter()
+            .flat_map(|opcode: &u16| opcode.to_be_bytes())
+            .collect();
+        let quirks = Quirks {
+            clip_sprites: true,
+            collision_row_count: true,
+            ..Quirks::for_platform(Platform::XoChip)
+        };
+        let mut emulator = Emulator::builder()
+            .platform(Platform::XoChip)
+            .quirks(quirks)
+            .seed(0)
+            .rom(rom)
+            .build()
+            .unwrap();
+        for _ in 0..5 {
+            emulator.step().unwrap();
+        }
+        assert_eq!(emulator.var_registers()[0xF], 24);
+    }
+
+    #[test]
+    fn keys() {
+        let mut emulator = load(Platform::Chip48, &[0x6005, 0xE09E, 0x0000, 0xE0A1]);
+        emulator.set_key(5, true);
+        emulator.step().unwrap();
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x206);
+        emulator.set_key(5, false);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x20A);
+    }
+
+    #[test]
+    fn wait_for_key() {
+        let mut emulator = load(Platform::Chip48, &[0xF00A]);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x200);
+        emulator.set_key(7, true);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x202);
+        assert_eq!(emulator.var_registers()[0], 7);
+    }
+
+    #[test]
+    fn wait_for_key_at_the_end_of_memory() {
+        let mut emulator = load(Platform::XoChip, &[]);
+        emulator.memory[0xFFFE..].copy_from_slice(&[0xF0, 0x0A]);
+        emulator.set_pc(0xFFFE);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0xFFFE);
+    }
+
+    #[test]
+    fn key_wait_release_quirk() {
+        let mut emulator = load(Platform::CosmacVip, &[0xF00A]);
+        emulator.set_key(7, true);
+        emulator.step().unwrap();
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x200);
+        emulator.set_key(7, false);
+        emulator.step().unwrap();
+        assert_eq!(emulator.pc(), 0x202);
+        assert_eq!(emulator.var_registers()[0], 7);
+    }
+
+    #[test]
+    fn rpl_flags() {
+        let emulator = run(
+            Platform::Schip11,
+            &[0x6001, 0x6102, 0xF175, 0x6000, 0x6100, 0xF185],
+        );
+        assert_eq!(emulator.var_registers()[..2], [1, 2]);
+    }
+
+    #[test]
+    fn exit() {
+        let emulator = run(Platform::Schip11, &[0x00FD]);
+        assert!(emulator.has_exited());
+        assert!(emulator.frame_is_over());
+    }
+
+    #[test]
+    fn random_follows_the_seed() {
+        let first = run(Platform::Chip48, &[0xC0FF, 0xC1FF]);
+        let second = run(Platform::Chip48, &[0xC0FF, 0xC1FF]);
+        assert_eq!(first.var_registers(), second.var_registers());
+
+        let masked = run(Platform::Chip48, &[0xC00F]);
+        assert!(masked.var_registers()[0] <= 0xF);
+    }
+
+    #[test]
+    fn cosmac_vip_random() {
+        let build = |interpreter: Vec<u8>| {
+            Emulator::builder()
+                .vip_random(interpreter)
+                .seed(0)
+                .rom(vec![0xC0, 0xFF, 0xC1, 0xFF])
+                .build()
+        };
+        assert_eq!(
+            build(vec![0; 0x100]).err(),
+            Some(EmulatorError::InvalidVipInterpreter { size: 0x100 })
+        );
+
+        // every byte of the interpreter's page is added up
+        let mut emulator = build(vec![5; VIP_INTERPRETER_SIZE]).unwrap();
+        emulator.step().unwrap();
+        emulator.step().unwrap();
+        assert_eq!(emulator.var_registers()[..2], [5, 10]);
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        let mut emulator = load(Platform::Chip48, &[0xAFFF, 0xF155]);
+        emulator.step().unwrap();
+        assert_eq!(
+            emulator.step(),
+            Err(EmulatorError::MemoryOutOfBounds {
+                pc: 0x202,
+                opcode: 0xF155,
+                address: 0x1000
+            })
+        );
+    }
+
+    #[test]
+    fn xo_chip_memory() {
+        let emulator = run(Platform::XoChip, &[0xF000, 0xFFF0, 0x6007, 0xF055]);
+        assert_eq!(emulator.memory().len(), XO_CHIP_RAM_SIZE);
+        assert_eq!(emulator.memory()[0xFFF0], 7);
+    }
+
+    #[test]
+    fn select_planes() {
+        let emulator = run(Platform::XoChip, &[0xA050, 0xF201, 0xD005]);
+        assert_eq!(emulator.display_buffer().pixel(2, 0), 2);
+        // the second plane is drawn with the 1 that follows the 0 in the font
+        let emulator = run(Platform::XoChip, &[0xA050, 0xF301, 0xD005]);
+        assert_eq!(emulator.display_buffer().pixel(0, 0), 1);
+        assert_eq!(emulator.display_buffer().pixel(2, 0), 3);
+    }
+
+    #[test]
+    fn audio_pattern() {
+        let emulator = run(Platform::XoChip, &[0xA050, 0xF002, 0x6010, 0xF03A]);
+        assert_eq!(emulator.audio_pattern().bits, emulator.memory()[0x50..0x60]);
+        assert_eq!(emulator.audio_pattern().pitch, 0x10);
+    }
+
+    #[test]
+    fn memory_accesses() {
+        let mut emulator = load(Platform::Chip48, &[0xA300, 0xF155]);
+        emulator.track_memory_accesses(true);
+        emulator.step().unwrap();
+        emulator.step().unwrap();
+        let writes: Vec<usize> = emulator
+            .memory_accesses()
+            .iter()
+            .filter(|access| access.kind == AccessKind::Write)
+            .map(|access| access.address)
+            .collect();
+        assert_eq!(writes, [0x300, 0x301]);
+        assert_eq!(emulator.memory_accesses()[0].kind, AccessKind::Fetch);
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let mut emulator = load(Platform::Chip48, &[0x6001, 0xA300, 0xF055, 0xD005]);
+        emulator.step().unwrap();
+        let snapshot = emulator.snapshot();
+        for _ in 0..3 {
+            emulator.step().unwrap();
+        }
+        assert_ne!(emulator.snapshot(), snapshot);
+        emulator.restore(&snapshot);
+        assert_eq!(emulator.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let result = Emulator::builder().rom(vec![0; RAM_SIZE]).build();
+        assert_eq!(
+            result.err(),
+            Some(EmulatorError::RomTooLarge {
+                size: RAM_SIZE,
+                max_size: RAM_SIZE - 0x200
+            })
+        );
+    }
 }
@@ -0,0 +1,894 @@
+use crate::repl::parse_number;
+use crate::runner::halt_report;
+use chip8_rust::debugger::{Debugger, Location, Stop};
+use chip8_rust::expression::{Expression, Register};
+use chip8_rust::instruction::OpcodePattern;
+use chip8_rust::{Emulator, EmulatorBuilder, EmulatorError};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+// The --dap server, the debugger behind the Debug Adapter Protocol so editors like VS Code can
+// drive it. Messages are JSON with a Content-Length header, on stdin and stdout or on one TCP
+// connection. The rom comes with the launch request, along with an optional symbol file for
+// breaking on Octo labels by name.
+//
+// CHIP-8 has one thread, the stack frames come from the call stack and the registers, timers
+// and memory are variables. There is no source line information, stepping and breakpoints
+// work on instructions and the editor's disassembly view.
+pub struct Dap {
+    // requests from the client, read on their own thread so running can be paused
+    requests: mpsc::Receiver<Value>,
+    output: Box<dyn Write>,
+    seq: u64,
+
+    // everything but the rom, which comes with the launch request
+    builder: Option<EmulatorBuilder>,
+    history: usize,
+
+    // None until launched
+    debugger: Option<Debugger>,
+    symbols: Symbols,
+    stop_on_entry: bool,
+
+    // the ids each kind of set*Breakpoints request created, the next one replaces them
+    function_breakpoints: Vec<u32>,
+    instruction_breakpoints: Vec<u32>,
+
+    // running in real time until a breakpoint, a pause or reaching the target of a step
+    running: bool,
+
+    // events wait until the response to the request that caused them is out
+    events: Vec<Value>,
+}
+
+// there only is the one
+const THREAD_ID: u64 = 1;
+
+// variablesReference of each scope
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const MEMORY: u64 = 3;
+
+// bytes to a variable in the memory scope
+const MEMORY_ROW: usize = 16;
+
+// Octo labels and their addresses
+#[derive(Default)]
+struct Symbols {
+    addresses: HashMap<String, u16>,
+    names: BTreeMap<u16, String>,
+}
+
+// Connect to a client: on stdin and stdout, or the first one to connect to the port on localhost
+pub fn connect(port: Option<u16>) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write>)> {
+    let Some(port) = port else {
+        return Ok((Box::new(io::stdin()), Box::new(io::stdout())));
+    };
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("waiting for a debugger on 127.0.0.1:{}", port);
+    let (stream, _) = listener.accept()?;
+    Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+}
+
+impl Dap {
+    pub fn new(
+        input: Box<dyn Read + Send>,
+        output: Box<dyn Write>,
+        builder: EmulatorBuilder,
+        history: usize,
+    ) -> Self {
+        let (request_tx, request_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            loop {
+                match read_message(&mut input) {
+                    Ok(Some(message)) => {
+                        if request_tx.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        eprintln!("failed to read a debug adapter message: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            requests: request_rx,
+            output,
+            seq: 0,
+            builder: Some(builder),
+            history,
+            debugger: None,
+            symbols: Symbols::default(),
+            stop_on_entry: false,
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            running: false,
+            events: Vec::new(),
+        }
+    }
+
+    // serves requests until the client disconnects
+    pub fn run(&mut self) {
+        loop {
+            let request = if self.running {
+                match self.requests.try_recv() {
+                    Ok(request) => request,
+                    Err(mpsc::TryRecvError::Empty) => {
+                        self.run_due_frames();
+                        self.send_events();
+                        continue;
+                    }
+                    Err(mpsc::TryRecvError::Disconnected) => return,
+                }
+            } else {
+                match self.requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => return,
+                }
+            };
+
+            if request["type"] != "request" {
+                continue;
+            }
+            let command = request["command"].as_str().unwrap_or_default().to_string();
+            let result = self.handle(&command, &request["arguments"]);
+            self.respond(&request, result);
+            self.send_events();
+            if command == "disconnect" || command == "terminate" {
+                return;
+            }
+        }
+    }
+
+    fn handle(&mut self, command: &str, args: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsDisassembleRequest": true,
+                "supportsSteppingGranularity": true,
+                "supportsStepBack": true,
+                "supportsSetVariable": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stopped(Ok(Stop::Step), "entry");
+                } else if let Some(stop) = self.debugger()?.breakpoint_at_pc() {
+                    // breakpoints are checked after each instruction, one on the entry
+                    // point would never hit otherwise
+                    self.stopped(Ok(stop), "breakpoint");
+                } else {
+                    self.start_running()?;
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                // one entry per requested breakpoint, none of them can be placed
+                let count = args["breakpoints"].as_array().map_or(0, Vec::len);
+                let breakpoint = json!({
+                    "verified": false,
+                    "message": "no line information, break on an Octo label or an address instead",
+                });
+                Ok(json!({ "breakpoints": vec![breakpoint; count] }))
+            }
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => self.stack_trace(args),
+            "scopes" => self.scopes(),
+            "variables" => self.variables(args),
+            "setVariable" => self.set_variable(args),
+            "evaluate" => self.evaluate(args),
+            "disassemble" => self.disassemble(args),
+            "continue" => {
+                self.debugger()?.clear_target();
+                self.start_running()?;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => {
+                if self.debugger()?.step_over() {
+                    self.start_running()?;
+                } else {
+                    let result = self.debugger()?.step();
+                    self.stopped(result, "step");
+                }
+                Ok(Value::Null)
+            }
+            "stepIn" => {
+                let result = self.debugger()?.step();
+                self.stopped(result, "step");
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                if !self.debugger()?.step_out() {
+                    return Err("not in a subroutine".to_string());
+                }
+                self.start_running()?;
+                Ok(Value::Null)
+            }
+            "stepBack" => {
+                let stop = self.debugger()?.reverse_step();
+                self.stopped(Ok(stop), "step");
+                Ok(Value::Null)
+            }
+            "reverseContinue" => {
+                let result = self.debugger()?.reverse_continue();
+                self.stopped(result, "step");
+                Ok(Value::Null)
+            }
+            "pause" => {
+                if self.running {
+                    self.debugger()?.clear_target();
+                    self.stopped(Ok(Stop::Step), "pause");
+                }
+                Ok(Value::Null)
+            }
+            "disconnect" | "terminate" => {
+                self.running = false;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("unsupported request {}", command)),
+        }
+    }
+
+    // arguments: program, the rom's path, and optionally symbols, a symbol file, and
+    // stopOnEntry
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or("launch needs the rom's path as program")?;
+        let rom =
+            fs::read(program).map_err(|e| format!("failed to read rom {}: {}", program, e))?;
+        if let Some(path) = args["symbols"].as_str() {
+            self.symbols = Symbols::load(path)?;
+        }
+
+        let builder = self
+            .builder
+            .take()
+            .ok_or("the program was already launched")?;
+        let emulator = builder.rom(rom).build().map_err(|e| e.to_string())?;
+        let mut debugger = Debugger::new(emulator);
+        debugger.set_history_capacity(self.history);
+        debugger.emulator_mut().present_paused();
+        self.debugger = Some(debugger);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+
+        self.event("initialized", Value::Null);
+        Ok(Value::Null)
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger, String> {
+        self.debugger
+            .as_mut()
+            .ok_or_else(|| "no program was launched".to_string())
+    }
+
+    fn emulator(&self) -> Result<&Emulator, String> {
+        self.debugger
+            .as_ref()
+            .map(Debugger::emulator)
+            .ok_or_else(|| "no program was launched".to_string())
+    }
+
+    fn start_running(&mut self) -> Result<(), String> {
+        self.debugger()?.emulator_mut().resync();
+        self.running = true;
+        Ok(())
+    }
+
+    fn run_due_frames(&mut self) {
+        let Some(debugger) = &mut self.debugger else {
+            return;
+        };
+        match debugger.run_due_frames() {
+            Ok(Stop::FrameEnd) => {}
+            result => self.stopped(result, "step"),
+        }
+    }
+
+    // tell the client why execution stopped, reason is the stopped event's for steps
+    fn stopped(&mut self, result: Result<Stop, EmulatorError>, reason: &str) {
+        self.running = false;
+        let Some(debugger) = &mut self.debugger else {
+            return;
+        };
+        debugger.emulator_mut().present_paused();
+
+        let mut body = json!({ "threadId": THREAD_ID, "allThreadsStopped": true });
+        match result {
+            Ok(Stop::Step | Stop::Reached(_) | Stop::FrameEnd) => body["reason"] = json!(reason),
+            Ok(Stop::Breakpoint(id)) => {
+                body["reason"] = json!("breakpoint");
+                body["hitBreakpointIds"] = json!([id]);
+            }
+            Ok(Stop::Watchpoint { id, pc, access }) => {
+                body["reason"] = json!("data breakpoint");
+                body["hitBreakpointIds"] = json!([id]);
+                body["description"] = json!(format!(
+                    "{} of {:#05x} by the instruction at {:#05x}",
+                    access.kind, access.address, pc
+                ));
+            }
+            Ok(Stop::StartOfHistory) => {
+                body["reason"] = json!(reason);
+                body["description"] = json!("no more history to go back through");
+            }
+            Ok(Stop::Exited) => {
+                self.event("exited", json!({ "exitCode": 0 }));
+                self.event("terminated", Value::Null);
+                return;
+            }
+            Err(e) => {
+                body["reason"] = json!("exception");
+                body["description"] = json!("halted");
+                body["text"] = json!(halt_report(debugger.emulator(), &e));
+            }
+        }
+        self.event("stopped", body);
+    }
+
+    // names are Octo labels from the symbol file, addresses or opcode patterns like Dxyn
+    fn set_function_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut breakpoints = Vec::new();
+        let mut ids = Vec::new();
+        for breakpoint in &requested {
+            let name = breakpoint["name"].as_str().unwrap_or_default();
+            let location = match self.symbols.addresses.get(name) {
+                Some(address) => Ok(Location::Address(*address)),
+                None => parse_number(name)
+                    .map(Location::Address)
+                    .or_else(|_| name.parse::<OpcodePattern>().map(Location::Opcode))
+                    .map_err(|_| format!("no symbol {}", name)),
+            };
+            breakpoints.push(self.add_breakpoint(location, &breakpoint["condition"], &mut ids)?);
+        }
+        self.replace_breakpoints(ids, |dap| &mut dap.function_breakpoints)?;
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let mut breakpoints = Vec::new();
+        let mut ids = Vec::new();
+        for breakpoint in &requested {
+            let reference = breakpoint["instructionReference"]
+                .as_str()
+                .unwrap_or_default();
+            let offset = breakpoint["offset"].as_i64().unwrap_or(0);
+            let location = parse_number(reference).and_then(|address| {
+                u16::try_from(address as i64 + offset)
+                    .map(Location::Address)
+                    .map_err(|_| format!("{} + {} is out of range", reference, offset))
+            });
+            breakpoints.push(self.add_breakpoint(location, &breakpoint["condition"], &mut ids)?);
+        }
+        self.replace_breakpoints(ids, |dap| &mut dap.instruction_breakpoints)?;
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    // the Breakpoint the client gets back, unverified with the reason when it can't be set
+    fn add_breakpoint(
+        &mut self,
+        location: Result<Location, String>,
+        condition: &Value,
+        ids: &mut Vec<u32>,
+    ) -> Result<Value, String> {
+        let condition = condition
+            .as_str()
+            .filter(|condition| !condition.trim().is_empty())
+            .map(str::parse::<Expression>)
+            .transpose();
+        let (location, condition) = match (location, condition) {
+            (Ok(location), Ok(condition)) => (location, condition),
+            (Err(e), _) | (_, Err(e)) => {
+                return Ok(json!({ "verified": false, "message": e }));
+            }
+        };
+
+        let mut breakpoint = json!({ "verified": true });
+        if let Location::Address(address) = location {
+            breakpoint["instructionReference"] = json!(format!("{:#05x}", address));
+        }
+        let id = self.debugger()?.add_breakpoint(location, condition);
+        breakpoint["id"] = json!(id);
+        ids.push(id);
+        Ok(breakpoint)
+    }
+
+    // drop the breakpoints the last request of a kind set, and keep the new ones
+    fn replace_breakpoints(
+        &mut self,
+        ids: Vec<u32>,
+        kind: fn(&mut Self) -> &mut Vec<u32>,
+    ) -> Result<(), String> {
+        let old = std::mem::replace(kind(self), ids);
+        for id in old {
+            self.debugger()?.remove_breakpoint(id);
+        }
+        Ok(())
+    }
+
+    // pc first, then the call of each subroutine on the stack, innermost first
+    fn stack_trace(&self, args: &Value) -> Result<Value, String> {
+        let emulator = self.emulator()?;
+        let calls = emulator
+            .stack()
+            .iter()
+            .rev()
+            .map(|address| address.wrapping_sub(2));
+        let addresses: Vec<u16> = std::iter::once(emulator.pc()).chain(calls).collect();
+
+        let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match args["levels"].as_u64() {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => addresses.len(),
+        };
+        let frames: Vec<Value> = addresses
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, address)| {
+                json!({
+                    "id": id,
+                    "name": self.symbols.describe(*address),
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#05x}", address),
+                })
+            })
+            .collect();
+        Ok(json!({ "stackFrames": frames, "totalFrames": addresses.len() }))
+    }
+
+    // the same for every frame, CHIP-8 doesn't save registers on calls
+    fn scopes(&self) -> Result<Value, String> {
+        let rows = self.emulator()?.memory().len().div_ceil(MEMORY_ROW);
+        Ok(json!({ "scopes": [
+            { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+            { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+            {
+                "name": "Memory",
+                "variablesReference": MEMORY,
+                "indexedVariables": rows,
+                "expensive": true,
+            },
+        ]}))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let emulator = self.emulator()?;
+        let register = |register: Register, width: usize| {
+            json!({
+                "name": register.to_string(),
+                "value": format!("{:#0width$x}", register.read(emulator), width = width + 2),
+                "variablesReference": 0,
+            })
+        };
+
+        let variables: Vec<Value> = match args["variablesReference"].as_u64() {
+            Some(REGISTERS) => {
+                let mut variables: Vec<Value> =
+                    (0..16).map(|x| register(Register::V(x), 2)).collect();
+                variables.push(register(Register::I, 3));
+                variables.push(register(Register::Pc, 3));
+                variables
+            }
+            Some(TIMERS) => vec![
+                register(Register::DelayTimer, 2),
+                register(Register::SoundTimer, 2),
+                json!({
+                    "name": "frame",
+                    "value": emulator.frame().to_string(),
+                    "variablesReference": 0,
+                }),
+            ],
+            Some(MEMORY) => {
+                let start = args["start"].as_u64().unwrap_or(0) as usize;
+                let count = args["count"]
+                    .as_u64()
+                    .map_or(usize::MAX, |count| count as usize);
+                emulator
+                    .memory()
+                    .chunks(MEMORY_ROW)
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(row, bytes)| {
+                        let bytes: Vec<String> =
+                            bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                        json!({
+                            "name": format!("{:#05x}", row * MEMORY_ROW),
+                            "value": bytes.join(" "),
+                            "variablesReference": 0,
+                        })
+                    })
+                    .collect()
+            }
+            _ => return Err("unknown variablesReference".to_string()),
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    // registers and timers, to anything an expression evaluates to
+    fn set_variable(&mut self, args: &Value) -> Result<Value, String> {
+        let register: Register = args["name"].as_str().unwrap_or_default().parse()?;
+        let value: Expression = args["value"].as_str().unwrap_or_default().parse()?;
+
+        let emulator = self.debugger()?.emulator_mut();
+        let val = value.eval(emulator);
+        let val = u16::try_from(val).map_err(|_| format!("{} doesn't fit in {}", val, register))?;
+        register.write(emulator, val)?;
+        Ok(json!({ "value": format!("{:#x}", register.read(emulator)) }))
+    }
+
+    // expressions like the conditions of breakpoints, from watches, hovers and the console
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let expression: Expression = args["expression"].as_str().unwrap_or_default().parse()?;
+        let val = expression.eval(self.emulator()?);
+        let result = if val < 0 {
+            val.to_string()
+        } else {
+            format!("{:#x} ({})", val, val)
+        };
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+
+    // instructions around an address, instructionOffset counts 2 bytes to an instruction
+    // before the address. Anything outside of memory comes back marked invalid
+    fn disassemble(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        let mut address = parse_number(reference)? as i64
+            + args["offset"].as_i64().unwrap_or(0)
+            + args["instructionOffset"].as_i64().unwrap_or(0) * 2;
+        let count = args["instructionCount"].as_u64().unwrap_or(0);
+
+        let emulator = self.emulator()?;
+        let memory = emulator.memory();
+        let mut instructions = Vec::new();
+        for _ in 0..count {
+            let instruction = u16::try_from(address)
+                .ok()
+                .and_then(|address| emulator.instruction_at(address));
+            let Some(instruction) = instruction else {
+                instructions.push(json!({
+                    "address": format_address(address),
+                    "instruction": "",
+                    "presentationHint": "invalid",
+                }));
+                address += 2;
+                continue;
+            };
+
+            let start = address as usize;
+            let end = (start + instruction.size() as usize).min(memory.len());
+            let bytes: String = memory[start..end]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            let mut entry = json!({
+                "address": format_address(address),
+                "instructionBytes": bytes,
+                "instruction": instruction.to_string(),
+            });
+            if let Some(name) = self.symbols.names.get(&(address as u16)) {
+                entry["symbol"] = json!(name);
+            }
+            instructions.push(entry);
+            address += instruction.size() as i64;
+        }
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.events.push(message);
+    }
+
+    fn send_events(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            self.send(event);
+        }
+    }
+
+    // a client that went away shows up as the requests ending
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        let _ = write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        let _ = self.output.flush();
+    }
+}
+
+impl Symbols {
+    // A symbol file has one label to a line, its name and then its address in decimal or in
+    // hex with a 0x prefix:
+    //   main 0x200
+    //   draw_player 586
+    // A line can also be written as an Octo constant, ":const main 0x200". Blank lines and
+    // everything after a # are ignored, any other line is an error
+    fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read symbols {}: {}", path, e))?;
+        Self::parse(&text).map_err(|(line, e)| format!("{}:{}: {}", path, line, e))
+    }
+
+    // fails with the line number and what is wrong with it
+    fn parse(text: &str) -> Result<Self, (usize, String)> {
+        let mut symbols = Symbols::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let (name, address) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [] => continue,
+                [":const", name, address] | [name, address] => (name, address),
+                _ => {
+                    return Err((
+                        number + 1,
+                        "expected a name and an address, like main 0x200".to_string(),
+                    ));
+                }
+            };
+            let address = parse_number(address).map_err(|e| (number + 1, e))?;
+            symbols.addresses.insert(name.to_string(), address);
+            symbols.names.entry(address).or_insert(name.to_string());
+        }
+        Ok(symbols)
+    }
+
+    // the closest symbol at or before the address, e.g "draw_player+0x4"
+    fn describe(&self, address: u16) -> String {
+        match self.names.range(..=address).next_back() {
+            Some((start, name)) if *start == address => name.clone(),
+            Some((start, name)) => format!("{}+{:#x}", name, address - start),
+            None => format!("{:#05x}", address),
+        }
+    }
+}
+
+fn format_address(address: i64) -> String {
+    if address < 0 {
+        format!("-{:#05x}", -address)
+    } else {
+        format!("{:#05x}", address)
+    }
+}
+
+// one message, None once the input ends
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(val) = line.strip_prefix("Content-Length:") {
+            len = val.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(len) = len else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a valid Content-Length header",
+        ));
+    };
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    // what the server wrote, shared with the test
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(message: &Value) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("chip8-rust-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    // runs a session with the requests, returns every message the server sent
+    fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+        let input: String = requests
+            .iter()
+            .enumerate()
+            .map(|(seq, (command, arguments))| {
+                frame(&json!({
+                    "seq": seq + 1,
+                    "type": "request",
+                    "command": command,
+                    "arguments": arguments,
+                }))
+            })
+            .collect();
+        let output = Output::default();
+        let builder = Emulator::builder().seed(0);
+        let mut dap = Dap::new(
+            Box::new(Cursor::new(input)),
+            Box::new(output.clone()),
+            builder,
+            0,
+        );
+        dap.run();
+
+        let bytes = output.0.borrow().clone();
+        let mut reader = Cursor::new(bytes);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn response<'a>(messages: &'a [Value], command: &str) -> &'a Value {
+        messages
+            .iter()
+            .find(|message| message["type"] == "response" && message["command"] == command)
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_messages() {
+        let message = json!({ "seq": 1, "type": "request", "command": "threads" });
+        let mut input = Cursor::new(format!("{}{}", frame(&message), frame(&message)));
+        assert_eq!(read_message(&mut input).unwrap(), Some(message.clone()));
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_messages_without_a_length() {
+        let mut input = Cursor::new("Content-Type: application/json\r\n\r\n{}");
+        let error = read_message(&mut input).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut input = Cursor::new("Content-Length: many\r\n\r\n{}");
+        assert!(read_message(&mut input).is_err());
+    }
+
+    #[test]
+    fn parses_symbols() {
+        let symbols = Symbols::parse(
+            "# labels\n\
+             main 0x200\n\
+             \n\
+             :const draw_player 586  # a subroutine\n",
+        )
+        .unwrap();
+        assert_eq!(symbols.addresses["main"], 0x200);
+        assert_eq!(symbols.addresses["draw_player"], 0x24A);
+        assert_eq!(symbols.describe(0x200), "main");
+        assert_eq!(symbols.describe(0x24E), "draw_player+0x4");
+        assert_eq!(symbols.describe(0x100), "0x100");
+    }
+
+    #[test]
+    fn symbol_errors_have_the_line() {
+        assert!(matches!(Symbols::parse("main 0x200\nmain"), Err((2, _))));
+        assert!(matches!(Symbols::parse("main zero"), Err((1, _))));
+    }
+
+    #[test]
+    fn debugs_a_program() {
+        // V0 := 1, then loop forever
+        let rom = temp_file("dap.ch8", &[0x60, 0x01, 0x12, 0x02]);
+        let symbols = temp_file("dap.sym", b"main 0x200\nloop 0x202\n");
+        let messages = session(&[
+            ("initialize", json!({ "adapterID": "chip8" })),
+            (
+                "launch",
+                json!({ "program": rom, "symbols": symbols, "stopOnEntry": true }),
+            ),
+            (
+                "setFunctionBreakpoints",
+                json!({ "breakpoints": [{ "name": "loop" }, { "name": "nowhere" }] }),
+            ),
+            ("configurationDone", Value::Null),
+            ("stepIn", json!({ "threadId": THREAD_ID })),
+            ("stackTrace", json!({ "threadId": THREAD_ID })),
+            ("readMemory", Value::Null),
+            ("disconnect", Value::Null),
+        ]);
+        fs::remove_file(rom).unwrap();
+        fs::remove_file(symbols).unwrap();
+
+        assert_eq!(
+            response(&messages, "initialize")["body"]["supportsStepBack"],
+            true
+        );
+        assert_eq!(response(&messages, "launch")["success"], true);
+
+        let breakpoints = &response(&messages, "setFunctionBreakpoints")["body"]["breakpoints"];
+        assert_eq!(breakpoints[0]["verified"], true);
+        assert_eq!(breakpoints[0]["instructionReference"], "0x202");
+        assert_eq!(breakpoints[1]["verified"], false);
+        assert_eq!(breakpoints[1]["message"], "no symbol nowhere");
+
+        let stops: Vec<&Value> = messages
+            .iter()
+            .filter(|message| message["event"] == "stopped")
+            .map(|message| &message["body"]["reason"])
+            .collect();
+        // the step lands on the breakpoint on loop
+        assert_eq!(stops, ["entry", "breakpoint"]);
+
+        let frames = &response(&messages, "stackTrace")["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "loop");
+
+        let unsupported = response(&messages, "readMemory");
+        assert_eq!(unsupported["success"], false);
+        assert_eq!(unsupported["message"], "unsupported request readMemory");
+
+        // every response answers its request, the events come after it
+        assert_eq!(messages[0]["request_seq"], 1);
+        assert_eq!(messages[2]["event"], "initialized");
+    }
+}
@@ -0,0 +1,763 @@
+use crate::chip8::{AccessKind, Emulator, MemoryAccess};
+use crate::display::DisplayBuffer;
+use crate::error::{EmulatorError, StateError};
+use crate::expression::Expression;
+use crate::history::History;
+use crate::instruction::{Instruction, OpcodePattern};
+use crate::rewind::{decode_delta, encode_delta};
+use crate::state::{Reader, Writer};
+use std::fmt;
+
+// The execution side of a debugger: runs the emulator an instruction at a time and stops at
+// breakpoints, leaving commands and output to whoever drives it.
+//
+// Frames still happen the way run_frame does them, input is read when a frame begins and the
+// timers tick and the screen is presented when it ends, they are just spread over as many
+// calls as it takes. Stepping over the end of a frame runs that end and the next frame's start.
+//
+// The state before every instruction goes into a history, so execution can also be walked
+// backwards. Going forwards again runs the instructions again, with the input of the moment.
+// Copying all of memory and the screen for every instruction would be slow, so an entry holds
+// the registers and only the bytes of memory the instruction wrote, found through the memory
+// access log, and the difference on the screen if it drew.
+
+// instructions of history kept by default, around 30 MB of it
+pub const DEFAULT_HISTORY: usize = 2_000_000;
+
+// Why execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    // the requested instructions ran
+    Step,
+    // the frame that was running ended
+    FrameEnd,
+    // a breakpoint with this id hit, the instruction at pc hasn't run yet
+    Breakpoint(u32),
+    // the instruction at pc touched memory a watchpoint covers, it has run
+    Watchpoint {
+        id: u32,
+        pc: u16,
+        access: MemoryAccess,
+    },
+    // the program ran 00FD, nothing runs after that
+    Exited,
+    // pc got to where step_over, step_out or run_to were headed
+    Reached(u16),
+    // going backwards ran out of history
+    StartOfHistory,
+}
+
+// Where a breakpoint stops execution, optionally only while a condition holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    // pc at the address
+    Address(u16),
+    // the instruction at pc matches the pattern
+    Opcode(OpcodePattern),
+    // an instruction touched memory in the watched range
+    Watch(Watchpoint),
+    // any instruction, for breakpoints that only have a condition
+    Anywhere,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    // numbered from 1 in the order they were added, never reused
+    pub id: u32,
+    pub location: Location,
+    pub condition: Option<Expression>,
+}
+
+// e.g "0x2a4 if V3 == 0x10" or "write 0x300-0x302"
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.location {
+            Location::Address(address) => write!(f, "{:#05x}", address)?,
+            Location::Opcode(pattern) => write!(f, "opcode {}", pattern)?,
+            Location::Watch(watchpoint) => write!(f, "{}", watchpoint)?,
+            Location::Anywhere => write!(f, "anywhere")?,
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+// A range of memory and the kinds of access to it that stop execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub len: usize,
+    pub read: bool,
+    pub write: bool,
+    pub fetch: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, access: &MemoryAccess) -> bool {
+        let kind = match access.kind {
+            AccessKind::Fetch => self.fetch,
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+        };
+        kind && (self.start..self.start + self.len).contains(&access.address)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds: Vec<&str> = [
+            (self.read, "read"),
+            (self.write, "write"),
+            (self.fetch, "fetch"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .collect();
+        write!(f, "{} {:#05x}", kinds.join("/"), self.start)?;
+        if self.len > 1 {
+            write!(f, "-{:#05x}", self.start + self.len - 1)?;
+        }
+        Ok(())
+    }
+}
+
+// Where a run started by step_over, step_out or run_to ends, unless a breakpoint comes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    // pc at the address, with no more calls on the stack than depth
+    Address { address: u16, depth: usize },
+    // a return took the stack below depth
+    Return { depth: usize },
+}
+
+pub struct Debugger {
+    emulator: Emulator,
+
+    // checked in order, the first one that hits stops execution
+    breakpoints: Vec<Breakpoint>,
+    next_breakpoint_id: u32,
+
+    // instructions the running frame has left, None in between frames
+    cycles_left: Option<u32>,
+
+    target: Option<Target>,
+
+    // the registers and cycles_left before each instruction, followed by the memory and
+    // screen it changed as they were before it ran
+    history: History,
+
+    // memory and the screen as the history last saw them, to tell what the next instruction
+    // changed. None until then, e.g after emulator_mut
+    memory_before: Option<Vec<u8>>,
+    display_before: Option<DisplayBuffer>,
+}
+
+impl Debugger {
+    pub fn new(emulator: Emulator) -> Self {
+        let mut debugger = Self {
+            emulator,
+            breakpoints: Vec::new(),
+            next_breakpoint_id: 1,
+            cycles_left: None,
+            target: None,
+            history: History::new(DEFAULT_HISTORY),
+            memory_before: None,
+            display_before: None,
+        };
+        debugger.update_memory_tracking();
+        debugger
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        self.memory_before = None;
+        self.display_before = None;
+        &mut self.emulator
+    }
+
+    // instructions of history to keep for going backwards, 0 turns it off. Forgets the history
+    pub fn set_history_capacity(&mut self, instructions: usize) {
+        self.history = History::new(instructions);
+        self.update_memory_tracking();
+    }
+
+    // instructions that can be stepped back through
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    // returns the id of the new breakpoint
+    pub fn add_breakpoint(&mut self, location: Location, condition: Option<Expression>) -> u32 {
+        let id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        self.breakpoints.push(Breakpoint {
+            id,
+            location,
+            condition,
+        });
+        self.update_memory_tracking();
+        id
+    }
+
+    // returns false if there is no breakpoint with the id
+    pub fn remove_breakpoint(&mut self, id: u32) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.update_memory_tracking();
+        self.breakpoints.len() != len
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.update_memory_tracking();
+    }
+
+    pub fn breakpoint(&self, id: u32) -> Option<&Breakpoint> {
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.id == id)
+    }
+
+    // the first breakpoint on an address, conditional or not
+    pub fn breakpoint_at(&self, address: u16) -> Option<u32> {
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| breakpoint.location == Location::Address(address))
+            .map(|breakpoint| breakpoint.id)
+    }
+
+    // the emulator only logs memory accesses while there are watchpoints to check them against
+    // or a history that needs to know what was written
+    fn update_memory_tracking(&mut self) {
+        let tracking = self.history.capacity() > 0
+            || self
+                .breakpoints
+                .iter()
+                .any(|breakpoint| matches!(breakpoint.location, Location::Watch(_)));
+        if tracking != self.emulator.is_tracking_memory_accesses() {
+            self.emulator.track_memory_accesses(tracking);
+        }
+    }
+
+    // Step over a 2NNN call: the next run_due_frames calls run until it returns, so the
+    // subroutine runs in real time. Returns false without doing anything if pc isn't at a call
+    pub fn step_over(&mut self) -> bool {
+        let pc = self.emulator.pc();
+        let Some(instruction @ Instruction::Call { .. }) = self.emulator.instruction_at(pc) else {
+            return false;
+        };
+        self.target = Some(Target::Address {
+            address: pc.wrapping_add(instruction.size()),
+            depth: self.emulator.stack().len(),
+        });
+        true
+    }
+
+    // Step out of the current subroutine: the next run_due_frames calls run until its 00EE.
+    // Returns false without doing anything outside of a subroutine
+    pub fn step_out(&mut self) -> bool {
+        let depth = self.emulator.stack().len();
+        if depth == 0 {
+            return false;
+        }
+        self.target = Some(Target::Return { depth });
+        true
+    }
+
+    // the next run_due_frames calls run until pc gets to the address, at any depth
+    pub fn run_to(&mut self, address: u16) {
+        self.target = Some(Target::Address {
+            address,
+            depth: usize::MAX,
+        });
+    }
+
+    // forget about where step_over, step_out or run_to were headed, e.g to continue instead
+    pub fn clear_target(&mut self) {
+        self.target = None;
+    }
+
+    // execute the next instruction, running the end of the frame and the start of the next one
+    // first if the frame has no instructions left. Forgets the target
+    pub fn step(&mut self) -> Result<Stop, EmulatorError> {
+        self.target = None;
+        if self.emulator.has_exited() {
+            return Ok(Stop::Exited);
+        }
+        let pc = loop {
+            if let Some(pc) = self.advance()? {
+                break pc;
+            }
+        };
+        Ok(self.stop_after_instruction(pc).unwrap_or(Stop::Step))
+    }
+
+    // run until the end of the frame, or of the next one when in between frames.
+    // Breakpoints are checked after each instruction, so one at pc doesn't stop it right away.
+    // Any stop but the end of the frame forgets the target
+    pub fn finish_frame(&mut self) -> Result<Stop, EmulatorError> {
+        let stop = self.run_frame_to_stop();
+        if !matches!(stop, Ok(Stop::FrameEnd)) {
+            self.target = None;
+        }
+        stop
+    }
+
+    fn run_frame_to_stop(&mut self) -> Result<Stop, EmulatorError> {
+        if self.emulator.has_exited() {
+            return Ok(Stop::Exited);
+        }
+        while let Some(pc) = self.advance()? {
+            if let Some(stop) = self.stop_after_instruction(pc) {
+                return Ok(stop);
+            }
+            if self.reached_target() {
+                return Ok(Stop::Reached(self.emulator.pc()));
+            }
+        }
+        Ok(Stop::FrameEnd)
+    }
+
+    // sleep until the next frame is due and run the frames that are, like Emulator::run_due_frames
+    // but stopping at breakpoints. Call Emulator::resync first when resuming after a pause
+    pub fn run_due_frames(&mut self) -> Result<Stop, EmulatorError> {
+        for _ in 0..self.emulator.wait_for_frames() {
+            match self.finish_frame()? {
+                Stop::FrameEnd => {}
+                stop => return Ok(stop),
+            }
+        }
+        Ok(Stop::FrameEnd)
+    }
+
+    // undo the last instruction. Forgets the target
+    pub fn reverse_step(&mut self) -> Stop {
+        self.target = None;
+        if self.step_back() {
+            Stop::Step
+        } else {
+            Stop::StartOfHistory
+        }
+    }
+
+    // Go backwards until the last place running forwards would have stopped at a breakpoint
+    // or watchpoint, before the current one. Forgets the target.
+    //
+    // Watchpoints need to know what memory an instruction touched, which the history doesn't
+    // keep, so each instruction on the way is run once more from the state before it
+    pub fn reverse_continue(&mut self) -> Result<Stop, EmulatorError> {
+        self.target = None;
+        if !self.step_back() {
+            return Ok(Stop::StartOfHistory);
+        }
+        loop {
+            if !self.step_back() {
+                return Ok(Stop::StartOfHistory);
+            }
+            if let Some(pc) = self.advance()?
+                && let Some(stop) = self.stop_after_instruction(pc)
+            {
+                return Ok(stop);
+            }
+            self.step_back();
+        }
+    }
+
+    // put the machine back the way it was before the last instruction in the history
+    fn step_back(&mut self) -> bool {
+        let Some(entry) = self.history.pop() else {
+            return false;
+        };
+        self.undo(&entry)
+            .expect("debugger history holds valid entries");
+        true
+    }
+
+    fn undo(&mut self, entry: &[u8]) -> Result<(), StateError> {
+        let mut data = Reader::new(entry);
+        self.emulator.load_registers(&mut data)?;
+        self.cycles_left = Some(data.u32()?);
+
+        // in reverse, in case an instruction wrote the same byte twice
+        let writes = data.u16()? as usize;
+        let writes: Vec<_> = (0..writes)
+            .map(|_| Ok((data.u16()? as usize, data.u8()?)))
+            .collect::<Result<_, StateError>>()?;
+        for (address, val) in writes.into_iter().rev() {
+            self.emulator.set_memory(address, val);
+            if let Some(memory) = &mut self.memory_before {
+                memory[address] = val;
+            }
+        }
+
+        // the rest is there if the instruction changed the screen
+        let delta = data.rest();
+        if !delta.is_empty() {
+            let mut screen = Writer::default();
+            screen.display_buffer(self.emulator.display_buffer());
+            let display_buffer = Reader::new(&decode_delta(&screen.0, delta)).display_buffer()?;
+            self.display_before = Some(display_buffer.clone());
+            self.emulator.set_display_buffer(display_buffer);
+        }
+        Ok(())
+    }
+
+    // Move execution on by one instruction, or by the end of a frame if it has nothing left
+    // to run. Returns the address of the instruction if one was executed
+    fn advance(&mut self) -> Result<Option<u16>, EmulatorError> {
+        let cycles = match self.cycles_left {
+            Some(cycles) => cycles,
+            None => self.emulator.begin_frame(),
+        };
+
+        if cycles == 0 || self.emulator.frame_is_over() {
+            self.emulator.end_frame();
+            self.cycles_left = None;
+            return Ok(None);
+        }
+
+        let mut entry = Writer::default();
+        if self.history.capacity() > 0 {
+            self.emulator.save_registers(&mut entry);
+            entry.u32(cycles);
+            self.memory_before
+                .get_or_insert_with(|| self.emulator.memory().to_vec());
+            self.display_before
+                .get_or_insert_with(|| self.emulator.display_buffer().clone());
+        }
+
+        self.cycles_left = Some(cycles - 1);
+        let pc = self.emulator.pc();
+        let result = self.emulator.step();
+        if self.history.capacity() > 0 {
+            self.record_changes(&mut entry);
+            self.history.push(entry.0);
+        }
+        result?;
+        Ok(Some(pc))
+    }
+
+    // add what the instruction that just ran wrote to memory and drew on the screen, as it was
+    // before, to its history entry and bring memory_before and display_before up to date
+    fn record_changes(&mut self, entry: &mut Writer) {
+        let memory = self.memory_before.as_mut().unwrap();
+        let writes = || {
+            self.emulator
+                .memory_accesses()
+                .iter()
+                .filter(|access| access.kind == AccessKind::Write)
+        };
+        entry.u16(writes().count() as u16);
+        for access in writes() {
+            entry.u16(access.address as u16);
+            entry.u8(memory[access.address]);
+            memory[access.address] = self.emulator.memory()[access.address];
+        }
+
+        let display = self.display_before.as_mut().unwrap();
+        if display != self.emulator.display_buffer() {
+            let mut before = Writer::default();
+            before.display_buffer(display);
+            let mut after = Writer::default();
+            after.display_buffer(self.emulator.display_buffer());
+            entry.bytes(&encode_delta(&before.0, &after.0));
+            display.clone_from(self.emulator.display_buffer());
+        }
+    }
+
+    fn reached_target(&self) -> bool {
+        let depth = self.emulator.stack().len();
+        match self.target {
+            Some(Target::Address {
+                address,
+                depth: max_depth,
+            }) => self.emulator.pc() == address && depth <= max_depth,
+            Some(Target::Return { depth: start_depth }) => depth < start_depth,
+            None => false,
+        }
+    }
+
+    // The first breakpoint that holds where execution is now, before anything ran, e.g one at
+    // the entry point when the program starts running. Watchpoints need an instruction to
+    // have run and are left out
+    pub fn breakpoint_at_pc(&self) -> Option<Stop> {
+        let pc = self.emulator.pc();
+        self.breakpoints
+            .iter()
+            .filter(|breakpoint| !matches!(breakpoint.location, Location::Watch(_)))
+            .find_map(|breakpoint| self.check(breakpoint, pc))
+    }
+
+    // whether to stop after the instruction at pc ran
+    fn stop_after_instruction(&self, pc: u16) -> Option<Stop> {
+        if self.emulator.has_exited() {
+            return Some(Stop::Exited);
+        }
+        self.breakpoints
+            .iter()
+            .find_map(|breakpoint| self.check(breakpoint, pc))
+    }
+
+    // where the breakpoint stops execution if it hits and its condition holds
+    fn check(&self, breakpoint: &Breakpoint, pc: u16) -> Option<Stop> {
+        let stop = self.hit(breakpoint, pc)?;
+        let condition_holds = breakpoint
+            .condition
+            .as_ref()
+            .is_none_or(|condition| condition.is_true(&self.emulator));
+        condition_holds.then_some(stop)
+    }
+
+    // where the breakpoint is, ignoring its condition
+    fn hit(&self, breakpoint: &Breakpoint, pc: u16) -> Option<Stop> {
+        let next_pc = self.emulator.pc();
+        let hit = match &breakpoint.location {
+            Location::Address(address) => *address == next_pc,
+            Location::Opcode(pattern) => self
+                .emulator
+                .instruction_at(next_pc)
+                .is_some_and(|instruction| pattern.matches(&instruction)),
+            Location::Watch(watchpoint) => {
+                return self
+                    .emulator
+                    .memory_accesses()
+                    .iter()
+                    .find(|access| watchpoint.matches(access))
+                    .map(|access| Stop::Watchpoint {
+                        id: breakpoint.id,
+                        pc,
+                        access: *access,
+                    });
+            }
+            Location::Anywhere => true,
+        };
+        hit.then_some(Stop::Breakpoint(breakpoint.id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quirks::Platform;
+
+    fn load(opcodes: &[u16]) -> Debugger {
+        load_for(Platform::Chip48, opcodes)
+    }
+
+    fn load_for(platform: Platform, opcodes: &[u16]) -> Debugger {
+        let rom = opcodes
+            .iter()
+            .flat_map(|opcode| opcode.to_be_bytes())
+            .collect();
+        let emulator = Emulator::builder()
+            .platform(platform)
+            .seed(0)
+            .rom(rom)
+            .build()
+            .unwrap();
+        Debugger::new(emulator)
+    }
+
+    // run frames until something other than the end of a frame stops execution
+    fn run(debugger: &mut Debugger) -> Stop {
+        loop {
+            match debugger.finish_frame().unwrap() {
+                Stop::FrameEnd => {}
+                stop => return stop,
+            }
+        }
+    }
+
+    // counts V0 up forever
+    const COUNTER: [u16; 2] = [0x7001, 0x1200];
+
+    #[test]
+    fn step() {
+        let mut debugger = load(&COUNTER);
+        assert_eq!(debugger.step(), Ok(Stop::Step));
+        assert_eq!(debugger.emulator().pc(), 0x202);
+        assert_eq!(debugger.emulator().var_registers()[0], 1);
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut debugger = load(&COUNTER);
+        let id = debugger.add_breakpoint(Location::Address(0x202), None);
+        assert_eq!(debugger.breakpoint_at(0x202), Some(id));
+        assert_eq!(run(&mut debugger), Stop::Breakpoint(id));
+        assert_eq!(debugger.emulator().pc(), 0x202);
+
+        assert!(debugger.remove_breakpoint(id));
+        assert!(!debugger.remove_breakpoint(id));
+        assert!(debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn breakpoint_at_pc() {
+        let mut debugger = load(&COUNTER);
+        assert_eq!(debugger.breakpoint_at_pc(), None);
+        let condition = "V0 == 1".parse().unwrap();
+        debugger.add_breakpoint(Location::Address(0x200), Some(condition));
+        assert_eq!(debugger.breakpoint_at_pc(), None);
+        let id = debugger.add_breakpoint(Location::Address(0x200), None);
+        assert_eq!(debugger.breakpoint_at_pc(), Some(Stop::Breakpoint(id)));
+    }
+
+    #[test]
+    fn conditional_breakpoints() {
+        let mut debugger = load(&COUNTER);
+        let condition = "V0 == 30".parse().unwrap();
+        let id = debugger.add_breakpoint(Location::Address(0x202), Some(condition));
+        assert_eq!(run(&mut debugger), Stop::Breakpoint(id));
+        assert_eq!(debugger.emulator().var_registers()[0], 30);
+    }
+
+    #[test]
+    fn opcode_breakpoints() {
+        let mut debugger = load(&COUNTER);
+        let id = debugger.add_breakpoint(Location::Opcode("1nnn".parse().unwrap()), None);
+        assert_eq!(run(&mut debugger), Stop::Breakpoint(id));
+        assert_eq!(debugger.emulator().pc(), 0x202);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let mut debugger = load(&[0xA300, 0x6005, 0xF055, 0x1206]);
+        let watchpoint = Watchpoint {
+            start: 0x300,
+            len: 1,
+            read: false,
+            write: true,
+            fetch: false,
+        };
+        let id = debugger.add_breakpoint(Location::Watch(watchpoint), None);
+        assert_eq!(
+            run(&mut debugger),
+            Stop::Watchpoint {
+                id,
+                pc: 0x204,
+                access: MemoryAccess {
+                    address: 0x300,
+                    kind: AccessKind::Write
+                }
+            }
+        );
+        assert_eq!(watchpoint.to_string(), "write 0x300");
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        // 0x200 calls the subroutine at 0x206, which adds 2 to V0
+        let mut debugger = load(&[0x2206, 0x7001, 0x1204, 0x7001, 0x7001, 0x00EE]);
+        assert!(debugger.step_over());
+        assert_eq!(run(&mut debugger), Stop::Reached(0x202));
+        assert_eq!(debugger.emulator().var_registers()[0], 2);
+
+        let mut debugger = load(&[0x2206, 0x7001, 0x1204, 0x7001, 0x7001, 0x00EE]);
+        debugger.step().unwrap();
+        assert!(debugger.step_out());
+        assert_eq!(run(&mut debugger), Stop::Reached(0x202));
+        assert!(!debugger.step_out());
+    }
+
+    #[test]
+    fn exit() {
+        let mut debugger = load(&[0x00FD]);
+        assert_eq!(debugger.step(), Ok(Stop::Exited));
+        assert_eq!(run(&mut debugger), Stop::Exited);
+        assert_eq!(debugger.step(), Ok(Stop::Exited));
+    }
+
+    #[test]
+    fn reverse_step() {
+        let mut debugger = load(&COUNTER);
+        debugger.step().unwrap();
+        let start = debugger.emulator().snapshot();
+        for _ in 0..100 {
+            debugger.step().unwrap();
+        }
+        let end = debugger.emulator().snapshot();
+        assert_eq!(debugger.history_len(), 101);
+
+        for _ in 0..100 {
+            assert_eq!(debugger.reverse_step(), Stop::Step);
+        }
+        assert!(debugger.emulator().snapshot() == start);
+
+        for _ in 0..100 {
+            debugger.step().unwrap();
+        }
+        assert!(debugger.emulator().snapshot() == end);
+
+        for _ in 0..101 {
+            debugger.reverse_step();
+        }
+        assert_eq!(debugger.reverse_step(), Stop::StartOfHistory);
+    }
+
+    #[test]
+    fn reverse_step_undoes_memory_and_screen() {
+        // stores V0 at 0x300, then draws the 0 of the font at V0, which waits for the next frame
+        let mut debugger = load_for(
+            Platform::CosmacVip,
+            &[0xA300, 0x6005, 0xF055, 0xA050, 0xD015],
+        );
+        for _ in 0..3 {
+            debugger.step().unwrap();
+        }
+        assert_eq!(debugger.emulator().memory()[0x300], 5);
+        assert_eq!(debugger.reverse_step(), Stop::Step);
+        assert_eq!(debugger.emulator().memory()[0x300], 0);
+        debugger.step().unwrap();
+        assert_eq!(debugger.emulator().memory()[0x300], 5);
+
+        debugger.step().unwrap();
+        let before_draw = debugger.emulator().snapshot();
+        debugger.step().unwrap();
+        let after_draw = debugger.emulator().snapshot();
+        assert!(debugger.emulator().frame_is_over());
+        assert_eq!(debugger.emulator().display_buffer().pixel(5, 0), 1);
+
+        assert_eq!(debugger.reverse_step(), Stop::Step);
+        assert!(debugger.emulator().snapshot() == before_draw);
+        assert!(!debugger.emulator().frame_is_over());
+        assert_eq!(debugger.emulator().display_buffer().pixel(5, 0), 0);
+
+        debugger.step().unwrap();
+        assert!(debugger.emulator().snapshot() == after_draw);
+        assert!(debugger.emulator().frame_is_over());
+    }
+
+    #[test]
+    fn reverse_continue() {
+        let mut debugger = load(&COUNTER);
+        let condition = "V0 == 10".parse().unwrap();
+        let id = debugger.add_breakpoint(Location::Address(0x202), Some(condition));
+        assert_eq!(run(&mut debugger), Stop::Breakpoint(id));
+        for _ in 0..20 {
+            debugger.step().unwrap();
+        }
+
+        assert_eq!(debugger.reverse_continue(), Ok(Stop::Breakpoint(id)));
+        assert_eq!(debugger.emulator().var_registers()[0], 10);
+        assert_eq!(debugger.emulator().pc(), 0x202);
+        assert_eq!(debugger.reverse_continue(), Ok(Stop::StartOfHistory));
+    }
+
+    #[test]
+    fn no_history() {
+        let mut debugger = load(&COUNTER);
+        debugger.set_history_capacity(0);
+        debugger.step().unwrap();
+        assert_eq!(debugger.reverse_step(), Stop::StartOfHistory);
+    }
+}
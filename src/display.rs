@@ -0,0 +1,313 @@
+// the largest screen any of the display modes uses, SUPER-CHIP's high resolution mode
+pub const MAX_WIDTH: usize = 128;
+pub const MAX_HEIGHT: usize = 64;
+
+// XO-CHIP draws to two bitplanes, together they pick one of four colours for each pixel
+pub const PLANE_COUNT: usize = 2;
+
+// the colours of the four combinations of the two bitplanes, indexed by the pixel value
+// (0 off, 1 only the first plane, 2 only the second plane, 3 both), as RGBA
+pub type Palette = [[u8; 4]; 1 << PLANE_COUNT];
+
+// The display modes a program can switch between at runtime.
+// Chip-8 programs always run in Low, SUPER-CHIP adds 00FF and 00FE to switch modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    // 64x32
+    Low,
+    // 128x64
+    High,
+}
+
+impl Resolution {
+    pub fn width(self) -> usize {
+        match self {
+            Resolution::Low => 64,
+            Resolution::High => MAX_WIDTH,
+        }
+    }
+
+    pub fn height(self) -> usize {
+        match self {
+            Resolution::Low => 32,
+            Resolution::High => MAX_HEIGHT,
+        }
+    }
+}
+
+// Display state, one bit per pixel in each of the bitplanes. Each row of a plane is one u128
+// where the most significant bit is the leftmost pixel. In low resolution only the top 64 bits
+// of the first 32 rows are used. Chip-8 and SUPER-CHIP programs only ever touch the first plane.
+// This is the real screen of the emulator, the colours only come into play when a frontend presents it.
+//
+// Operations that take a `planes` argument only affect the planes whose bit is set in it,
+// bit 0 is the first plane and bit 1 the second, the same mask XO-CHIP's FN01 selects.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DisplayBuffer {
+    resolution: Resolution,
+    planes: [[u128; MAX_HEIGHT]; PLANE_COUNT],
+}
+
+impl Default for DisplayBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DisplayBuffer {
+    pub fn new() -> Self {
+        Self {
+            resolution: Resolution::Low,
+            planes: [[0; MAX_HEIGHT]; PLANE_COUNT],
+        }
+    }
+
+    // a buffer with exactly these contents, e.g one restored from a save state
+    pub fn from_planes(resolution: Resolution, planes: [[u128; MAX_HEIGHT]; PLANE_COUNT]) -> Self {
+        Self { resolution, planes }
+    }
+
+    // every row of every plane, including the ones that are off screen in low resolution
+    pub fn planes(&self) -> &[[u128; MAX_HEIGHT]; PLANE_COUNT] {
+        &self.planes
+    }
+
+    // clear every plane
+    pub fn clear(&mut self) {
+        self.clear_planes(!0);
+    }
+
+    pub fn clear_planes(&mut self, planes: u8) {
+        for plane in self.selected_planes(planes) {
+            *plane = [0; MAX_HEIGHT];
+        }
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    // switching display modes also clears the screen
+    pub fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+        self.clear();
+    }
+
+    pub fn width(&self) -> usize {
+        self.resolution.width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.resolution.height()
+    }
+
+    // the visible rows of a plane in the current display mode
+    pub fn rows(&self, plane: usize) -> &[u128] {
+        &self.planes[plane][..self.height()]
+    }
+
+    // the value of the pixel at x, y, with one bit for each plane that has it turned on
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.planes
+            .iter()
+            .enumerate()
+            .map(|(idx, plane)| (((plane[y] >> (MAX_WIDTH - 1 - x)) & 1) as u8) << idx)
+            .sum()
+    }
+
+    // XOR a sprite row of sprite_width pixels (8, or 16 for SUPER-CHIP's big sprites)
+    // onto row y of a plane, starting at column x.
+    // Pixels that fall off the right edge are clipped, or wrap around to the left edge.
+    // Returns true if any pixel was turned off.
+    pub fn draw_sprite_row(
+        &mut self,
+        plane: usize,
+        x: usize,
+        y: usize,
+        sprite_data: u16,
+        sprite_width: usize,
+        wrap: bool,
+    ) -> bool {
+        let sprite_row = (sprite_data as u128) << (MAX_WIDTH - sprite_width);
+        let mut mask = (sprite_row >> x) & self.row_mask();
+        if wrap {
+            // the columns that went past the right edge, moved back to the left edge
+            mask |= sprite_row
+                .checked_shl((self.width() - x) as u32)
+                .unwrap_or(0);
+        }
+
+        let row = &mut self.planes[plane][y];
+        let collision = *row & mask != 0;
+        *row ^= mask;
+        collision
+    }
+
+    // move the screen down n pixels, the rows at the top are left blank
+    pub fn scroll_down(&mut self, planes: u8, n: usize) {
+        let height = self.height();
+        for rows in self.selected_planes(planes) {
+            for y in (0..height).rev() {
+                rows[y] = if y >= n { rows[y - n] } else { 0 };
+            }
+        }
+    }
+
+    // move the screen up n pixels, the rows at the bottom are left blank
+    pub fn scroll_up(&mut self, planes: u8, n: usize) {
+        let height = self.height();
+        for rows in self.selected_planes(planes) {
+            for y in 0..height {
+                rows[y] = if y + n < height { rows[y + n] } else { 0 };
+            }
+        }
+    }
+
+    // move the screen right n pixels, the columns on the left are left blank
+    pub fn scroll_right(&mut self, planes: u8, n: usize) {
+        let row_mask = self.row_mask();
+        for rows in self.selected_planes(planes) {
+            for row in rows.iter_mut() {
+                *row = (*row >> n) & row_mask;
+            }
+        }
+    }
+
+    // move the screen left n pixels, the columns on the right are left blank
+    pub fn scroll_left(&mut self, planes: u8, n: usize) {
+        for rows in self.selected_planes(planes) {
+            for row in rows.iter_mut() {
+                *row <<= n;
+            }
+        }
+    }
+
+    // convert to an RGBA buffer of width x height pixels of the current display mode
+    pub fn to_rgba(&self, frame: &mut [u8], palette: &Palette) {
+        let width = self.width();
+        for (idx, pixel) in frame.chunks_exact_mut(4).enumerate() {
+            let x = idx % width;
+            let y = idx / width;
+            pixel.copy_from_slice(&palette[self.pixel(x, y) as usize]);
+        }
+    }
+
+    fn selected_planes(&mut self, planes: u8) -> impl Iterator<Item = &mut [u128; MAX_HEIGHT]> {
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(idx, _)| planes & (1 << idx) != 0)
+            .map(|(_, rows)| rows)
+    }
+
+    // the bits of a row that are on screen in the current display mode
+    fn row_mask(&self) -> u128 {
+        !0 << (MAX_WIDTH - self.width())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_and_collide() {
+        let mut display = DisplayBuffer::new();
+        assert!(!display.draw_sprite_row(0, 0, 0, 0b1100_0000, 8, false));
+        assert_eq!(display.pixel(0, 0), 1);
+        assert_eq!(display.pixel(1, 0), 1);
+        assert_eq!(display.pixel(2, 0), 0);
+        assert!(display.draw_sprite_row(0, 1, 0, 0b1000_0000, 8, false));
+        assert_eq!(display.pixel(1, 0), 0);
+    }
+
+    #[test]
+    fn clip_or_wrap_at_the_right_edge() {
+        let mut clipped = DisplayBuffer::new();
+        clipped.draw_sprite_row(0, 62, 0, 0xF0, 8, false);
+        assert_eq!(clipped.pixel(63, 0), 1);
+        assert_eq!(clipped.pixel(0, 0), 0);
+        // nothing is drawn past the edge of the low resolution screen either
+        assert_eq!(clipped.pixel(64, 0), 0);
+
+        let mut wrapped = DisplayBuffer::new();
+        wrapped.draw_sprite_row(0, 62, 0, 0xF0, 8, true);
+        assert_eq!(wrapped.pixel(63, 0), 1);
+        assert_eq!(wrapped.pixel(0, 0), 1);
+        assert_eq!(wrapped.pixel(1, 0), 1);
+        assert_eq!(wrapped.pixel(2, 0), 0);
+    }
+
+    #[test]
+    fn planes() {
+        let mut display = DisplayBuffer::new();
+        display.draw_sprite_row(0, 0, 0, 0x80, 8, false);
+        display.draw_sprite_row(1, 0, 0, 0xC0, 8, false);
+        assert_eq!(display.pixel(0, 0), 3);
+        assert_eq!(display.pixel(1, 0), 2);
+
+        display.clear_planes(0b10);
+        assert_eq!(display.pixel(0, 0), 1);
+        assert_eq!(display.pixel(1, 0), 0);
+    }
+
+    #[test]
+    fn scroll() {
+        let mut display = DisplayBuffer::new();
+        display.draw_sprite_row(0, 4, 4, 0x80, 8, false);
+
+        display.scroll_down(1, 2);
+        assert_eq!(display.pixel(4, 6), 1);
+        display.scroll_up(1, 2);
+        assert_eq!(display.pixel(4, 4), 1);
+        display.scroll_right(1, 4);
+        assert_eq!(display.pixel(8, 4), 1);
+        display.scroll_left(1, 8);
+        assert_eq!(display.pixel(0, 4), 1);
+
+        // pixels scrolled off the screen are gone for good
+        display.scroll_left(1, 1);
+        display.scroll_right(1, 1);
+        assert_eq!(display, DisplayBuffer::new());
+
+        // only the selected planes move
+        display.draw_sprite_row(0, 0, 0, 0x80, 8, false);
+        display.scroll_down(0b10, 1);
+        assert_eq!(display.pixel(0, 0), 1);
+    }
+
+    #[test]
+    fn scroll_right_stays_on_the_low_resolution_screen() {
+        let mut display = DisplayBuffer::new();
+        display.draw_sprite_row(0, 56, 0, 0x01, 8, false);
+        display.scroll_right(1, 4);
+        assert_eq!(display, DisplayBuffer::new());
+    }
+
+    #[test]
+    fn switching_resolution_clears_the_screen() {
+        let mut display = DisplayBuffer::new();
+        display.draw_sprite_row(0, 0, 0, 0x80, 8, false);
+        display.set_resolution(Resolution::High);
+        assert_eq!(display.width(), 128);
+        assert_eq!(display.height(), 64);
+        assert_eq!(display.pixel(0, 0), 0);
+        assert_eq!(display.rows(0).len(), 64);
+    }
+
+    #[test]
+    fn rgba() {
+        let palette = [
+            [0, 0, 0, 255],
+            [255, 255, 255, 255],
+            [1, 1, 1, 1],
+            [2, 2, 2, 2],
+        ];
+        let mut display = DisplayBuffer::new();
+        display.draw_sprite_row(0, 1, 0, 0x80, 8, false);
+        let mut frame = vec![0; 64 * 32 * 4];
+        display.to_rgba(&mut frame, &palette);
+        assert_eq!(frame[..4], palette[0]);
+        assert_eq!(frame[4..8], palette[1]);
+    }
+}
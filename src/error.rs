@@ -0,0 +1,182 @@
+use crate::random::VIP_INTERPRETER_SIZE;
+use std::fmt;
+
+// Everything that can go wrong while loading or running a program.
+// Errors raised by an instruction carry the address of that instruction (pc) and its opcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmulatorError {
+    // a subroutine was called with all stack slots in use
+    StackOverflow { pc: u16, opcode: u16 },
+
+    // returned from a subroutine while the stack was empty
+    StackUnderflow { pc: u16, opcode: u16 },
+
+    // an instruction read or wrote memory past the end of RAM
+    MemoryOutOfBounds { pc: u16, opcode: u16, address: usize },
+
+    // the pc ran off the end of RAM, so there is no instruction to fetch
+    PcOutOfBounds { pc: u16 },
+
+    UnknownOpcode { pc: u16, opcode: u16 },
+
+    // the ROM doesn't fit in the memory between the program start and the end of RAM
+    RomTooLarge { size: usize, max_size: usize },
+
+    // the dump of the COSMAC VIP interpreter for its random numbers isn't 512 bytes
+    InvalidVipInterpreter { size: usize },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulatorError::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow at {:#05x} ({:04x})", pc, opcode)
+            }
+            EmulatorError::StackUnderflow { pc, opcode } => write!(
+                f,
+                "return with an empty stack at {:#05x} ({:04x})",
+                pc, opcode
+            ),
+            EmulatorError::MemoryOutOfBounds {
+                pc,
+                opcode,
+                address,
+            } => write!(
+                f,
+                "memory access out of bounds at {:#05x} ({:04x}): address {:#x}",
+                pc, opcode, address
+            ),
+            EmulatorError::PcOutOfBounds { pc } => {
+                write!(f, "program counter out of bounds: {:#x}", pc)
+            }
+            EmulatorError::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode at {:#05x}: {:04x}", pc, opcode)
+            }
+            EmulatorError::RomTooLarge { size, max_size } => write!(
+                f,
+                "rom is too large: {} bytes, at most {} bytes fit in memory",
+                size, max_size
+            ),
+            EmulatorError::InvalidVipInterpreter { size } => write!(
+                f,
+                "the COSMAC VIP interpreter is {} bytes, expected a dump of {} bytes",
+                size, VIP_INTERPRETER_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
+
+// What went wrong inside an instruction, turned into an EmulatorError
+// once the address and opcode of the instruction are attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),
+    UnknownOpcode,
+}
+
+impl Fault {
+    pub(crate) fn at(self, pc: u16, opcode: u16) -> EmulatorError {
+        match self {
+            Fault::StackOverflow => EmulatorError::StackOverflow { pc, opcode },
+            Fault::StackUnderflow => EmulatorError::StackUnderflow { pc, opcode },
+            Fault::MemoryOutOfBounds(address) => EmulatorError::MemoryOutOfBounds {
+                pc,
+                opcode,
+                address,
+            },
+            Fault::UnknownOpcode => EmulatorError::UnknownOpcode { pc, opcode },
+        }
+    }
+}
+
+// Why a save state couldn't be loaded. States are checked before anything is restored,
+// so a bad one leaves the running program alone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    // the data doesn't start with the save state magic bytes
+    NotAState,
+
+    // saved by a version of the emulator whose format this one doesn't read
+    UnsupportedVersion { version: u16 },
+
+    // the data ends before the state does
+    Truncated,
+
+    // the contents don't match the checksum in the header
+    ChecksumMismatch,
+
+    // the checksum is fine but a value is out of range, e.g a stack deeper than 16
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion { version } => write!(
+                f,
+                "save state version {} is not supported, expected version {}",
+                version,
+                crate::state::VERSION
+            ),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::ChecksumMismatch => {
+                write!(f, "save state is corrupted, the checksum doesn't match")
+            }
+            StateError::Invalid(reason) => write!(f, "save state is invalid: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// Why an input recording couldn't be replayed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    // the data doesn't start with the replay magic bytes
+    NotAReplay,
+
+    // recorded by a version of the emulator whose format this one doesn't read
+    UnsupportedVersion { version: u16 },
+
+    // the data ends in the middle of the header or of an event
+    Truncated,
+
+    // a value is out of range, e.g a key above F
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::NotAReplay => write!(f, "not an input recording"),
+            ReplayError::UnsupportedVersion { version } => write!(
+                f,
+                "input recording version {} is not supported, expected version {}",
+                version,
+                crate::replay::VERSION
+            ),
+            ReplayError::Truncated => write!(f, "input recording is truncated"),
+            ReplayError::Invalid(reason) => write!(f, "input recording is invalid: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+// the replay header is read with the same decoder as save states
+impl From<StateError> for ReplayError {
+    fn from(error: StateError) -> Self {
+        match error {
+            StateError::Truncated => ReplayError::Truncated,
+            StateError::Invalid(reason) => ReplayError::Invalid(reason),
+            StateError::NotAState
+            | StateError::UnsupportedVersion { .. }
+            | StateError::ChecksumMismatch => ReplayError::Invalid("malformed header"),
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use crate::chip8::Emulator;
+use std::fmt;
+use std::str::FromStr;
+
+// Conditions for breakpoints, e.g "V3 == 0x10 && I > 0x300", evaluated against the machine
+// in between instructions.
+//
+// Values are the registers V0-VF, I, PC, DT and ST, numbers in decimal or with a 0x prefix,
+// and [address] for the byte of memory at an address, 0 past the end of memory. The operators
+// are, from loosest to tightest: ||, &&, the comparisons == != < <= > >=, then + - & | and
+// finally !, with parentheses to group. Anything but 0 counts as true, comparisons give 1 or 0.
+
+// A register that can be read, and set by debuggers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    pub fn read(self, emulator: &Emulator) -> u16 {
+        match self {
+            Register::V(x) => emulator.var_registers()[(x & 0xF) as usize] as u16,
+            Register::I => emulator.index_register(),
+            Register::Pc => emulator.pc(),
+            Register::DelayTimer => emulator.delay_timer() as u16,
+            Register::SoundTimer => emulator.sound_timer() as u16,
+        }
+    }
+
+    // Sets the register, the 8-bit ones refuse values above 0xFF
+    pub fn write(self, emulator: &mut Emulator, val: u16) -> Result<(), String> {
+        let byte = || u8::try_from(val).map_err(|_| format!("{:#x} doesn't fit in {}", val, self));
+        match self {
+            Register::V(x) => emulator.set_var_register(x, byte()?),
+            Register::I => emulator.set_index_register(val),
+            Register::Pc => emulator.set_pc(val),
+            Register::DelayTimer => emulator.set_delay_timer(byte()?),
+            Register::SoundTimer => emulator.set_sound_timer(byte()?),
+        }
+        Ok(())
+    }
+}
+
+// case doesn't matter, e.g v3, V3, pc and PC
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_uppercase().as_str() {
+            "I" => Ok(Register::I),
+            "PC" => Ok(Register::Pc),
+            "DT" => Ok(Register::DelayTimer),
+            "ST" => Ok(Register::SoundTimer),
+            name => name
+                .strip_prefix('V')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| format!("unknown register {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    root: Node,
+
+    // as written, for listing breakpoints
+    text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Binary(Operator, Box<Node>, Box<Node>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract,
+    BitAnd,
+    BitOr,
+}
+
+impl Expression {
+    pub fn eval(&self, emulator: &Emulator) -> i64 {
+        self.root.eval(emulator)
+    }
+
+    pub fn is_true(&self, emulator: &Emulator) -> bool {
+        self.eval(emulator) != 0
+    }
+}
+
+impl Node {
+    fn eval(&self, emulator: &Emulator) -> i64 {
+        match self {
+            Node::Number(val) => *val,
+            Node::Register(register) => register.read(emulator) as i64,
+            Node::Memory(address) => usize::try_from(address.eval(emulator))
+                .ok()
+                .and_then(|address| emulator.memory().get(address))
+                .map_or(0, |byte| *byte as i64),
+            Node::Not(node) => (node.eval(emulator) == 0) as i64,
+            Node::Binary(Operator::Or, lhs, rhs) => {
+                (lhs.eval(emulator) != 0 || rhs.eval(emulator) != 0) as i64
+            }
+            Node::Binary(Operator::And, lhs, rhs) => {
+                (lhs.eval(emulator) != 0 && rhs.eval(emulator) != 0) as i64
+            }
+            Node::Binary(operator, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(emulator), rhs.eval(emulator));
+                match operator {
+                    Operator::Equal => (lhs == rhs) as i64,
+                    Operator::NotEqual => (lhs != rhs) as i64,
+                    Operator::Less => (lhs < rhs) as i64,
+                    Operator::LessOrEqual => (lhs <= rhs) as i64,
+                    Operator::Greater => (lhs > rhs) as i64,
+                    Operator::GreaterOrEqual => (lhs >= rhs) as i64,
+                    Operator::Add => lhs.wrapping_add(rhs),
+                    Operator::Subtract => lhs.wrapping_sub(rhs),
+                    Operator::BitAnd => lhs & rhs,
+                    Operator::BitOr => lhs | rhs,
+                    Operator::Or | Operator::And => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let root = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {} in {}", token, s));
+        }
+        Ok(Self {
+            root,
+            text: s.trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+// operators longest first, so == isn't read as two =
+const SYMBOLS: [&str; 17] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "&", "|", "!", "(", ")", "[", "]",
+];
+
+// numbers and register names, or symbols
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let word_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        let len = if word_len > 0 {
+            word_len
+        } else {
+            SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(*symbol))
+                .map(|symbol| symbol.len())
+                .ok_or_else(|| format!("unexpected {} in {}", &rest[..1], s))?
+        };
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or("unexpected end of the expression")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected {} but found {}", expected, token)),
+        }
+    }
+
+    // one level of left associative binary operators
+    fn binary(
+        &mut self,
+        operators: &[(&str, Operator)],
+        operand: fn(&mut Self) -> Result<Node, String>,
+    ) -> Result<Node, String> {
+        let mut node = operand(self)?;
+        while let Some(&(_, operator)) = operators
+            .iter()
+            .find(|(symbol, _)| self.peek() == Some(symbol))
+        {
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(operand(self)?));
+        }
+        Ok(node)
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        self.binary(&[("||", Operator::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        self.binary(&[("&&", Operator::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[
+                ("==", Operator::Equal),
+                ("!=", Operator::NotEqual),
+                ("<", Operator::Less),
+                ("<=", Operator::LessOrEqual),
+                (">", Operator::Greater),
+                (">=", Operator::GreaterOrEqual),
+            ],
+            Self::arithmetic,
+        )
+    }
+
+    fn arithmetic(&mut self) -> Result<Node, String> {
+        self.binary(
+            &[
+                ("+", Operator::Add),
+                ("-", Operator::Subtract),
+                ("&", Operator::BitAnd),
+                ("|", Operator::BitOr),
+            ],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let token = self.next()?;
+        match token.as_str() {
+            "!" => Ok(Node::Not(Box::new(self.unary()?))),
+            "(" => {
+                let node = self.or()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            "[" => {
+                let node = self.or()?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(node)))
+            }
+            _ if token.starts_with(|c: char| c.is_ascii_digit()) => parse_number(&token),
+            _ if token.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                Ok(Node::Register(token.parse()?))
+            }
+            _ => Err(format!("unexpected {}", token)),
+        }
+    }
+}
+
+fn parse_number(token: &str) -> Result<Node, String> {
+    let parsed = match token
+        .strip_prefix("0x")
+        .or_else(|| token.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => token.parse(),
+    };
+    parsed
+        .map(Node::Number)
+        .map_err(|_| format!("expected a number: {}", token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str, emulator: &Emulator) -> i64 {
+        expression.parse::<Expression>().unwrap().eval(emulator)
+    }
+
+    fn emulator() -> Emulator {
+        let mut emulator = Emulator::builder().build().unwrap();
+        emulator.set_var_register(3, 0x10);
+        emulator.set_index_register(0x300);
+        emulator
+    }
+
+    #[test]
+    fn values() {
+        let emulator = emulator();
+        assert_eq!(eval("42", &emulator), 42);
+        assert_eq!(eval("0x2A", &emulator), 42);
+        assert_eq!(eval("v3", &emulator), 0x10);
+        assert_eq!(eval("I", &emulator), 0x300);
+        assert_eq!(eval("pc", &emulator), 0x200);
+        // the top row of the font's 0
+        assert_eq!(eval("[0x50]", &emulator), 0xF0);
+        assert_eq!(eval("[0x4F + 1]", &emulator), 0xF0);
+        assert_eq!(eval("[0x10000]", &emulator), 0);
+    }
+
+    #[test]
+    fn precedence() {
+        let emulator = emulator();
+        assert_eq!(eval("1 + 2 == 3", &emulator), 1);
+        assert_eq!(eval("1 | 2 == 3", &emulator), 1);
+        assert_eq!(eval("0 && 1 || 1", &emulator), 1);
+        assert_eq!(eval("1 || 1 && 0", &emulator), 1);
+        assert_eq!(eval("(1 || 1) && 0", &emulator), 0);
+        assert_eq!(eval("!0 + 1", &emulator), 2);
+        assert_eq!(eval("!(0 + 1)", &emulator), 0);
+        assert_eq!(eval("V3 == 0x10 && I > 0x2FF", &emulator), 1);
+    }
+
+    #[test]
+    fn left_associative() {
+        let emulator = emulator();
+        assert_eq!(eval("5 - 2 - 1", &emulator), 2);
+        assert_eq!(eval("1 < 2 < 1", &emulator), 0);
+    }
+
+    #[test]
+    fn comparisons() {
+        let emulator = emulator();
+        for (expression, expected) in [
+            ("1 == 1", 1),
+            ("1 != 1", 0),
+            ("1 < 2", 1),
+            ("2 <= 2", 1),
+            ("1 > 2", 0),
+            ("2 >= 3", 0),
+        ] {
+            assert_eq!(eval(expression, &emulator), expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        for expression in ["", "V3 ==", "1 2", "(1", "[1", "VG", "1 $ 2", "0xZ"] {
+            assert!(
+                expression.parse::<Expression>().is_err(),
+                "{} should not parse",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_the_text() {
+        let expression: Expression = " V3 == 1 ".parse().unwrap();
+        assert_eq!(expression.to_string(), "V3 == 1");
+    }
+
+    #[test]
+    fn registers() {
+        let mut emulator = emulator();
+        assert_eq!("dt".parse(), Ok(Register::DelayTimer));
+        assert_eq!("Va".parse(), Ok(Register::V(0xA)));
+        assert!("V10".parse::<Register>().is_err());
+
+        Register::I.write(&mut emulator, 0x1234).unwrap();
+        assert_eq!(emulator.index_register(), 0x1234);
+        assert!(Register::V(0).write(&mut emulator, 0x100).is_err());
+        assert_eq!(Register::V(0xA).to_string(), "VA");
+    }
+}
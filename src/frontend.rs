@@ -0,0 +1,236 @@
+use crate::audio::{AudioPattern, Beep, Buzzer, SAMPLE_RATE, Sample};
+use crate::backend::{Audio, Display, Input, KeyEvent};
+use crate::display::{DisplayBuffer, Palette, Resolution};
+use pixels::Pixels;
+use rodio::{Decoder, Source};
+use std::fs::File;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+use winit::event::ElementState;
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+// Desktop backends, the emulator draws into a pixels surface owned by the winit window,
+// reads the keypad from winit keyboard events and plays its beep through rodio
+
+// off, first plane, second plane, both planes.
+// Programs that only use the first plane are black and white
+const PALETTE: Palette = [
+    [0x00, 0x00, 0x00, 0xFF],
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+];
+
+pub struct PixelsDisplay {
+    pixel_buffer: Arc<Mutex<Pixels<'static>>>,
+
+    // the display mode the pixel buffer is currently sized for, None until the first present
+    resolution: Option<Resolution>,
+}
+
+impl PixelsDisplay {
+    pub fn new(pixel_buffer: Arc<Mutex<Pixels<'static>>>) -> Self {
+        Self {
+            pixel_buffer,
+            resolution: None,
+        }
+    }
+}
+
+impl Display for PixelsDisplay {
+    fn present(&mut self, buffer: &DisplayBuffer) {
+        let mut locked_buffer = self.pixel_buffer.lock().unwrap();
+
+        // the program switched display modes, the window keeps its size and scales the new buffer to fit
+        if self.resolution != Some(buffer.resolution()) {
+            if let Err(e) =
+                locked_buffer.resize_buffer(buffer.width() as u32, buffer.height() as u32)
+            {
+                eprintln!("failed to resize pixel buffer in emulator: {}", e);
+                return;
+            }
+            self.resolution = Some(buffer.resolution());
+        }
+
+        buffer.to_rgba(locked_buffer.frame_mut(), &PALETTE);
+
+        if let Err(e) = locked_buffer.render() {
+            eprintln!("failed to render to pixel buffer in emulator: {}", e);
+        }
+    }
+}
+
+pub struct WinitInput {
+    key_event_rx: mpsc::Receiver<winit::event::KeyEvent>,
+}
+
+impl WinitInput {
+    pub fn new(key_event_rx: mpsc::Receiver<winit::event::KeyEvent>) -> Self {
+        Self { key_event_rx }
+    }
+}
+
+impl Input for WinitInput {
+    fn poll(&mut self) -> Option<KeyEvent> {
+        // skip over keys that aren't part of the keypad
+        while let Ok(event) = self.key_event_rx.try_recv() {
+            if let Some(key) = keypad_key(event.physical_key) {
+                return Some(KeyEvent {
+                    key,
+                    pressed: event.state == ElementState::Pressed,
+                });
+            }
+        }
+        None
+    }
+}
+
+// Chip8 keypad     QWERTY Keyboard mapping
+// 1 | 2 | 3 | C        1 | 2 | 3 | 4
+// 4 | 5 | 6 | D  <=>   Q | W | E | R
+// 7 | 8 | 9 | E  <=>   A | S | D | F
+// A | 0 | B | F        Z | X | C | V
+fn keypad_key(physical_key: PhysicalKey) -> Option<u8> {
+    let key = match physical_key {
+        PhysicalKey::Code(KeyCode::Digit1) => 0x1,
+        PhysicalKey::Code(KeyCode::Digit2) => 0x2,
+        PhysicalKey::Code(KeyCode::Digit3) => 0x3,
+        PhysicalKey::Code(KeyCode::Digit4) => 0xC,
+        PhysicalKey::Code(KeyCode::KeyQ) => 0x4,
+        PhysicalKey::Code(KeyCode::KeyW) => 0x5,
+        PhysicalKey::Code(KeyCode::KeyE) => 0x6,
+        PhysicalKey::Code(KeyCode::KeyR) => 0xD,
+        PhysicalKey::Code(KeyCode::KeyA) => 0x7,
+        PhysicalKey::Code(KeyCode::KeyS) => 0x8,
+        PhysicalKey::Code(KeyCode::KeyD) => 0x9,
+        PhysicalKey::Code(KeyCode::KeyF) => 0xE,
+        PhysicalKey::Code(KeyCode::KeyZ) => 0xA,
+        PhysicalKey::Code(KeyCode::KeyX) => 0x0,
+        PhysicalKey::Code(KeyCode::KeyC) => 0xB,
+        PhysicalKey::Code(KeyCode::KeyV) => 0xF,
+        _ => return None, // not a key on the keypad
+    };
+    Some(key)
+}
+
+// how many samples the buzzer source plays before checking what the emulator wants it to play
+const CONTROL_REFRESH_SAMPLES: u32 = 64;
+
+// what the emulator thread tells the source playing on the audio thread
+#[derive(Debug, Default)]
+struct BuzzerControl {
+    playing: bool,
+    pattern: Option<AudioPattern>,
+}
+
+pub struct RodioAudio {
+    // kept alive for as long as the emulator, dropping it stops the sound
+    _audio_sink: rodio::Sink,
+
+    control: Arc<Mutex<BuzzerControl>>,
+}
+
+impl RodioAudio {
+    // the buzzer source starts playing straight away and stays silent until the sound timer runs,
+    // so the sound starts and stops without waiting on the sink
+    pub fn new(audio_sink: rodio::Sink, beep: Beep, volume: f32) -> Self {
+        let control = Arc::new(Mutex::new(BuzzerControl::default()));
+        let buzzer = Buzzer::new(beep, volume, SAMPLE_RATE);
+        audio_sink.append(BuzzerSource::new(buzzer, control.clone()));
+        audio_sink.play();
+
+        Self {
+            _audio_sink: audio_sink,
+            control,
+        }
+    }
+}
+
+impl Audio for RodioAudio {
+    fn set_playing(&mut self, playing: bool) {
+        self.control.lock().unwrap().playing = playing;
+    }
+
+    fn set_pattern(&mut self, pattern: &AudioPattern) {
+        self.control.lock().unwrap().pattern = Some(*pattern);
+    }
+}
+
+// Endless rodio source rendering the buzzer
+struct BuzzerSource {
+    buzzer: Buzzer,
+
+    control: Arc<Mutex<BuzzerControl>>,
+
+    // the last state read from control
+    playing: bool,
+
+    // samples left until control is read again
+    samples_until_refresh: u32,
+}
+
+impl BuzzerSource {
+    fn new(buzzer: Buzzer, control: Arc<Mutex<BuzzerControl>>) -> Self {
+        Self {
+            buzzer,
+            control,
+            playing: false,
+            samples_until_refresh: 0,
+        }
+    }
+}
+
+impl Iterator for BuzzerSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.samples_until_refresh == 0 {
+            let control = self.control.lock().unwrap();
+            self.playing = control.playing;
+            if let Some(pattern) = control.pattern {
+                self.buzzer.set_pattern(pattern);
+            }
+            self.samples_until_refresh = CONTROL_REFRESH_SAMPLES;
+        }
+        self.samples_until_refresh -= 1;
+
+        Some(self.buzzer.next_sample(self.playing))
+    }
+}
+
+impl Source for BuzzerSource {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.buzzer.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// decode an audio file rodio understands (wav, mp3, ...) into a mono sample to use as the beep
+pub fn load_sample(path: &str) -> Result<Sample, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let decoder = Decoder::try_from(file).map_err(|e| e.to_string())?;
+
+    let channels = decoder.channels() as usize;
+    let sample_rate = decoder.sample_rate();
+    let interleaved: Vec<f32> = decoder.collect();
+    let samples = interleaved
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    Ok(Sample {
+        samples,
+        sample_rate,
+    })
+}
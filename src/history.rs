@@ -0,0 +1,115 @@
+use crate::rewind::{decode_delta, encode_delta};
+use std::collections::VecDeque;
+
+// The states a debugger went through, one per instruction, for stepping backwards. The last
+// `capacity` states pushed are kept.
+//
+// Deltas work the way they do in Rewind, the newest state is kept whole and each older one as
+// its XOR difference to the state after it. One instruction changes a handful of bytes, so a
+// state costs around twenty bytes and the deltas are packed into one buffer rather than
+// allocated one by one: millions of instructions fit in tens of MB.
+pub struct History {
+    capacity: usize,
+
+    newest: Option<Vec<u8>>,
+
+    // oldest first, back to back, each one turns the state after it into the one before
+    deltas: VecDeque<u8>,
+    delta_lens: VecDeque<u32>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            newest: None,
+            deltas: VecDeque::new(),
+            delta_lens: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // number of states in the history
+    pub fn len(&self) -> usize {
+        self.delta_lens.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.delta_lens.clear();
+    }
+
+    // the oldest state is dropped once there are more than capacity
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(previous) = self.newest.replace(state) {
+            let delta = encode_delta(&previous, self.newest.as_ref().unwrap());
+            self.delta_lens.push_back(delta.len() as u32);
+            self.deltas.extend(delta);
+        }
+        while self.len() > self.capacity {
+            let len = self.delta_lens.pop_front().unwrap_or_default();
+            self.deltas.drain(..len as usize);
+        }
+    }
+
+    // take the most recent state out of the history, None once it is used up
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        if let Some(len) = self.delta_lens.pop_back() {
+            let delta: Vec<u8> = self
+                .deltas
+                .drain(self.deltas.len() - len as usize..)
+                .collect();
+            self.newest = Some(decode_delta(&newest, &delta));
+        }
+        Some(newest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_newest_first() {
+        let mut history = History::new(3);
+        for i in 0..5u8 {
+            history.push(vec![i; 4 + i as usize]);
+        }
+        assert_eq!(history.len(), 3);
+        for i in (2..5u8).rev() {
+            assert_eq!(history.pop(), Some(vec![i; 4 + i as usize]));
+        }
+        assert_eq!(history.pop(), None);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn keeps_nothing_without_capacity() {
+        let mut history = History::new(0);
+        history.push(vec![1]);
+        assert!(history.is_empty());
+        assert_eq!(history.pop(), None);
+    }
+
+    #[test]
+    fn clear() {
+        let mut history = History::new(3);
+        history.push(vec![1]);
+        history.push(vec![2]);
+        history.clear();
+        assert_eq!(history.len(), 0);
+        assert_eq!(history.pop(), None);
+    }
+}
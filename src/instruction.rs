@@ -0,0 +1,454 @@
+use std::fmt;
+use std::str::FromStr;
+
+const LOW_4_BITS_MASK: u16 = 0x000F;
+const LOW_8_BITS_MASK: u16 = 0x00FF;
+const LOW_12_BITS_MASK: u16 = 0x0FFF;
+
+// A decoded Chip-8 instruction, including the SUPER-CHIP 1.1 and XO-CHIP extensions.
+//
+// x and y are always one of the 16 variable registers (V0 through VF),
+// n is a 4-bit number, nn an 8-bit immediate number and nnn a 12-bit immediate memory address.
+// nnnn is the 16-bit address that follows XO-CHIP's 4 byte F000 instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    // 00E0
+    ClearScreen,
+    // 00EE
+    Return,
+    // 00CN
+    ScrollDown { n: u8 },
+    // 00DN
+    ScrollUp { n: u8 },
+    // 00FB
+    ScrollRight,
+    // 00FC
+    ScrollLeft,
+    // 00FD
+    Exit,
+    // 00FE
+    LowResolution,
+    // 00FF
+    HighResolution,
+    // 1NNN
+    Jump { nnn: u16 },
+    // 2NNN
+    Call { nnn: u16 },
+    // 3XNN
+    SkipIfEqual { x: u8, nn: u8 },
+    // 4XNN
+    SkipIfNotEqual { x: u8, nn: u8 },
+    // 5XY0
+    SkipIfRegistersEqual { x: u8, y: u8 },
+    // 5XY2
+    StoreRegisterRange { x: u8, y: u8 },
+    // 5XY3
+    LoadRegisterRange { x: u8, y: u8 },
+    // 6XNN
+    SetRegister { x: u8, nn: u8 },
+    // 7XNN
+    AddToRegister { x: u8, nn: u8 },
+    // 8XY0
+    CopyRegister { x: u8, y: u8 },
+    // 8XY1
+    Or { x: u8, y: u8 },
+    // 8XY2
+    And { x: u8, y: u8 },
+    // 8XY3
+    Xor { x: u8, y: u8 },
+    // 8XY4
+    Add { x: u8, y: u8 },
+    // 8XY5
+    Subtract { x: u8, y: u8 },
+    // 8XY6
+    ShiftRight { x: u8, y: u8 },
+    // 8XY7
+    SubtractReversed { x: u8, y: u8 },
+    // 8XYE
+    ShiftLeft { x: u8, y: u8 },
+    // 9XY0
+    SkipIfRegistersNotEqual { x: u8, y: u8 },
+    // ANNN
+    SetIndex { nnn: u16 },
+    // BNNN
+    JumpWithOffset { x: u8, nnn: u16 },
+    // CXNN
+    Random { x: u8, nn: u8 },
+    // DXYN
+    Draw { x: u8, y: u8, n: u8 },
+    // EX9E
+    SkipIfKeyPressed { x: u8 },
+    // EXA1
+    SkipIfKeyNotPressed { x: u8 },
+    // F000 NNNN
+    SetIndexLong { nnnn: u16 },
+    // FN01
+    SelectPlanes { n: u8 },
+    // F002
+    LoadAudioPattern,
+    // FX07
+    GetDelayTimer { x: u8 },
+    // FX0A
+    WaitForKey { x: u8 },
+    // FX15
+    SetDelayTimer { x: u8 },
+    // FX18
+    SetSoundTimer { x: u8 },
+    // FX1E
+    AddToIndex { x: u8 },
+    // FX29
+    FontCharacter { x: u8 },
+    // FX30
+    BigFontCharacter { x: u8 },
+    // FX33
+    BinaryCodedDecimal { x: u8 },
+    // FX3A
+    SetPitch { x: u8 },
+    // FX55
+    StoreRegisters { x: u8 },
+    // FX65
+    LoadRegisters { x: u8 },
+    // FX75
+    StoreFlags { x: u8 },
+    // FX85
+    LoadFlags { x: u8 },
+    // anything else, holds the raw opcode
+    Unknown(u16),
+}
+
+// decode the instruction starting with opcode.
+// next_word is the two bytes after it, only used by the 4 byte F000 NNNN instruction
+pub fn decode(opcode: u16, next_word: u16) -> Instruction {
+    // first nibble that tells you what kind of instruction it is
+    let first_nibble: u16 = (opcode >> 12) & LOW_4_BITS_MASK;
+
+    // The second nibble. Used to look up one of the 16 registers (VX) from V0 through VF
+    let x = ((opcode >> 8) & LOW_4_BITS_MASK) as u8;
+
+    // The third nibble. Also used to look up one of the 16 registers (VY) from V0 through VF.
+    let y = ((opcode >> 4) & LOW_4_BITS_MASK) as u8;
+
+    // The fourth nibble. A 4-bit number.
+    let n = (opcode & LOW_4_BITS_MASK) as u8;
+
+    // The second byte (third and fourth nibbles). An 8-bit immediate number.
+    let nn = (opcode & LOW_8_BITS_MASK) as u8;
+
+    // The second, third and fourth nibbles. A 12-bit immediate memory address.
+    let nnn: u16 = opcode & LOW_12_BITS_MASK;
+
+    match (first_nibble, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => Instruction::ClearScreen,
+        (0x0, 0x0, 0xE, 0xE) => Instruction::Return,
+        (0x0, 0x0, 0xC, _) => Instruction::ScrollDown { n },
+        (0x0, 0x0, 0xD, _) => Instruction::ScrollUp { n },
+        (0x0, 0x0, 0xF, 0xB) => Instruction::ScrollRight,
+        (0x0, 0x0, 0xF, 0xC) => Instruction::ScrollLeft,
+        (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+        (0x0, 0x0, 0xF, 0xE) => Instruction::LowResolution,
+        (0x0, 0x0, 0xF, 0xF) => Instruction::HighResolution,
+        (0x1, _, _, _) => Instruction::Jump { nnn },
+        (0x2, _, _, _) => Instruction::Call { nnn },
+        (0x3, _, _, _) => Instruction::SkipIfEqual { x, nn },
+        (0x4, _, _, _) => Instruction::SkipIfNotEqual { x, nn },
+        (0x5, _, _, 0x0) => Instruction::SkipIfRegistersEqual { x, y },
+        (0x5, _, _, 0x2) => Instruction::StoreRegisterRange { x, y },
+        (0x5, _, _, 0x3) => Instruction::LoadRegisterRange { x, y },
+        (0x6, _, _, _) => Instruction::SetRegister { x, nn },
+        (0x7, _, _, _) => Instruction::AddToRegister { x, nn },
+        (0x8, _, _, 0x0) => Instruction::CopyRegister { x, y },
+        (0x8, _, _, 0x1) => Instruction::Or { x, y },
+        (0x8, _, _, 0x2) => Instruction::And { x, y },
+        (0x8, _, _, 0x3) => Instruction::Xor { x, y },
+        (0x8, _, _, 0x4) => Instruction::Add { x, y },
+        (0x8, _, _, 0x5) => Instruction::Subtract { x, y },
+        (0x8, _, _, 0x6) => Instruction::ShiftRight { x, y },
+        (0x8, _, _, 0x7) => Instruction::SubtractReversed { x, y },
+        (0x8, _, _, 0xE) => Instruction::ShiftLeft { x, y },
+        (0x9, _, _, 0x0) => Instruction::SkipIfRegistersNotEqual { x, y },
+        (0xA, _, _, _) => Instruction::SetIndex { nnn },
+        (0xB, _, _, _) => Instruction::JumpWithOffset { x, nnn },
+        (0xC, _, _, _) => Instruction::Random { x, nn },
+        (0xD, _, _, _) => Instruction::Draw { x, y, n },
+        (0xE, _, 0x9, 0xE) => Instruction::SkipIfKeyPressed { x },
+        (0xE, _, 0xA, 0x1) => Instruction::SkipIfKeyNotPressed { x },
+        (0xF, 0x0, 0x0, 0x0) => Instruction::SetIndexLong { nnnn: next_word },
+        (0xF, _, 0x0, 0x1) => Instruction::SelectPlanes { n: x },
+        (0xF, 0x0, 0x0, 0x2) => Instruction::LoadAudioPattern,
+        (0xF, _, 0x0, 0x7) => Instruction::GetDelayTimer { x },
+        (0xF, _, 0x0, 0xA) => Instruction::WaitForKey { x },
+        (0xF, _, 0x1, 0x5) => Instruction::SetDelayTimer { x },
+        (0xF, _, 0x1, 0x8) => Instruction::SetSoundTimer { x },
+        (0xF, _, 0x1, 0xE) => Instruction::AddToIndex { x },
+        (0xF, _, 0x2, 0x9) => Instruction::FontCharacter { x },
+        (0xF, _, 0x3, 0x0) => Instruction::BigFontCharacter { x },
+        (0xF, _, 0x3, 0x3) => Instruction::BinaryCodedDecimal { x },
+        (0xF, _, 0x3, 0xA) => Instruction::SetPitch { x },
+        (0xF, _, 0x5, 0x5) => Instruction::StoreRegisters { x },
+        (0xF, _, 0x6, 0x5) => Instruction::LoadRegisters { x },
+        (0xF, _, 0x7, 0x5) => Instruction::StoreFlags { x },
+        (0xF, _, 0x8, 0x5) => Instruction::LoadFlags { x },
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+impl Instruction {
+    // how many bytes the instruction takes up in memory
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::SetIndexLong { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    // encode the instruction back into its 16-bit opcode,
+    // for F000 NNNN that is only the first half
+    pub fn opcode(&self) -> u16 {
+        let xy = |high: u16, x: u8, y: u8, low: u16| {
+            high << 12 | (x as u16) << 8 | (y as u16) << 4 | low
+        };
+        let xnn = |high: u16, x: u8, nn: u8| high << 12 | (x as u16) << 8 | nn as u16;
+        let fx = |x: u8, low: u16| 0xF000 | (x as u16) << 8 | low;
+
+        match *self {
+            Instruction::ClearScreen => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollDown { n } => 0x00C0 | n as u16,
+            Instruction::ScrollUp { n } => 0x00D0 | n as u16,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowResolution => 0x00FE,
+            Instruction::HighResolution => 0x00FF,
+            Instruction::Jump { nnn } => 0x1000 | nnn,
+            Instruction::Call { nnn } => 0x2000 | nnn,
+            Instruction::SkipIfEqual { x, nn } => xnn(0x3, x, nn),
+            Instruction::SkipIfNotEqual { x, nn } => xnn(0x4, x, nn),
+            Instruction::SkipIfRegistersEqual { x, y } => xy(0x5, x, y, 0x0),
+            Instruction::StoreRegisterRange { x, y } => xy(0x5, x, y, 0x2),
+            Instruction::LoadRegisterRange { x, y } => xy(0x5, x, y, 0x3),
+            Instruction::SetRegister { x, nn } => xnn(0x6, x, nn),
+            Instruction::AddToRegister { x, nn } => xnn(0x7, x, nn),
+            Instruction::CopyRegister { x, y } => xy(0x8, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8, x, y, 0x3),
+            Instruction::Add { x, y } => xy(0x8, x, y, 0x4),
+            Instruction::Subtract { x, y } => xy(0x8, x, y, 0x5),
+            Instruction::ShiftRight { x, y } => xy(0x8, x, y, 0x6),
+            Instruction::SubtractReversed { x, y } => xy(0x8, x, y, 0x7),
+            Instruction::ShiftLeft { x, y } => xy(0x8, x, y, 0xE),
+            Instruction::SkipIfRegistersNotEqual { x, y } => xy(0x9, x, y, 0x0),
+            Instruction::SetIndex { nnn } => 0xA000 | nnn,
+            Instruction::JumpWithOffset { nnn, .. } => 0xB000 | nnn,
+            Instruction::Random { x, nn } => xnn(0xC, x, nn),
+            Instruction::Draw { x, y, n } => xy(0xD, x, y, n as u16),
+            Instruction::SkipIfKeyPressed { x } => xnn(0xE, x, 0x9E),
+            Instruction::SkipIfKeyNotPressed { x } => xnn(0xE, x, 0xA1),
+            Instruction::SetIndexLong { .. } => 0xF000,
+            Instruction::SelectPlanes { n } => fx(n, 0x01),
+            Instruction::LoadAudioPattern => 0xF002,
+            Instruction::GetDelayTimer { x } => fx(x, 0x07),
+            Instruction::WaitForKey { x } => fx(x, 0x0A),
+            Instruction::SetDelayTimer { x } => fx(x, 0x15),
+            Instruction::SetSoundTimer { x } => fx(x, 0x18),
+            Instruction::AddToIndex { x } => fx(x, 0x1E),
+            Instruction::FontCharacter { x } => fx(x, 0x29),
+            Instruction::BigFontCharacter { x } => fx(x, 0x30),
+            Instruction::BinaryCodedDecimal { x } => fx(x, 0x33),
+            Instruction::SetPitch { x } => fx(x, 0x3A),
+            Instruction::StoreRegisters { x } => fx(x, 0x55),
+            Instruction::LoadRegisters { x } => fx(x, 0x65),
+            Instruction::StoreFlags { x } => fx(x, 0x75),
+            Instruction::LoadFlags { x } => fx(x, 0x85),
+            Instruction::Unknown(opcode) => opcode,
+        }
+    }
+}
+
+// Formats instructions with the common Chip-8 assembly mnemonics, e.g "DRW V0, V1, 0x5"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::ScrollDown { n } => write!(f, "SCD 0x{:X}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU 0x{:X}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::LowResolution => write!(f, "LOW"),
+            Instruction::HighResolution => write!(f, "HIGH"),
+            Instruction::Jump { nnn } => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call { nnn } => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SkipIfEqual { x, nn } => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipIfNotEqual { x, nn } => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Instruction::SkipIfRegistersEqual { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::StoreRegisterRange { x, y } => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LoadRegisterRange { x, y } => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Instruction::SetRegister { x, nn } => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Instruction::AddToRegister { x, nn } => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Instruction::CopyRegister { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Subtract { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubtractReversed { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipIfRegistersNotEqual { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetIndex { nnn } => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JumpWithOffset { x, nnn } => write!(f, "JP V{:X}, 0x{:03X}", x, nnn),
+            Instruction::Random { x, nn } => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, 0x{:X}", x, y, n),
+            Instruction::SkipIfKeyPressed { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipIfKeyNotPressed { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::SetIndexLong { nnnn } => write!(f, "LD I, 0x{:04X}", nnnn),
+            Instruction::SelectPlanes { n } => write!(f, "PLANE 0x{:X}", n),
+            Instruction::LoadAudioPattern => write!(f, "LD AUDIO, [I]"),
+            Instruction::GetDelayTimer { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitForKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelayTimer { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSoundTimer { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddToIndex { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::FontCharacter { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::BigFontCharacter { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::BinaryCodedDecimal { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::SetPitch { x } => write!(f, "LD PITCH, V{:X}", x),
+            Instruction::StoreRegisters { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegisters { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+            Instruction::Unknown(opcode) => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+// An opcode with wildcards, written the way instructions are documented, e.g "Dxyn" matches
+// every draw and "Fx0A" every wait for a key. Hex digits have to match, the placeholders
+// x, y, n and k match anything. F000 NNNN is matched on its first two bytes only
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OpcodePattern {
+    // the bits that have to match, and what they have to be
+    mask: u16,
+    value: u16,
+
+    // as written, with the hex digits in upper case
+    text: [char; 4],
+}
+
+impl OpcodePattern {
+    pub fn matches(&self, instruction: &Instruction) -> bool {
+        instruction.opcode() & self.mask == self.value
+    }
+}
+
+impl FromStr for OpcodePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.chars().count() != 4 {
+            return Err(format!("expected 4 hex digits or placeholders: {}", s));
+        }
+
+        let mut pattern = OpcodePattern {
+            mask: 0,
+            value: 0,
+            text: [' '; 4],
+        };
+        for (idx, c) in s.chars().enumerate() {
+            pattern.mask <<= 4;
+            pattern.value <<= 4;
+            pattern.text[idx] = match c.to_ascii_lowercase() {
+                placeholder @ ('x' | 'y' | 'n' | 'k') => placeholder,
+                c => {
+                    let digit = c
+                        .to_digit(16)
+                        .ok_or_else(|| format!("unexpected {} in opcode pattern {}", c, s))?;
+                    pattern.mask |= LOW_4_BITS_MASK;
+                    pattern.value |= digit as u16;
+                    c.to_ascii_uppercase()
+                }
+            };
+        }
+        Ok(pattern)
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text.iter().collect::<String>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_opcodes() {
+        assert_eq!(decode(0x00E0, 0), Instruction::ClearScreen);
+        assert_eq!(decode(0x00C5, 0), Instruction::ScrollDown { n: 5 });
+        assert_eq!(decode(0x1234, 0), Instruction::Jump { nnn: 0x234 });
+        assert_eq!(
+            decode(0x3A42, 0),
+            Instruction::SkipIfEqual { x: 0xA, nn: 0x42 }
+        );
+        assert_eq!(
+            decode(0x8AB6, 0),
+            Instruction::ShiftRight { x: 0xA, y: 0xB }
+        );
+        assert_eq!(
+            decode(0xB123, 0),
+            Instruction::JumpWithOffset { x: 1, nnn: 0x123 }
+        );
+        assert_eq!(decode(0xD12F, 0), Instruction::Draw { x: 1, y: 2, n: 0xF });
+        assert_eq!(decode(0xF30A, 0), Instruction::WaitForKey { x: 3 });
+        assert_eq!(decode(0xF201, 0), Instruction::SelectPlanes { n: 2 });
+        assert_eq!(
+            decode(0xF000, 0xBEEF),
+            Instruction::SetIndexLong { nnnn: 0xBEEF }
+        );
+        assert_eq!(decode(0x5121, 0), Instruction::Unknown(0x5121));
+        assert_eq!(decode(0xE1FF, 0), Instruction::Unknown(0xE1FF));
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(decode(0xF000, 0).size(), 4);
+        assert_eq!(decode(0xF002, 0).size(), 2);
+        assert_eq!(decode(0x00E0, 0).size(), 2);
+    }
+
+    #[test]
+    fn opcode_round_trip() {
+        for opcode in 0..=0xFFFF {
+            assert_eq!(decode(opcode, 0).opcode(), opcode, "{:04X}", opcode);
+        }
+    }
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(decode(0xD125, 0).to_string(), "DRW V1, V2, 0x5");
+        assert_eq!(decode(0xA2F0, 0).to_string(), "LD I, 0x2F0");
+        assert_eq!(decode(0xF000, 0x1234).to_string(), "LD I, 0x1234");
+        assert_eq!(decode(0x0123, 0).to_string(), "DW 0x0123");
+    }
+
+    #[test]
+    fn opcode_patterns() {
+        let draw: OpcodePattern = "Dxyn".parse().unwrap();
+        assert!(draw.matches(&decode(0xD125, 0)));
+        assert!(!draw.matches(&decode(0xC125, 0)));
+        assert_eq!(draw.to_string(), "Dxyn");
+
+        let wait: OpcodePattern = "fx0a".parse().unwrap();
+        assert!(wait.matches(&decode(0xF30A, 0)));
+        assert!(!wait.matches(&decode(0xF307, 0)));
+        assert_eq!(wait.to_string(), "Fx0A");
+
+        assert!("Dxy".parse::<OpcodePattern>().is_err());
+        assert!("Gxyn".parse::<OpcodePattern>().is_err());
+    }
+}
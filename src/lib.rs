@@ -0,0 +1,30 @@
+// Chip-8 emulator core, usable from any frontend. The desktop binary in main.rs is one user of it,
+// it wires the emulator up to a winit window through the backends in `frontend`, which are behind
+// the default frontend feature so the core can be used without pixels, winit and rodio.
+
+pub mod audio;
+pub mod backend;
+pub mod chip8;
+pub mod debugger;
+pub mod display;
+pub mod error;
+pub mod expression;
+#[cfg(feature = "frontend")]
+pub mod frontend;
+pub mod history;
+pub mod instruction;
+pub mod quirks;
+pub mod random;
+pub mod replay;
+pub mod rewind;
+pub mod scheduler;
+pub mod state;
+pub mod wav;
+
+pub use chip8::{Emulator, EmulatorBuilder};
+pub use error::{EmulatorError, ReplayError, StateError};
+pub use instruction::{Instruction, decode};
+pub use quirks::{Platform, Quirks};
+pub use random::RandomMode;
+pub use scheduler::Speed;
+pub use state::Snapshot;
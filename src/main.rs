@@ -1,86 +1,578 @@
 mod app;
-mod chip8;
+mod dap;
+mod repl;
+mod runner;
+mod tas;
+mod tui;
 
+use chip8_rust::audio::{self, Beep, Buzzer, Waveform};
+use chip8_rust::backend::{Audio, Input, NullAudio, NullInput};
+use chip8_rust::debugger::{DEFAULT_HISTORY, Debugger};
+use chip8_rust::display::Resolution;
+use chip8_rust::quirks::IndexIncrement;
+use chip8_rust::random::VIP_INTERPRETER_SIZE;
+use chip8_rust::replay::{InputRecorder, Movie, Replay, ReplayHeader, ReplayInput};
+use chip8_rust::rewind::Rewind;
+use chip8_rust::scheduler::MAX_CYCLES;
+use chip8_rust::wav::WavRecorder;
+use chip8_rust::{
+    Emulator, EmulatorBuilder, EmulatorError, Platform, Quirks, RandomMode, Speed, chip8, frontend,
+};
 use clap::Parser;
+use clap::builder::{PossibleValuesParser, TypedValueParser};
 use rodio::OutputStreamBuilder;
 use std::fs;
-use std::sync::mpsc;
+use std::process;
+use std::sync::{Arc, mpsc};
 use std::thread;
 use winit::event_loop::{ControlFlow, EventLoop};
 
 const EMULATOR_TITLE: &str = "Chip-8";
 
-static BEEP_SOUND_DATA: &[u8] = include_bytes!("../assets/beep_short.mp3");
+const DEFAULT_VOLUME: f32 = 0.25;
 
 /// A Chip-8 Emulator
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Path to the Chip-8 ROM
-    #[arg(long, required = true)]
-    rom: String,
+    #[arg(long, required_unless_present = "dap")]
+    rom: Option<String>,
 
-    /// Original behaviour of the shift instruction (default: false)
-    #[arg(long, default_value_t = false)]
-    shift_instruction_original: bool,
+    /// Platform whose quirks to emulate, individual quirks can be overridden below
+    #[arg(long, default_value_t = Platform::Legacy, value_parser = platform_parser())]
+    platform: Platform,
+
+    /// Override: 8XY6/8XYE shift VY into VX instead of shifting VX in place
+    #[arg(long, value_name = "BOOL")]
+    shift_quirk: Option<bool>,
+
+    /// Override: BNNN jumps to XNN plus VX instead of NNN plus V0
+    #[arg(long, value_name = "BOOL")]
+    jump_quirk: Option<bool>,
+
+    /// Override: how much FX55/FX65 increment the index register
+    #[arg(long, value_parser = index_increment_parser())]
+    index_increment_quirk: Option<IndexIncrement>,
+
+    /// Override: 8XY1/8XY2/8XY3 reset VF to 0
+    #[arg(long, value_name = "BOOL")]
+    vf_reset_quirk: Option<bool>,
+
+    /// Override: sprites are clipped at the screen edges instead of wrapping around
+    #[arg(long, value_name = "BOOL")]
+    clip_quirk: Option<bool>,
+
+    /// Override: DXYN waits for the next frame before drawing again
+    #[arg(long, value_name = "BOOL")]
+    display_wait_quirk: Option<bool>,
+
+    /// Override: FX0A waits for the key to be released instead of returning on press
+    #[arg(long, value_name = "BOOL")]
+    key_release_quirk: Option<bool>,
+
+    /// Override: in hires mode DXYN sets VF to the number of rows that collided
+    #[arg(long, value_name = "BOOL")]
+    collision_count_quirk: Option<bool>,
+
+    /// Seed for the random numbers of CXNN, the same seed and input give the same run (default: random)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// How CXNN generates random numbers, cosmac-vip follows the VIP interpreter's scheme
+    #[arg(long, default_value_t = RandomMode::Standard, value_parser = random_mode_parser())]
+    random: RandomMode,
 
-    /// Original behaviour of jump with offset instruction (default: false)
+    /// Dump of the COSMAC VIP's 512 byte CHIP-8 interpreter, which --random cosmac-vip reads its numbers from
+    #[arg(long, value_name = "FILE", required_if_eq("random", "cosmac-vip"))]
+    vip_interpreter: Option<String>,
+
+    /// Instructions to execute per second, spread evenly over each frame (default: 700)
+    #[arg(long, conflicts_with = "cycles_per_frame", value_parser = clap::value_parser!(u32).range(1..=MAX_CYCLES as i64))]
+    cycles_per_second: Option<u32>,
+
+    /// Instructions to execute per frame, the way Octo sets its speed
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=MAX_CYCLES as i64))]
+    cycles_per_frame: Option<u32>,
+
+    /// Frames per second, the timers tick and the screen refreshes once per frame
+    #[arg(long, default_value_t = chip8::FRAME_RATE, value_parser = clap::value_parser!(u32).range(1..))]
+    frame_rate: u32,
+
+    /// Shape of the beep
+    #[arg(long, default_value_t = Waveform::Square, value_parser = waveform_parser())]
+    waveform: Waveform,
+
+    /// Pitch of the beep in Hz
+    #[arg(long, default_value_t = Beep::DEFAULT_FREQUENCY, value_parser = frequency_parser)]
+    frequency: f32,
+
+    /// Volume of the beep and XO-CHIP audio, from 0.0 to 1.0
+    #[arg(long, default_value_t = DEFAULT_VOLUME, value_parser = volume_parser)]
+    volume: f32,
+
+    /// Loop an audio file (wav, mp3, ...) as the beep instead of a synthesized tone
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["waveform", "frequency"])]
+    beep_sample: Option<String>,
+
+    /// Run without sound, the sound timer still counts down (default: false)
     #[arg(long, default_value_t = false)]
-    jump_with_offset_original: bool,
+    no_audio: bool,
+
+    /// Write everything the emulator plays to a WAV file, frame by frame, also with --no-audio or --headless
+    #[arg(long, value_name = "FILE")]
+    record_audio: Option<String>,
+
+    /// Seconds of history kept for rewinding with Backspace, 0 turns rewinding off
+    #[arg(long, default_value_t = 60)]
+    rewind_seconds: u32,
 
-    /// Original behaviour of store and load instruction (default: false)
+    /// Frames between the snapshots kept for rewinding
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    rewind_interval: u32,
+
+    /// Start from a save state, e.g one saved with F5 next to the rom as <ROM>.state1
+    #[arg(long, value_name = "FILE")]
+    load_state: Option<String>,
+
+    /// Record every keypad change with its frame number, to reproduce the run with --replay
+    #[arg(long, value_name = "FILE", conflicts_with = "load_state")]
+    record_input: Option<String>,
+
+    /// Play back an input recording made with the same rom, using its quirks, speed and seed
+    #[arg(long, value_name = "FILE", conflicts_with = "load_state")]
+    replay: Option<String>,
+
+    /// Tool-assisted mode: start paused, Space advances one frame, keypad keys toggle, the movie goes to --record-input
+    #[arg(long, default_value_t = false, conflicts_with_all = ["headless", "load_state"])]
+    tas: bool,
+
+    /// Stop before the first instruction in a debugger on the terminal, type help for its commands
+    #[arg(long, default_value_t = false, conflicts_with = "tas")]
+    debug: bool,
+
+    /// Like --debug, but a full-screen terminal debugger with disassembly, registers, memory and the screen
+    #[arg(long, default_value_t = false, conflicts_with_all = ["tas", "debug"])]
+    tui: bool,
+
+    /// Serve the Debug Adapter Protocol on stdin and stdout, or on this port on localhost, the rom comes with the launch request
+    #[arg(
+        long,
+        value_name = "PORT",
+        num_args = 0..=1,
+        conflicts_with_all = ["tas", "debug", "tui", "load_state", "record_input", "replay"]
+    )]
+    dap: Option<Option<u16>>,
+
+    /// Instructions --debug, --tui and --dap keep a history of, for stepping backwards. 0 turns it off
+    #[arg(long, value_name = "INSTRUCTIONS", default_value_t = DEFAULT_HISTORY)]
+    history: usize,
+
+    /// Run without a window, keyboard or audio device (default: false)
     #[arg(long, default_value_t = false)]
-    store_and_load_original: bool,
+    headless: bool,
+}
+
+impl Args {
+    fn quirks(&self) -> Quirks {
+        let mut quirks = Quirks::for_platform(self.platform);
+        if let Some(shift_uses_vy) = self.shift_quirk {
+            quirks.shift_uses_vy = shift_uses_vy;
+        }
+        if let Some(jump_uses_vx) = self.jump_quirk {
+            quirks.jump_uses_vx = jump_uses_vx;
+        }
+        if let Some(index_increment) = self.index_increment_quirk {
+            quirks.index_increment = index_increment;
+        }
+        if let Some(vf_reset) = self.vf_reset_quirk {
+            quirks.vf_reset = vf_reset;
+        }
+        if let Some(clip_sprites) = self.clip_quirk {
+            quirks.clip_sprites = clip_sprites;
+        }
+        if let Some(display_wait) = self.display_wait_quirk {
+            quirks.display_wait = display_wait;
+        }
+        if let Some(key_wait_release) = self.key_release_quirk {
+            quirks.key_wait_release = key_wait_release;
+        }
+        if let Some(collision_row_count) = self.collision_count_quirk {
+            quirks.collision_row_count = collision_row_count;
+        }
+        quirks
+    }
+
+    fn beep(&self) -> Beep {
+        match &self.beep_sample {
+            Some(path) => {
+                let sample = frontend::load_sample(path).unwrap_or_else(|e| {
+                    eprintln!("failed to load beep sample {}: {}", path, e);
+                    process::exit(1);
+                });
+                Beep::Sample(Arc::new(sample))
+            }
+            None => Beep::Tone {
+                waveform: self.waveform,
+                frequency: self.frequency,
+            },
+        }
+    }
+
+    // wraps the audio backend in a recorder when --record-audio is given
+    fn audio(&self, beep: Beep, audio: Box<dyn Audio>) -> Box<dyn Audio> {
+        let Some(path) = &self.record_audio else {
+            return audio;
+        };
+
+        let buzzer = Buzzer::new(beep, self.volume, audio::SAMPLE_RATE);
+        match WavRecorder::create(path, buzzer, self.frame_rate, audio) {
+            Ok(recorder) => Box::new(recorder),
+            Err(e) => {
+                eprintln!("failed to create audio recording {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    fn rewind(&self) -> Rewind {
+        let snapshots = self.rewind_seconds.saturating_mul(self.frame_rate) / self.rewind_interval;
+        Rewind::new(snapshots as usize, self.rewind_interval)
+    }
+
+    // everything but the backends, a replay brings its own settings
+    fn builder(&self, rom: Vec<u8>, replay: Option<&ReplayHeader>) -> EmulatorBuilder {
+        let mut builder = Emulator::builder()
+            .rom(rom)
+            .speed(self.speed())
+            .frame_rate(self.frame_rate)
+            .platform(self.platform)
+            .quirks(self.quirks());
+        if let Some(seed) = self.seed {
+            builder = builder.seed(seed);
+        }
+        if let Some(header) = replay {
+            return header.configure(builder);
+        }
+        match self.vip_interpreter() {
+            Some(interpreter) => builder.vip_random(interpreter),
+            None => builder,
+        }
+    }
+
+    // plays back --replay, then the keyboard, and records all of it with --record-input
+    fn input(&self, rom: &[u8], replay: Option<Replay>, input: Box<dyn Input>) -> Box<dyn Input> {
+        let header = self.replay_header(rom, replay.as_ref());
+        let input: Box<dyn Input> = match replay {
+            Some(replay) => Box::new(ReplayInput::new(replay, input)),
+            None => input,
+        };
+        let Some(path) = &self.record_input else {
+            return input;
+        };
+
+        match InputRecorder::create(path, &header, input) {
+            Ok(recorder) => Box::new(recorder),
+            Err(e) => {
+                eprintln!("failed to create input recording {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // the keyboard edits a movie, which starts out as --replay if it was given
+    fn tas(&self, rom: &[u8], replay: Option<Replay>, keyboard: Box<dyn Input>) -> tas::Tas {
+        let movie = match replay {
+            Some(replay) => Movie::from_replay(&replay),
+            None => Movie::new(self.replay_header(rom, None)),
+        };
+        tas::Tas::new(movie, self.record_input.clone(), keyboard)
+    }
+
+    // the settings of the run as they go into a recording, those of the replay if there is one
+    fn replay_header(&self, rom: &[u8], replay: Option<&Replay>) -> ReplayHeader {
+        match replay {
+            Some(replay) => replay.header.clone(),
+            None => ReplayHeader::new(
+                rom,
+                self.platform,
+                self.quirks(),
+                self.speed(),
+                self.frame_rate,
+                self.seed.expect("the seed is picked before recording"),
+                self.vip_interpreter(),
+            ),
+        }
+    }
+
+    // the interpreter dump for --random cosmac-vip, None for the standard generator
+    fn vip_interpreter(&self) -> Option<Vec<u8>> {
+        if self.random != RandomMode::CosmacVip {
+            return None;
+        }
+        let path = self
+            .vip_interpreter
+            .as_deref()
+            .expect("clap requires --vip-interpreter");
+        match fs::read(path) {
+            Ok(interpreter) if interpreter.len() == VIP_INTERPRETER_SIZE => Some(interpreter),
+            Ok(interpreter) => {
+                eprintln!(
+                    "{} is {} bytes, expected a {} byte dump of the COSMAC VIP interpreter",
+                    path,
+                    interpreter.len(),
+                    VIP_INTERPRETER_SIZE
+                );
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("failed to read the COSMAC VIP interpreter {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    fn speed(&self) -> Speed {
+        match (self.cycles_per_second, self.cycles_per_frame) {
+            (_, Some(cycles)) => Speed::CyclesPerFrame(cycles),
+            (Some(cycles), None) => Speed::CyclesPerSecond(cycles),
+            (None, None) => Speed::CyclesPerSecond(chip8::DEFAULT_CYCLE_RATE),
+        }
+    }
+}
+
+fn waveform_parser() -> impl TypedValueParser<Value = Waveform> {
+    PossibleValuesParser::new(Waveform::ALL.map(Waveform::name))
+        .map(|name| name.parse::<Waveform>().unwrap())
+}
+
+fn frequency_parser(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(frequency) if frequency > 0.0 => Ok(frequency),
+        _ => Err("expected a frequency above 0".to_string()),
+    }
+}
+
+fn volume_parser(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(volume) if (0.0..=1.0).contains(&volume) => Ok(volume),
+        _ => Err("expected a volume from 0.0 to 1.0".to_string()),
+    }
+}
+
+fn random_mode_parser() -> impl TypedValueParser<Value = RandomMode> {
+    PossibleValuesParser::new(RandomMode::ALL.map(RandomMode::name))
+        .map(|name| name.parse::<RandomMode>().unwrap())
+}
+
+fn platform_parser() -> impl TypedValueParser<Value = Platform> {
+    PossibleValuesParser::new(Platform::ALL.map(Platform::name))
+        .map(|name| name.parse::<Platform>().unwrap())
+}
+
+fn index_increment_parser() -> impl TypedValueParser<Value = IndexIncrement> {
+    PossibleValuesParser::new(IndexIncrement::ALL.map(IndexIncrement::name))
+        .map(|name| name.parse::<IndexIncrement>().unwrap())
 }
 
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+
+    // pick the seed up front, so it can go into an input recording
+    args.seed.get_or_insert_with(rand::random);
+
+    // under --dap the rom comes later, with the launch request
+    let rom = args.rom.as_deref().map(read_rom).unwrap_or_default();
+    let replay = args.replay.as_deref().map(|path| read_replay(path, &rom));
+    if let Some(replay) = &replay {
+        args.frame_rate = replay.header.frame_rate;
+    }
+
+    if args.headless {
+        run_headless(args, rom, replay);
+        return;
+    }
 
-    // default output stream
-    let audio_output =
-        OutputStreamBuilder::open_default_stream().expect("open default audio stream");
+    // default output stream, machines without an audio device (CI, SSH sessions) run silently
+    let audio_output = if args.no_audio {
+        None
+    } else {
+        OutputStreamBuilder::open_default_stream()
+            .inspect_err(|e| eprintln!("failed to open audio device, running without sound: {}", e))
+            .ok()
+    };
 
     let (key_event_tx, key_event_rx) = mpsc::channel();
-    let (frame_buffer_tx, frame_buffer_rx): (
-        mpsc::Sender<std::sync::Arc<std::sync::Mutex<pixels::Pixels<'static>>>>,
-        mpsc::Receiver<std::sync::Arc<std::sync::Mutex<pixels::Pixels<'static>>>>,
-    ) = mpsc::channel();
+    let (hotkey_tx, hotkey_rx) = mpsc::channel();
+    let (frame_buffer_tx, frame_buffer_rx) = mpsc::channel();
 
     let mut app = app::App::new(
-        chip8::DISPLAY_WIDTH.into(),
-        chip8::DISPLAY_HEIGHT.into(),
+        Resolution::Low.width() as u32,
+        Resolution::Low.height() as u32,
         EMULATOR_TITLE.to_string(),
         key_event_tx,
+        hotkey_tx,
         frame_buffer_tx,
     );
 
-    let event_loop = EventLoop::new().unwrap();
+    let event_loop = EventLoop::<app::UserEvent>::with_user_event()
+        .build()
+        .unwrap();
+    let event_loop_proxy = event_loop.create_proxy();
 
     // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't
     // dispatched any events. This is ideal for games and similar applications.
     event_loop.set_control_flow(ControlFlow::Poll);
 
+    let beep = args.beep();
+
     thread::spawn(move || {
-        let rom: Vec<u8> = fs::read(args.rom).unwrap();
+        let frame_buffer = frame_buffer_rx.recv().unwrap();
 
-        let audio_sink = rodio::Sink::connect_new(&audio_output.mixer());
-        let beep_data: Vec<u8> = BEEP_SOUND_DATA.to_vec();
+        // without an output stream the emulator stays silent
+        let speaker: Box<dyn Audio> = match &audio_output {
+            Some(audio_output) => {
+                let audio_sink = rodio::Sink::connect_new(audio_output.mixer());
+                Box::new(frontend::RodioAudio::new(
+                    audio_sink,
+                    beep.clone(),
+                    args.volume,
+                ))
+            }
+            None => Box::new(NullAudio),
+        };
 
-        let frame_buffer = frame_buffer_rx.recv().unwrap();
-        let mut emulator = chip8::Emulator::new(
-            frame_buffer,
-            key_event_rx,
-            chip8::DEFAULT_CYCLE_RATE,
-            args.shift_instruction_original,
-            args.jump_with_offset_original,
-            args.store_and_load_original,
-            audio_sink,
-            beep_data,
-        );
+        let header = replay.as_ref().map(|replay| replay.header.clone());
+        let keyboard: Box<dyn Input> = Box::new(frontend::WinitInput::new(key_event_rx));
+        // in tool-assisted mode the keyboard goes to the runner, which sets the keypad itself
+        let (input, tas) = if args.tas {
+            let tas = args.tas(&rom, replay, keyboard);
+            (Box::new(NullInput) as Box<dyn Input>, Some(tas))
+        } else {
+            (args.input(&rom, replay, keyboard), None)
+        };
+
+        let builder = args
+            .builder(rom, header.as_ref())
+            .display(frontend::PixelsDisplay::new(frame_buffer))
+            .input(input)
+            .audio(args.audio(beep, speaker));
+        if let Some(port) = args.dap {
+            let _hotkey_rx = hotkey_rx;
+            run_dap(builder, port, &args);
+            process::exit(0);
+        }
+        let emulator = builder.build().unwrap_or_else(|e| exit_with_error(&e));
+        let emulator = load_initial_state(emulator, &args);
 
-        emulator.load_rom(rom);
-        emulator.run();
+        if args.debug || args.tui {
+            // hotkeys don't apply while debugging, keeping the channel open drops them quietly
+            let _hotkey_rx = hotkey_rx;
+            run_debugger(emulator, &args);
+            process::exit(0);
+        }
+
+        let mut runner = runner::Runner::new(
+            emulator,
+            hotkey_rx,
+            event_loop_proxy,
+            EMULATOR_TITLE.to_string(),
+            args.rom.clone().unwrap_or_default(),
+            args.rewind(),
+            !args.tas && (args.record_input.is_some() || args.replay.is_some()),
+            tas,
+        );
+        runner.run();
     });
 
     let _ = event_loop.run_app(&mut app);
 }
+
+fn run_headless(args: Args, rom: Vec<u8>, replay: Option<Replay>) {
+    let header = replay.as_ref().map(|replay| replay.header.clone());
+    let input = args.input(&rom, replay, Box::new(NullInput));
+
+    // no-op backends, apart from the replay and the audio recording if there are any
+    let builder = args
+        .builder(rom, header.as_ref())
+        .input(input)
+        .audio(args.audio(args.beep(), Box::new(NullAudio)));
+    if let Some(port) = args.dap {
+        run_dap(builder, port, &args);
+        return;
+    }
+    let emulator = builder.build().unwrap_or_else(|e| exit_with_error(&e));
+    let mut emulator = load_initial_state(emulator, &args);
+
+    if args.debug || args.tui {
+        run_debugger(emulator, &args);
+        return;
+    }
+
+    if let Err(e) = emulator.run() {
+        eprintln!("{}", runner::halt_report(&emulator, &e));
+        process::exit(1);
+    }
+}
+
+// --debug or --tui, until the debugger is quit
+fn run_debugger(emulator: Emulator, args: &Args) {
+    let mut debugger = Debugger::new(emulator);
+    debugger.set_history_capacity(args.history);
+    if args.debug {
+        repl::Repl::new(debugger).run();
+    } else if let Err(e) = tui::Tui::new(debugger).run() {
+        eprintln!("terminal error: {}", e);
+        process::exit(1);
+    }
+}
+
+// --dap, until the client disconnects
+fn run_dap(builder: EmulatorBuilder, port: Option<u16>, args: &Args) {
+    let (input, output) = dap::connect(port).unwrap_or_else(|e| {
+        eprintln!("failed to start the debug adapter: {}", e);
+        process::exit(1);
+    });
+    dap::Dap::new(input, output, builder, args.history).run();
+}
+
+// restore --load-state if it was given, a state that can't be loaded stops the emulator
+// before it runs anything
+fn load_initial_state(mut emulator: Emulator, args: &Args) -> Emulator {
+    if let Some(path) = &args.load_state
+        && let Err(e) = runner::load_state(&mut emulator, path)
+    {
+        eprintln!("failed to load state {}: {}", path, e);
+        process::exit(1);
+    }
+    emulator
+}
+
+fn read_rom(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| {
+        eprintln!("failed to read rom {}: {}", path, e);
+        process::exit(1);
+    })
+}
+
+// a recording that can't be read, or was made with another rom, stops the emulator
+// before it runs anything
+fn read_replay(path: &str, rom: &[u8]) -> Replay {
+    let replay = fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| Replay::from_bytes(&bytes).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            eprintln!("failed to read replay {}: {}", path, e);
+            process::exit(1);
+        });
+    if !replay.header.matches_rom(rom) {
+        eprintln!("replay {} was recorded with a different rom", path);
+        process::exit(1);
+    }
+    replay
+}
+
+fn exit_with_error(error: &EmulatorError) -> ! {
+    eprintln!("error: {}", error);
+    process::exit(1);
+}
@@ -0,0 +1,248 @@
+use crate::chip8::{RAM_SIZE, XO_CHIP_RAM_SIZE};
+use std::fmt;
+use std::str::FromStr;
+
+// Machines that ran Chip-8 programs, each with their own take on the "ambiguous" instructions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    // what this emulator always did before there were platforms to pick from: SUPER-CHIP's
+    // shifts, jumps and loads, but with the COSMAC VIP's VF reset and no display wait
+    #[default]
+    Legacy,
+    CosmacVip,
+    Chip48,
+    Schip10,
+    Schip11,
+    SchipModern,
+    XoChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 7] = [
+        Platform::Legacy,
+        Platform::CosmacVip,
+        Platform::Chip48,
+        Platform::Schip10,
+        Platform::Schip11,
+        Platform::SchipModern,
+        Platform::XoChip,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Legacy => "legacy",
+            Platform::CosmacVip => "cosmac-vip",
+            Platform::Chip48 => "chip-48",
+            Platform::Schip10 => "schip-1.0",
+            Platform::Schip11 => "schip-1.1",
+            Platform::SchipModern => "schip-modern",
+            Platform::XoChip => "xo-chip",
+        }
+    }
+
+    // bytes of memory, everything before XO-CHIP has 4KB and XO-CHIP has 64KB
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::XoChip => XO_CHIP_RAM_SIZE,
+            _ => RAM_SIZE,
+        }
+    }
+
+    // whether sound plays the XO-CHIP audio pattern at the pitch register
+    // instead of the frontend's beep
+    pub fn has_audio_pattern(self) -> bool {
+        self == Platform::XoChip
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Platform::ALL
+            .into_iter()
+            .find(|platform| platform.name() == s)
+            .ok_or_else(|| format!("unknown platform: {}", s))
+    }
+}
+
+// What FX55 and FX65 do to the index register after storing or loading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IndexIncrement {
+    // I is left alone
+    Unchanged,
+    // I is incremented by X, an off by one bug in CHIP-48 and SUPER-CHIP 1.0
+    ByX,
+    // I ends up pointing just past the last register, like on the COSMAC VIP
+    ByXPlusOne,
+}
+
+impl IndexIncrement {
+    pub const ALL: [IndexIncrement; 3] = [
+        IndexIncrement::Unchanged,
+        IndexIncrement::ByX,
+        IndexIncrement::ByXPlusOne,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IndexIncrement::Unchanged => "unchanged",
+            IndexIncrement::ByX => "x",
+            IndexIncrement::ByXPlusOne => "x-plus-one",
+        }
+    }
+}
+
+impl FromStr for IndexIncrement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        IndexIncrement::ALL
+            .into_iter()
+            .find(|increment| increment.name() == s)
+            .ok_or_else(|| format!("unknown index increment: {}", s))
+    }
+}
+
+// Behaviour of the instructions that differ between platforms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Quirks {
+    // 8XY6 and 8XYE put the value of VY into VX before shifting, like the COSMAC VIP.
+    // Starting with CHIP-48 and SUPER-CHIP they shift VX in place and ignore VY.
+    pub shift_uses_vy: bool,
+
+    // BNNN jumps to XNN plus the value in VX, like CHIP-48 and SUPER-CHIP.
+    // The COSMAC VIP jumps to NNN plus the value in V0.
+    pub jump_uses_vx: bool,
+
+    // what FX55 and FX65 do to the index register
+    pub index_increment: IndexIncrement,
+
+    // 8XY1, 8XY2 and 8XY3 reset VF to 0, a side effect of how the COSMAC VIP implemented them
+    pub vf_reset: bool,
+
+    // sprites drawn past the edge of the screen are clipped, otherwise they wrap around
+    // to the opposite side like on XO-CHIP
+    pub clip_sprites: bool,
+
+    // DXYN waits for the vertical blank interrupt, so at most one sprite is drawn per frame
+    pub display_wait: bool,
+
+    // FX0A waits for a key to be pressed and then released, like the COSMAC VIP,
+    // instead of returning as soon as a key is down
+    pub key_wait_release: bool,
+
+    // in high resolution mode DXYN sets VF to the number of sprite rows that collided
+    // or were clipped at the bottom of the screen, like SUPER-CHIP 1.x
+    pub collision_row_count: bool,
+}
+
+impl Quirks {
+    pub fn for_platform(platform: Platform) -> Self {
+        match platform {
+            Platform::Legacy => Self {
+                shift_uses_vy: false,
+                jump_uses_vx: true,
+                index_increment: IndexIncrement::Unchanged,
+                vf_reset: true,
+                clip_sprites: true,
+                display_wait: false,
+                key_wait_release: false,
+                collision_row_count: false,
+            },
+            Platform::CosmacVip => Self {
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                index_increment: IndexIncrement::ByXPlusOne,
+                vf_reset: true,
+                clip_sprites: true,
+                display_wait: true,
+                key_wait_release: true,
+                collision_row_count: false,
+            },
+            Platform::Chip48 => Self {
+                shift_uses_vy: false,
+                jump_uses_vx: true,
+                index_increment: IndexIncrement::ByX,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+                key_wait_release: false,
+                collision_row_count: false,
+            },
+            Platform::Schip10 => Self {
+                shift_uses_vy: false,
+                jump_uses_vx: true,
+                index_increment: IndexIncrement::ByX,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: true,
+                key_wait_release: false,
+                collision_row_count: true,
+            },
+            Platform::Schip11 => Self {
+                shift_uses_vy: false,
+                jump_uses_vx: true,
+                index_increment: IndexIncrement::Unchanged,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: true,
+                key_wait_release: false,
+                collision_row_count: true,
+            },
+            Platform::SchipModern => Self {
+                shift_uses_vy: false,
+                jump_uses_vx: true,
+                index_increment: IndexIncrement::Unchanged,
+                vf_reset: false,
+                clip_sprites: true,
+                display_wait: false,
+                key_wait_release: true,
+                collision_row_count: false,
+            },
+            Platform::XoChip => Self {
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                index_increment: IndexIncrement::ByXPlusOne,
+                vf_reset: false,
+                clip_sprites: false,
+                display_wait: false,
+                key_wait_release: true,
+                collision_row_count: false,
+            },
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::for_platform(Platform::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_names() {
+        for platform in Platform::ALL {
+            assert_eq!(platform.name().parse(), Ok(platform));
+        }
+        assert!("chip-9".parse::<Platform>().is_err());
+    }
+
+    #[test]
+    fn index_increment_names() {
+        for increment in IndexIncrement::ALL {
+            assert_eq!(increment.name().parse(), Ok(increment));
+        }
+        assert!("y".parse::<IndexIncrement>().is_err());
+    }
+}
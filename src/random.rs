@@ -0,0 +1,197 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// The random numbers CXNN draws. The emulator owns its generator and starts it from a seed,
+// so two runs with the same seed and the same input do exactly the same thing, and the
+// generator's state goes into save states like the rest of the machine.
+
+// How the random bytes are generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RandomMode {
+    // a SplitMix64 generator, uniformly distributed bytes
+    Standard,
+
+    // the COSMAC VIP interpreter's CXNN: the low byte of its R9 register counts up on every
+    // CXNN and picks a byte of the interpreter's own code in page 0x100, which is added to the
+    // high byte and is the random number. The code is read from a dump of the interpreter,
+    // the seed stands in for whatever R9 held when the program started
+    CosmacVip,
+}
+
+impl RandomMode {
+    pub const ALL: [RandomMode; 2] = [RandomMode::Standard, RandomMode::CosmacVip];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RandomMode::Standard => "standard",
+            RandomMode::CosmacVip => "cosmac-vip",
+        }
+    }
+}
+
+impl fmt::Display for RandomMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for RandomMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        RandomMode::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| format!("unknown random mode: {}", s))
+    }
+}
+
+// the COSMAC VIP's CHIP-8 interpreter fills memory from 0x000 to 0x1FF
+pub const VIP_INTERPRETER_SIZE: usize = 0x200;
+
+// the part of the interpreter the random numbers are read from, 0x100 to 0x1FF
+pub const VIP_PAGE_SIZE: usize = 0x100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Random {
+    // what the generator was started from, kept so it can be written to replay files
+    seed: u64,
+
+    // the SplitMix64 state, or the VIP's 16-bit R9 register in the low bits
+    state: u64,
+
+    // page 0x100 of the interpreter in the COSMAC VIP mode, shared by every snapshot
+    vip_page: Option<Arc<[u8; VIP_PAGE_SIZE]>>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            state: seed,
+            vip_page: None,
+        }
+    }
+
+    // a generator following the COSMAC VIP's scheme with the code of its interpreter
+    pub fn cosmac_vip(seed: u64, interpreter: &[u8; VIP_INTERPRETER_SIZE]) -> Self {
+        let mut page = [0; VIP_PAGE_SIZE];
+        page.copy_from_slice(&interpreter[VIP_INTERPRETER_SIZE - VIP_PAGE_SIZE..]);
+        Self {
+            seed,
+            state: seed & 0xFFFF,
+            vip_page: Some(Arc::new(page)),
+        }
+    }
+
+    // a generator in exactly this state, e.g one restored from a save state.
+    // vip_page is page 0x100 of the interpreter for the COSMAC VIP mode
+    pub fn from_state(seed: u64, state: u64, vip_page: Option<[u8; VIP_PAGE_SIZE]>) -> Self {
+        Self {
+            seed,
+            state,
+            vip_page: vip_page.map(Arc::new),
+        }
+    }
+
+    pub fn mode(&self) -> RandomMode {
+        match self.vip_page {
+            Some(_) => RandomMode::CosmacVip,
+            None => RandomMode::Standard,
+        }
+    }
+
+    pub(crate) fn vip_page(&self) -> Option<&[u8; VIP_PAGE_SIZE]> {
+        self.vip_page.as_deref()
+    }
+
+    // carry on from another state of the same generator
+    pub(crate) fn set_state(&mut self, seed: u64, state: u64) {
+        self.seed = seed;
+        self.state = state;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match &self.vip_page {
+            None => (self.next_u64() >> 56) as u8,
+            Some(page) => {
+                let [low, high, ..] = self.state.to_le_bytes();
+                let low = low.wrapping_add(1);
+                let high = high.wrapping_add(page[low as usize]);
+                self.state = u16::from_le_bytes([low, high]) as u64;
+                high
+            }
+        }
+    }
+
+    // SplitMix64, see https://prng.di.unimi.it/splitmix64.c
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(random: &mut Random) -> Vec<u8> {
+        (0..16).map(|_| random.next_byte()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut first = Random::new(1);
+        let mut second = Random::new(1);
+        let mut other = Random::new(2);
+        let numbers = bytes(&mut first);
+        assert_eq!(numbers, bytes(&mut second));
+        assert_ne!(numbers, bytes(&mut other));
+    }
+
+    #[test]
+    fn carries_on_from_its_state() {
+        let mut random = Random::new(1);
+        bytes(&mut random);
+        let mut restored = Random::from_state(random.seed(), random.state(), None);
+        assert_eq!(bytes(&mut random), bytes(&mut restored));
+
+        let mut vip = Random::cosmac_vip(1, &[7; VIP_INTERPRETER_SIZE]);
+        bytes(&mut vip);
+        let mut restored = Random::from_state(vip.seed(), vip.state(), vip.vip_page().copied());
+        assert_eq!(bytes(&mut vip), bytes(&mut restored));
+    }
+
+    #[test]
+    fn cosmac_vip_reads_the_interpreter() {
+        // only page 0x100 of the interpreter is used
+        let mut interpreter = [0xFF; VIP_INTERPRETER_SIZE];
+        for (i, byte) in interpreter[VIP_PAGE_SIZE..].iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        // R9 starts at 0x0302, so the bytes at 0x103, 0x104 and 0x105 are added to 0x03
+        let mut random = Random::cosmac_vip(0x1234_0302, &interpreter);
+        assert_eq!(random.mode(), RandomMode::CosmacVip);
+        assert_eq!(bytes(&mut random)[..3], [0x06, 0x0A, 0x0F]);
+        assert_eq!(random.state() >> 16, 0);
+    }
+
+    #[test]
+    fn mode_names() {
+        for mode in RandomMode::ALL {
+            assert_eq!(mode.name().parse(), Ok(mode));
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use crate::runner::halt_report;
+use chip8_rust::EmulatorError;
+use chip8_rust::debugger::{Debugger, Location, Stop, Watchpoint};
+use chip8_rust::expression::{Expression, Register};
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc;
+use std::thread;
+
+// The --debug command line, a small gdb-like debugger on stdin and stdout. Execution stays in
+// the window's frame: stepping presents the screen after every command and continue runs in
+// real time until a breakpoint, the end of the program or a line on stdin interrupts it.
+pub struct Repl {
+    debugger: Debugger,
+
+    // lines read from stdin on their own thread, so continue can be interrupted
+    lines: mpsc::Receiver<String>,
+
+    // an empty line runs the last command again
+    last_line: String,
+
+    // a command typed while running, which interrupted it and runs next
+    pending_line: Option<String>,
+}
+
+const HELP: &str = "\
+step [n]          execute n instructions (default 1)
+continue          run in real time until a breakpoint, Enter or a command breaks
+frame             run until the end of the current frame
+reverse-step [n]  undo n instructions (default 1)
+reverse-continue  go back to the last stop at a breakpoint or watchpoint
+break [where] [if <cond>]
+                  break at an address or an opcode pattern like Dxyn,
+                  anywhere the condition holds, or list the breakpoints
+watch <addr> [len] [rwx] [if <cond>]
+                  break when the program reads, writes or fetches the memory
+                  (default rw)
+delete [n]        delete breakpoint or watchpoint n, or all of them
+regs              show the registers and timers
+mem <addr> <len>  dump memory
+stack             show the call stack
+set <reg> <val>   set V0-VF, I, PC, DT or ST
+disasm [addr] [n] disassemble n instructions (default: 10 at pc)
+quit              exit the emulator
+Numbers are decimal, or hex with a 0x prefix. An empty line repeats the last command.
+Conditions are expressions like V3 == 0x10 && I > 0x300, over V0-VF, I, PC, DT, ST
+and [addr] for a byte of memory, with || && == != < <= > >= + - & | ! and parentheses.";
+
+enum Command {
+    Step(u32),
+    Continue,
+    Frame,
+    ReverseStep(u32),
+    ReverseContinue,
+    // no location or condition lists them
+    Break {
+        location: Option<Location>,
+        condition: Option<Expression>,
+    },
+    Delete(Option<u32>),
+    Regs,
+    Mem {
+        address: u16,
+        len: u16,
+    },
+    Stack,
+    Set(Register, u16),
+    Disasm {
+        address: Option<u16>,
+        count: u16,
+    },
+    Help,
+    Quit,
+}
+
+impl Repl {
+    pub fn new(debugger: Debugger) -> Self {
+        let (line_tx, line_rx) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if line_tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            debugger,
+            lines: line_rx,
+            last_line: String::new(),
+            pending_line: None,
+        }
+    }
+
+    // runs until quit or the end of stdin
+    pub fn run(&mut self) {
+        println!("stopped before the first instruction, type help for the commands");
+        self.debugger.emulator_mut().present_paused();
+        self.show_location();
+
+        loop {
+            let line = match self.pending_line.take() {
+                Some(line) => line,
+                None => {
+                    print!("(chip8) ");
+                    let _ = io::stdout().flush();
+                    let Ok(line) = self.lines.recv() else {
+                        return;
+                    };
+                    line
+                }
+            };
+
+            let line = if line.trim().is_empty() {
+                self.last_line.clone()
+            } else {
+                line
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            self.last_line = line.clone();
+
+            let command = match parse(&line) {
+                Ok(command) => command,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            if let Command::Quit = command {
+                return;
+            }
+            self.execute(command);
+        }
+    }
+
+    fn execute(&mut self, command: Command) {
+        match command {
+            Command::Step(count) => {
+                let result = self.step(count);
+                self.stopped(result);
+            }
+            Command::Continue => {
+                let result = self.continue_running();
+                self.stopped(result);
+            }
+            Command::Frame => {
+                let result = self.debugger.finish_frame();
+                self.stopped(result);
+            }
+            Command::ReverseStep(count) => {
+                let stop = self.reverse_step(count);
+                self.stopped(Ok(stop));
+            }
+            Command::ReverseContinue => {
+                let result = self.debugger.reverse_continue();
+                self.stopped(result);
+            }
+            Command::Break {
+                location: None,
+                condition: None,
+            } => {
+                if self.debugger.breakpoints().is_empty() {
+                    println!("no breakpoints");
+                }
+                for breakpoint in self.debugger.breakpoints() {
+                    println!("  {}: {}", breakpoint.id, breakpoint);
+                }
+            }
+            Command::Break {
+                location,
+                condition,
+            } => {
+                let location = location.unwrap_or(Location::Anywhere);
+                let kind = match location {
+                    Location::Watch(_) => "watchpoint",
+                    _ => "breakpoint",
+                };
+                let id = self.debugger.add_breakpoint(location, condition);
+                println!("{} {}: {}", kind, id, self.breakpoint(id));
+            }
+            Command::Delete(None) => {
+                self.debugger.clear_breakpoints();
+                println!("deleted all breakpoints");
+            }
+            Command::Delete(Some(id)) => {
+                let description = self.breakpoint(id);
+                if self.debugger.remove_breakpoint(id) {
+                    println!("deleted {}: {}", id, description);
+                } else {
+                    println!("no breakpoint or watchpoint {}", id);
+                }
+            }
+            Command::Regs => self.show_registers(),
+            Command::Mem { address, len } => self.show_memory(address, len),
+            Command::Stack => self.show_stack(),
+            Command::Set(register, val) => {
+                if let Err(e) = register.write(self.debugger.emulator_mut(), val) {
+                    println!("{}", e);
+                }
+            }
+            Command::Disasm { address, count } => {
+                let address = address.unwrap_or(self.debugger.emulator().pc());
+                self.disassemble(address, count);
+            }
+            Command::Help => println!("{}", HELP),
+            Command::Quit => {}
+        }
+    }
+
+    fn step(&mut self, count: u32) -> Result<Stop, EmulatorError> {
+        for _ in 0..count {
+            match self.debugger.step()? {
+                Stop::Step => {}
+                stop => return Ok(stop),
+            }
+        }
+        Ok(Stop::Step)
+    }
+
+    fn reverse_step(&mut self, count: u32) -> Stop {
+        for _ in 0..count {
+            match self.debugger.reverse_step() {
+                Stop::Step => {}
+                stop => return stop,
+            }
+        }
+        Stop::Step
+    }
+
+    fn continue_running(&mut self) -> Result<Stop, EmulatorError> {
+        println!("continuing, press Enter to break");
+        self.debugger.emulator_mut().resync();
+
+        loop {
+            if let Ok(line) = self.lines.try_recv() {
+                println!("interrupted");
+                // an empty line only interrupts, it doesn't repeat continue
+                if !line.trim().is_empty() {
+                    self.pending_line = Some(line);
+                }
+                return Ok(Stop::Step);
+            }
+            match self.debugger.run_due_frames()? {
+                Stop::FrameEnd => {}
+                stop => return Ok(stop),
+            }
+        }
+    }
+
+    // report why execution stopped and where
+    fn stopped(&mut self, result: Result<Stop, EmulatorError>) {
+        self.debugger.emulator_mut().present_paused();
+        match result {
+            Ok(Stop::Step | Stop::Reached(_)) => {}
+            Ok(Stop::FrameEnd) => {
+                println!("end of frame {}", self.debugger.emulator().frame() - 1)
+            }
+            Ok(Stop::Breakpoint(id)) => println!("breakpoint {}: {}", id, self.breakpoint(id)),
+            Ok(Stop::Watchpoint { id, pc, access }) => println!(
+                "watchpoint {}: {} of {:#05x} by the instruction at {:#05x}",
+                id, access.kind, access.address, pc
+            ),
+            Ok(Stop::Exited) => println!("the program exited"),
+            Ok(Stop::StartOfHistory) => println!("no more history to go back through"),
+            Err(e) => println!("{}", halt_report(self.debugger.emulator(), &e)),
+        }
+        self.show_location();
+    }
+
+    fn show_location(&self) {
+        self.disassemble(self.debugger.emulator().pc(), 1);
+    }
+
+    fn show_registers(&self) {
+        let emulator = self.debugger.emulator();
+        let registers: Vec<String> = emulator
+            .var_registers()
+            .iter()
+            .enumerate()
+            .map(|(idx, val)| format!("V{:X}={:02x}", idx, val))
+            .collect();
+        println!(
+            "pc={:#05x} I={:#05x} DT={} ST={} frame={}",
+            emulator.pc(),
+            emulator.index_register(),
+            emulator.delay_timer(),
+            emulator.sound_timer(),
+            emulator.frame()
+        );
+        println!("{}", registers[..8].join(" "));
+        println!("{}", registers[8..].join(" "));
+    }
+
+    // 16 bytes to a line
+    fn show_memory(&self, address: u16, len: u16) {
+        let memory = self.debugger.emulator().memory();
+        let start = address as usize;
+        if start >= memory.len() {
+            println!("{:#05x} is past the end of memory", address);
+            return;
+        }
+        let end = (start + len as usize).min(memory.len());
+
+        for line_start in (start..end).step_by(16) {
+            let bytes: Vec<String> = memory[line_start..end.min(line_start + 16)]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            println!("{:#05x}: {}", line_start, bytes.join(" "));
+        }
+    }
+
+    // innermost call first, each entry is the address the call returns to
+    fn show_stack(&self) {
+        let stack = self.debugger.emulator().stack();
+        if stack.is_empty() {
+            println!("the stack is empty");
+        }
+        for (depth, address) in stack.iter().rev().enumerate() {
+            println!("#{} returns to {:#05x}", depth, address);
+        }
+    }
+
+    // as listed, e.g "opcode Dxyn if V0 > 2"
+    fn breakpoint(&self, id: u32) -> String {
+        self.debugger
+            .breakpoint(id)
+            .map(|breakpoint| breakpoint.to_string())
+            .unwrap_or_default()
+    }
+
+    // pc is marked with =>, breakpoints with *
+    fn disassemble(&self, address: u16, count: u16) {
+        let emulator = self.debugger.emulator();
+        let mut address = address;
+        for _ in 0..count {
+            let Some(instruction) = emulator.instruction_at(address) else {
+                println!("{:#05x} is past the end of memory", address);
+                return;
+            };
+
+            let marker = if address == emulator.pc() { "=>" } else { "  " };
+            let breakpoint = if self.debugger.breakpoint_at(address).is_some() {
+                "*"
+            } else {
+                " "
+            };
+            // F000 NNNN shows both of its words
+            let memory = emulator.memory();
+            let end = (address as usize + instruction.size() as usize).min(memory.len());
+            let words: Vec<String> = memory[address as usize..end]
+                .chunks(2)
+                .map(|word| word.iter().map(|byte| format!("{:02x}", byte)).collect())
+                .collect();
+            println!(
+                "{}{} {:#05x}  {:<9}  {}",
+                marker,
+                breakpoint,
+                address,
+                words.join(" "),
+                instruction
+            );
+
+            address = address.wrapping_add(instruction.size());
+        }
+    }
+}
+
+fn parse(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args: Vec<&str> = words.collect();
+
+    let arity = |min: usize, max: usize| {
+        if (min..=max).contains(&args.len()) {
+            Ok(())
+        } else {
+            Err(format!("wrong number of arguments for {}, see help", name))
+        }
+    };
+    let arg = |idx: usize| args.get(idx).map(|arg| parse_number(arg)).transpose();
+
+    let command = match name {
+        "step" | "s" => {
+            arity(0, 1)?;
+            Command::Step(arg(0)?.unwrap_or(1) as u32)
+        }
+        "continue" | "c" => {
+            arity(0, 0)?;
+            Command::Continue
+        }
+        "frame" | "f" => {
+            arity(0, 0)?;
+            Command::Frame
+        }
+        "reverse-step" | "rs" => {
+            arity(0, 1)?;
+            Command::ReverseStep(arg(0)?.unwrap_or(1) as u32)
+        }
+        "reverse-continue" | "rc" => {
+            arity(0, 0)?;
+            Command::ReverseContinue
+        }
+        "break" | "b" => {
+            let (args, condition) = split_condition(&args)?;
+            if args.len() > 1 {
+                return Err(format!("wrong number of arguments for {}, see help", name));
+            }
+            Command::Break {
+                location: args.first().map(|arg| parse_location(arg)).transpose()?,
+                condition,
+            }
+        }
+        "watch" | "w" => {
+            let (args, condition) = split_condition(&args)?;
+            Command::Break {
+                location: Some(Location::Watch(parse_watchpoint(args)?)),
+                condition,
+            }
+        }
+        "delete" | "d" => {
+            arity(0, 1)?;
+            Command::Delete(arg(0)?.map(u32::from))
+        }
+        "regs" | "r" => {
+            arity(0, 0)?;
+            Command::Regs
+        }
+        "mem" | "m" => {
+            arity(2, 2)?;
+            Command::Mem {
+                address: parse_number(args[0])?,
+                len: parse_number(args[1])?,
+            }
+        }
+        "stack" | "bt" => {
+            arity(0, 0)?;
+            Command::Stack
+        }
+        "set" => {
+            arity(2, 2)?;
+            Command::Set(args[0].parse::<Register>()?, parse_number(args[1])?)
+        }
+        "disasm" | "x" => {
+            arity(0, 2)?;
+            Command::Disasm {
+                address: arg(0)?,
+                count: arg(1)?.unwrap_or(10),
+            }
+        }
+        "help" | "h" => Command::Help,
+        "quit" | "q" => Command::Quit,
+        _ => return Err(format!("unknown command {}, see help", name)),
+    };
+    Ok(command)
+}
+
+// decimal, or hex with a 0x prefix
+pub fn parse_number(s: &str) -> Result<u16, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("expected a number from 0 to 0xffff: {}", s))
+}
+
+// the arguments before an if, and the condition after it
+fn split_condition<'a>(args: &'a [&'a str]) -> Result<(&'a [&'a str], Option<Expression>), String> {
+    match args.iter().position(|arg| *arg == "if") {
+        Some(idx) => Ok((&args[..idx], Some(args[idx + 1..].join(" ").parse()?))),
+        None => Ok((args, None)),
+    }
+}
+
+// an address, or failing that an opcode pattern
+fn parse_location(s: &str) -> Result<Location, String> {
+    if let Ok(address) = parse_number(s) {
+        return Ok(Location::Address(address));
+    }
+    s.parse()
+        .map(Location::Opcode)
+        .map_err(|_| format!("expected an address or an opcode pattern like Dxyn: {}", s))
+}
+
+// <addr> [len] [rwx]
+fn parse_watchpoint(args: &[&str]) -> Result<Watchpoint, String> {
+    let (address, rest) = args
+        .split_first()
+        .ok_or("watch needs an address, see help")?;
+    let mut rest = rest.iter();
+    let mut next = rest.next();
+
+    let len = match next.map(|arg| parse_number(arg)) {
+        Some(Ok(len)) => {
+            next = rest.next();
+            len
+        }
+        _ => 1,
+    };
+    if len == 0 {
+        return Err("can't watch 0 bytes".to_string());
+    }
+
+    let kinds = next.copied().unwrap_or("rw");
+    if kinds.is_empty() || !kinds.chars().all(|c| "rwx".contains(c)) {
+        return Err(format!("expected some of r, w and x: {}", kinds));
+    }
+    if rest.next().is_some() {
+        return Err("wrong number of arguments for watch, see help".to_string());
+    }
+
+    Ok(Watchpoint {
+        start: parse_number(address)? as usize,
+        len: len as usize,
+        read: kinds.contains('r'),
+        write: kinds.contains('w'),
+        fetch: kinds.contains('x'),
+    })
+}
@@ -0,0 +1,547 @@
+use crate::backend::{Input, KeyEvent};
+use crate::chip8::EmulatorBuilder;
+use crate::error::ReplayError;
+use crate::quirks::{Platform, Quirks};
+use crate::random::VIP_INTERPRETER_SIZE;
+use crate::scheduler::{MAX_CYCLES, Speed};
+use crate::state::{Reader, Writer, crc32};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Input recordings. Everything the emulator does follows from the rom, its settings, the random
+// seed and the keypad, so a recording is a header with the first three and then every change
+// to the keypad along with the frame it happened in. Played back against the same rom it
+// reproduces the run exactly, bugs included.
+//
+// The file is a header followed by events until the end of the file:
+//   magic    "C8RP"
+//   version  u16
+//   rom      CRC-32 and length of the rom, both u32
+//   settings platform and quirks (encoded like save states), speed, frame rate and seed
+//   random   u8 1 followed by the COSMAC VIP interpreter when CXNN follows the VIP, else u8 0
+//   events   u32 frame, u8 key and u8 pressed, 6 bytes each
+// Every number is little endian. Events are written as they happen, so the recording is
+// usable even if the emulator is killed.
+
+pub const MAGIC: &[u8; 4] = b"C8RP";
+
+pub const VERSION: u16 = 1;
+
+// What a run needs to be reproduced, apart from the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayHeader {
+    pub rom_checksum: u32,
+    pub rom_size: u32,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub speed: Speed,
+    pub frame_rate: u32,
+    pub seed: u64,
+    // the interpreter dump the COSMAC VIP's random numbers were read from
+    pub vip_interpreter: Option<Vec<u8>>,
+}
+
+impl ReplayHeader {
+    pub fn new(
+        rom: &[u8],
+        platform: Platform,
+        quirks: Quirks,
+        speed: Speed,
+        frame_rate: u32,
+        seed: u64,
+        vip_interpreter: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            rom_checksum: crc32(rom),
+            rom_size: rom.len() as u32,
+            platform,
+            quirks,
+            speed,
+            frame_rate,
+            seed,
+            vip_interpreter,
+        }
+    }
+
+    // whether this is the rom the recording was made with
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_size == rom.len() as u32 && self.rom_checksum == crc32(rom)
+    }
+
+    // set up the builder the way the recorded run was
+    pub fn configure(&self, builder: EmulatorBuilder) -> EmulatorBuilder {
+        let builder = builder
+            .platform(self.platform)
+            .quirks(self.quirks)
+            .speed(self.speed)
+            .frame_rate(self.frame_rate)
+            .seed(self.seed);
+        match &self.vip_interpreter {
+            Some(interpreter) => builder.vip_random(interpreter.clone()),
+            None => builder,
+        }
+    }
+
+    fn write(&self, data: &mut Writer) {
+        data.bytes(MAGIC);
+        data.u16(VERSION);
+        data.u32(self.rom_checksum);
+        data.u32(self.rom_size);
+        data.platform(self.platform);
+        data.quirks(&self.quirks);
+        match self.speed {
+            Speed::CyclesPerSecond(cycles) => {
+                data.u8(0);
+                data.u32(cycles);
+            }
+            Speed::CyclesPerFrame(cycles) => {
+                data.u8(1);
+                data.u32(cycles);
+            }
+        }
+        data.u32(self.frame_rate);
+        data.u64(self.seed);
+        match &self.vip_interpreter {
+            Some(interpreter) => {
+                data.u8(1);
+                data.bytes(interpreter);
+            }
+            None => data.u8(0),
+        }
+    }
+
+    fn read(data: &mut Reader) -> Result<Self, ReplayError> {
+        if data.bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(ReplayError::NotAReplay);
+        }
+        let version = data.u16()?;
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion { version });
+        }
+
+        let rom_checksum = data.u32()?;
+        let rom_size = data.u32()?;
+        let platform = data.platform()?;
+        let quirks = data.quirks()?;
+        let speed = match (data.u8()?, data.u32()?) {
+            (_, 0) => return Err(ReplayError::Invalid("speed of 0 cycles")),
+            (_, cycles) if cycles > MAX_CYCLES => {
+                return Err(ReplayError::Invalid("speed above the maximum"));
+            }
+            (0, cycles) => Speed::CyclesPerSecond(cycles),
+            (1, cycles) => Speed::CyclesPerFrame(cycles),
+            _ => return Err(ReplayError::Invalid("unknown speed")),
+        };
+        let frame_rate = data.u32()?;
+        if frame_rate == 0 {
+            return Err(ReplayError::Invalid("frame rate of 0"));
+        }
+
+        Ok(Self {
+            rom_checksum,
+            rom_size,
+            platform,
+            quirks,
+            speed,
+            frame_rate,
+            seed: data.u64()?,
+            vip_interpreter: match data.u8()? {
+                0 => None,
+                1 => Some(data.bytes(VIP_INTERPRETER_SIZE)?.to_vec()),
+                _ => return Err(ReplayError::Invalid("unknown random mode")),
+            },
+        })
+    }
+}
+
+// A change to the keypad and the frame it happened in, counting from 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub frame: u32,
+    pub event: KeyEvent,
+}
+
+impl InputEvent {
+    fn write(&self, data: &mut Writer) {
+        data.u32(self.frame);
+        data.u8(self.event.key);
+        data.bool(self.event.pressed);
+    }
+
+    fn read(data: &mut Reader) -> Result<Self, ReplayError> {
+        let frame = data.u32()?;
+        let key = data.u8()?;
+        if key > 0xF {
+            return Err(ReplayError::Invalid("unknown key"));
+        }
+        let pressed = data.bool()?;
+        Ok(Self {
+            frame,
+            event: KeyEvent { key, pressed },
+        })
+    }
+}
+
+// A whole recording, read back from a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    pub header: ReplayHeader,
+
+    // in the order they happened
+    pub events: Vec<InputEvent>,
+}
+
+impl Replay {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Writer::default();
+        self.header.write(&mut data);
+        for event in &self.events {
+            event.write(&mut data);
+        }
+        data.0
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let mut data = Reader::new(bytes);
+        let header = ReplayHeader::read(&mut data)?;
+
+        let mut events: Vec<InputEvent> = Vec::new();
+        while !data.is_empty() {
+            let event = InputEvent::read(&mut data)?;
+            if events.last().is_some_and(|last| last.frame > event.frame) {
+                return Err(ReplayError::Invalid("events out of order"));
+            }
+            events.push(event);
+        }
+
+        Ok(Self { header, events })
+    }
+}
+
+// Input that passes on the keys of another backend and writes every one of them
+// to a recording as it goes
+pub struct InputRecorder {
+    input: Box<dyn Input>,
+
+    // None once writing failed, the emulator carries on without recording
+    writer: Option<BufWriter<File>>,
+
+    // the frame the emulator is polling for
+    frame: u32,
+}
+
+impl InputRecorder {
+    pub fn create(path: &str, header: &ReplayHeader, input: Box<dyn Input>) -> io::Result<Self> {
+        let mut data = Writer::default();
+        header.write(&mut data);
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&data.0)?;
+        writer.flush()?;
+
+        Ok(Self {
+            input,
+            writer: Some(writer),
+            frame: 0,
+        })
+    }
+
+    fn record(&mut self, event: KeyEvent) -> io::Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+
+        let mut data = Writer::default();
+        InputEvent {
+            frame: self.frame,
+            event,
+        }
+        .write(&mut data);
+        writer.write_all(&data.0)?;
+        writer.flush()
+    }
+}
+
+impl Input for InputRecorder {
+    fn poll(&mut self) -> Option<KeyEvent> {
+        let Some(event) = self.input.poll() else {
+            // polling for this frame is over
+            self.frame += 1;
+            return None;
+        };
+
+        if let Err(e) = self.record(event) {
+            eprintln!("failed to record input, recording stopped: {}", e);
+            self.writer = None;
+        }
+        Some(event)
+    }
+}
+
+// Input that plays a recording back frame by frame. The keys of the live backend are thrown away
+// until the recording runs out, after that they are passed on so a player can take over
+pub struct ReplayInput {
+    events: VecDeque<InputEvent>,
+    input: Box<dyn Input>,
+    frame: u32,
+}
+
+impl ReplayInput {
+    pub fn new(replay: Replay, input: Box<dyn Input>) -> Self {
+        Self {
+            events: replay.events.into(),
+            input,
+            frame: 0,
+        }
+    }
+}
+
+impl Input for ReplayInput {
+    fn poll(&mut self) -> Option<KeyEvent> {
+        if self.events.is_empty() {
+            return self.input.poll();
+        }
+
+        if self
+            .events
+            .front()
+            .is_some_and(|event| event.frame <= self.frame)
+        {
+            return self.events.pop_front().map(|event| event.event);
+        }
+
+        while self.input.poll().is_some() {}
+        self.frame += 1;
+        None
+    }
+}
+
+// A recording as the state of the keypad in every frame, the form tool-assisted play edits it in.
+// Converts to and from the event based Replay so either can be played back with --replay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub header: ReplayHeader,
+
+    // one entry per frame starting at frame 0, bit n is set while key n is down
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    pub fn new(header: ReplayHeader) -> Self {
+        Self {
+            header,
+            frames: Vec::new(),
+        }
+    }
+
+    // runs up to the frame of the last event
+    pub fn from_replay(replay: &Replay) -> Self {
+        let mut movie = Self::new(replay.header.clone());
+        let mut keys = 0u16;
+        for event in &replay.events {
+            let frame = event.frame as usize;
+            movie.frames.resize(frame, keys);
+            let bit = 1 << event.event.key;
+            keys = if event.event.pressed {
+                keys | bit
+            } else {
+                keys & !bit
+            };
+        }
+        if let Some(last) = replay.events.last() {
+            movie.frames.resize(last.frame as usize + 1, keys);
+        }
+        movie
+    }
+
+    pub fn to_replay(&self) -> Replay {
+        let mut events = Vec::new();
+        let mut previous = 0u16;
+        for (frame, keys) in self.frames.iter().enumerate() {
+            let changed = keys ^ previous;
+            for key in (0..16).filter(|key| changed & (1 << key) != 0) {
+                events.push(InputEvent {
+                    frame: frame as u32,
+                    event: KeyEvent {
+                        key,
+                        pressed: keys & (1 << key) != 0,
+                    },
+                });
+            }
+            previous = *keys;
+        }
+        Replay {
+            header: self.header.clone(),
+            events,
+        }
+    }
+
+    // number of frames recorded
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // the keys recorded for a frame, None past the end of the recording
+    pub fn keys(&self, frame: usize) -> Option<u16> {
+        self.frames.get(frame).copied()
+    }
+
+    // Record the keys of a frame. Keys that differ from what was already recorded there
+    // branch the recording off: every frame after it is dropped
+    pub fn record(&mut self, frame: usize, keys: u16) {
+        if self.keys(frame) == Some(keys) {
+            return;
+        }
+        let last = self.frames.last().copied().unwrap_or(0);
+        self.frames.resize(frame, last);
+        self.frames.push(keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::NullInput;
+    use crate::quirks::Platform;
+
+    fn header() -> ReplayHeader {
+        ReplayHeader::new(
+            &[0x12, 0x00],
+            Platform::Schip11,
+            Quirks::for_platform(Platform::Schip11),
+            Speed::CyclesPerFrame(15),
+            60,
+            42,
+            None,
+        )
+    }
+
+    fn event(frame: u32, key: u8, pressed: bool) -> InputEvent {
+        InputEvent {
+            frame,
+            event: KeyEvent { key, pressed },
+        }
+    }
+
+    fn replay() -> Replay {
+        Replay {
+            header: header(),
+            events: vec![event(0, 1, true), event(3, 1, false), event(3, 0xF, true)],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let replay = replay();
+        assert_eq!(Replay::from_bytes(&replay.to_bytes()), Ok(replay.clone()));
+
+        let mut vip = replay;
+        vip.header.vip_interpreter = Some(vec![3; VIP_INTERPRETER_SIZE]);
+        assert_eq!(Replay::from_bytes(&vip.to_bytes()), Ok(vip));
+    }
+
+    #[test]
+    fn matches_rom() {
+        assert!(header().matches_rom(&[0x12, 0x00]));
+        assert!(!header().matches_rom(&[0x12, 0x02]));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(Replay::from_bytes(b"C8ST"), Err(ReplayError::NotAReplay));
+
+        let mut bytes = replay().to_bytes();
+        bytes[4..6].copy_from_slice(&99u16.to_le_bytes());
+        assert_eq!(
+            Replay::from_bytes(&bytes),
+            Err(ReplayError::UnsupportedVersion { version: 99 })
+        );
+    }
+
+    #[test]
+    fn rejects_broken_events() {
+        let bytes = replay().to_bytes();
+        assert_eq!(
+            Replay::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ReplayError::Truncated)
+        );
+
+        let mut unknown_key = replay();
+        unknown_key.events.push(event(4, 0x10, true));
+        assert_eq!(
+            Replay::from_bytes(&unknown_key.to_bytes()),
+            Err(ReplayError::Invalid("unknown key"))
+        );
+
+        let mut out_of_order = replay();
+        out_of_order.events.push(event(2, 1, true));
+        assert_eq!(
+            Replay::from_bytes(&out_of_order.to_bytes()),
+            Err(ReplayError::Invalid("events out of order"))
+        );
+    }
+
+    #[test]
+    fn plays_events_in_their_frame() {
+        let mut input = ReplayInput::new(replay(), Box::new(NullInput));
+        let mut frames: Vec<Vec<KeyEvent>> = Vec::new();
+        for _ in 0..5 {
+            frames.push(std::iter::from_fn(|| input.poll()).collect());
+        }
+        assert_eq!(
+            frames[0],
+            [KeyEvent {
+                key: 1,
+                pressed: true
+            }]
+        );
+        assert!(frames[1].is_empty() && frames[2].is_empty());
+        assert_eq!(
+            frames[3],
+            [
+                KeyEvent {
+                    key: 1,
+                    pressed: false
+                },
+                KeyEvent {
+                    key: 0xF,
+                    pressed: true
+                }
+            ]
+        );
+        assert!(frames[4].is_empty());
+    }
+
+    #[test]
+    fn movie_round_trip() {
+        let replay = replay();
+        let movie = Movie::from_replay(&replay);
+        assert_eq!(movie.len(), 4);
+        assert_eq!(movie.keys(0), Some(0b10));
+        assert_eq!(movie.keys(2), Some(0b10));
+        assert_eq!(movie.keys(3), Some(0x8000));
+        assert_eq!(movie.keys(4), None);
+        assert_eq!(movie.to_replay(), replay);
+    }
+
+    #[test]
+    fn recording_a_different_frame_branches_off() {
+        let mut movie = Movie::from_replay(&replay());
+        movie.record(1, 0b10);
+        assert_eq!(movie.len(), 4);
+
+        movie.record(1, 0);
+        assert_eq!(movie.len(), 2);
+        assert_eq!(movie.keys(1), Some(0));
+
+        // frames skipped over hold the keys of the last one
+        movie.record(4, 1);
+        assert_eq!(movie.len(), 5);
+        assert_eq!(movie.keys(3), Some(0));
+    }
+}
@@ -0,0 +1,253 @@
+use crate::chip8::Emulator;
+use crate::state::Snapshot;
+use std::collections::VecDeque;
+
+// History of the machine for rewinding. A snapshot is taken every `interval` frames and
+// the last `capacity` of them are kept.
+//
+// Only the newest snapshot is kept whole. Each older one is stored as the difference to the
+// snapshot after it: the bytes of the two saved states XORed together, which is almost all
+// zeros from one frame to the next, with the runs of zeros squeezed out. A few KB of
+// machine state then costs tens of bytes per frame, so minutes of history are cheap.
+pub struct Rewind {
+    capacity: usize,
+    interval: u32,
+    frames_until_snapshot: u32,
+
+    // the latest snapshot in the save state format
+    newest: Option<Vec<u8>>,
+
+    // oldest first, each one turns the snapshot after it into the one before
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    pub fn new(capacity: usize, interval: u32) -> Self {
+        Self {
+            capacity,
+            interval: interval.max(1),
+            frames_until_snapshot: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // frames between snapshots, rewinding steps back this many frames at a time
+    pub fn interval(&self) -> u32 {
+        self.interval
+    }
+
+    // number of snapshots in the history
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.frames_until_snapshot = 0;
+    }
+
+    // call after every frame, a snapshot is taken once every interval frames
+    pub fn record(&mut self, emulator: &Emulator) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames_until_snapshot > 0 {
+            self.frames_until_snapshot -= 1;
+            return;
+        }
+        self.frames_until_snapshot = self.interval - 1;
+
+        let snapshot = emulator.snapshot().to_bytes();
+        if let Some(previous) = self.newest.replace(snapshot) {
+            let delta = encode_delta(&previous, self.newest.as_ref().unwrap());
+            self.deltas.push_back(delta);
+        }
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    // take the most recent snapshot out of the history, None once it is used up
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        let newest = self.newest.take()?;
+        self.newest = self
+            .deltas
+            .pop_back()
+            .map(|delta| decode_delta(&newest, &delta));
+        self.frames_until_snapshot = 0;
+
+        Some(Snapshot::from_bytes(&newest).expect("rewind history holds valid states"))
+    }
+}
+
+// The difference between older and newer: the length of older as a u32, then runs of
+// a zero count and a literal count (both varints) followed by that many XORed bytes.
+// Bytes past the end of the shorter state count as zero
+pub(crate) fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let xored = |idx: usize| older[idx] ^ newer.get(idx).copied().unwrap_or(0);
+
+    let mut delta = (older.len() as u32).to_le_bytes().to_vec();
+    let mut idx = 0;
+    while idx < older.len() {
+        let start = idx;
+        idx += common_prefix_len(&older[idx..], newer.get(idx..).unwrap_or_default());
+        while idx < older.len() && xored(idx) == 0 {
+            idx += 1;
+        }
+        let zeros = idx - start;
+
+        let start = idx;
+        while idx < older.len() && xored(idx) != 0 {
+            idx += 1;
+        }
+        write_varint(&mut delta, zeros);
+        write_varint(&mut delta, idx - start);
+        delta.extend((start..idx).map(xored));
+    }
+    delta
+}
+
+// the number of bytes both start with, compared a chunk at a time first since most of two
+// states in a row are the same
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    const CHUNK_SIZE: usize = 64;
+    let len: usize = a
+        .chunks(CHUNK_SIZE)
+        .zip(b.chunks(CHUNK_SIZE))
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len())
+        .sum();
+    len + a[len..]
+        .iter()
+        .zip(&b[len..])
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+// turn newer back into older with a delta from encode_delta
+pub(crate) fn decode_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let len = u32::from_le_bytes(delta[..4].try_into().unwrap()) as usize;
+    let mut older = newer.to_vec();
+    older.resize(len, 0);
+
+    let mut pos = 4;
+    let mut idx = 0;
+    while pos < delta.len() {
+        idx += read_varint(delta, &mut pos);
+        let literals = read_varint(delta, &mut pos);
+        for byte in &delta[pos..pos + literals] {
+            older[idx] ^= byte;
+            idx += 1;
+        }
+        pos += literals;
+    }
+    older
+}
+
+// 7 bits at a time, least significant first, the top bit is set while more follow
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push(val as u8 | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        val |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Emulator;
+
+    fn round_trip(older: &[u8], newer: &[u8]) {
+        let delta = encode_delta(older, newer);
+        assert_eq!(decode_delta(newer, &delta), older);
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let state: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut changed = state.clone();
+        changed[0] ^= 1;
+        changed[500] = 0;
+        changed[999] ^= 0xFF;
+
+        round_trip(&state, &state);
+        round_trip(&state, &changed);
+        round_trip(&changed, &state);
+        round_trip(&state, &state[..10]);
+        round_trip(&state[..10], &state);
+        round_trip(&[], &state);
+        round_trip(&state, &[]);
+    }
+
+    #[test]
+    fn delta_of_equal_states_is_small() {
+        let state = vec![0xAB; 4096];
+        let mut changed = state.clone();
+        changed[2000] = 0;
+        assert!(encode_delta(&state, &state).len() <= 8);
+        assert!(encode_delta(&state, &changed).len() <= 16);
+    }
+
+    #[test]
+    fn varints() {
+        for val in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, u32::MAX as usize] {
+            let mut data = Vec::new();
+            write_varint(&mut data, val);
+            let mut pos = 0;
+            assert_eq!(read_varint(&data, &mut pos), val);
+            assert_eq!(pos, data.len());
+        }
+    }
+
+    #[test]
+    fn rewinds_snapshots_newest_first() {
+        let mut emulator = Emulator::builder()
+            .rom(vec![0x70, 0x01, 0x12, 0x00])
+            .build()
+            .unwrap();
+        let mut rewind = Rewind::new(3, 1);
+        let mut snapshots = Vec::new();
+        for _ in 0..5 {
+            snapshots.push(emulator.snapshot());
+            rewind.record(&emulator);
+            emulator.run_frame().unwrap();
+        }
+
+        assert_eq!(rewind.len(), 3);
+        for snapshot in snapshots.iter().rev().take(3) {
+            assert_eq!(rewind.pop().as_ref(), Some(snapshot));
+        }
+        assert!(rewind.pop().is_none());
+    }
+
+    #[test]
+    fn snapshots_every_interval() {
+        let emulator = Emulator::builder().build().unwrap();
+        let mut rewind = Rewind::new(10, 4);
+        for _ in 0..9 {
+            rewind.record(&emulator);
+        }
+        assert_eq!(rewind.len(), 3);
+    }
+}
@@ -0,0 +1,419 @@
+use crate::app::{Hotkey, UserEvent};
+use crate::tas::{Tas, keypad_description};
+use chip8_rust::rewind::Rewind;
+use chip8_rust::{Emulator, EmulatorError, Snapshot, Speed};
+use std::fs;
+use std::sync::mpsc;
+use winit::event_loop::EventLoopProxy;
+
+// Drives the emulator in real time on its own thread and applies the hotkeys
+// pressed in the window in between frames
+pub struct Runner {
+    emulator: Emulator,
+    hotkey_rx: mpsc::Receiver<Hotkey>,
+    event_loop_proxy: EventLoopProxy<UserEvent>,
+    window_title: String,
+
+    // the speed the emulator was started with, ResetSpeed goes back to it
+    initial_speed: Speed,
+
+    // save states go to numbered slots next to the rom, see state_path
+    rom_path: String,
+    slot: u8,
+
+    // snapshots of the last frames, played back while the rewind key is held
+    rewind: Rewind,
+    rewinding: bool,
+    frames_until_rewind_step: u32,
+
+    // an input recording or replay is running, changing the speed, rewinding or loading
+    // a state would make the run play out differently from the recording
+    recording_input: bool,
+
+    // tool-assisted mode, which starts paused and runs a frame at a time with FrameAdvance
+    tas: Option<Tas>,
+    paused: bool,
+}
+
+impl Runner {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        emulator: Emulator,
+        hotkey_rx: mpsc::Receiver<Hotkey>,
+        event_loop_proxy: EventLoopProxy<UserEvent>,
+        window_title: String,
+        rom_path: String,
+        rewind: Rewind,
+        recording_input: bool,
+        tas: Option<Tas>,
+    ) -> Self {
+        let initial_speed = emulator.speed();
+        let paused = tas.is_some();
+        Self {
+            emulator,
+            hotkey_rx,
+            event_loop_proxy,
+            window_title,
+            initial_speed,
+            rom_path,
+            slot: 1,
+            rewind,
+            rewinding: false,
+            frames_until_rewind_step: 0,
+            recording_input,
+            tas,
+            paused,
+        }
+    }
+
+    // runs until the program exits or hits an error, the window stays open showing the last frame
+    pub fn run(&mut self) {
+        self.load_tas_keys();
+        if self.paused {
+            self.emulator.present_paused();
+        }
+        self.update_title();
+
+        loop {
+            while let Ok(hotkey) = self.hotkey_rx.try_recv() {
+                if let Err(e) = self.handle_hotkey(hotkey) {
+                    self.halt(&e);
+                    return;
+                }
+            }
+
+            if self.rewinding {
+                self.rewind_due_frames();
+                continue;
+            }
+
+            if self.paused {
+                self.wait_paused();
+            } else if let Err(e) = self.run_due_frames() {
+                self.halt(&e);
+                return;
+            }
+
+            if self.emulator.has_exited() {
+                self.save_movie();
+                self.set_title(&format!("{} - exited", self.window_title));
+                return;
+            }
+        }
+    }
+
+    fn halt(&self, error: &EmulatorError) {
+        self.save_movie();
+        eprintln!("{}", halt_report(&self.emulator, error));
+        self.set_title(&format!("{} - halted: {}", self.window_title, error));
+    }
+
+    fn run_due_frames(&mut self) -> Result<(), EmulatorError> {
+        for _ in 0..self.emulator.wait_for_frames() {
+            self.run_frame()?;
+        }
+        Ok(())
+    }
+
+    // run one frame, keeping a snapshot of it to rewind to. In tool-assisted mode the keyboard
+    // is read in between frames and the keys of every frame go into the movie
+    fn run_frame(&mut self) -> Result<(), EmulatorError> {
+        if let Some(tas) = &mut self.tas {
+            let changed = tas.poll_keyboard(&mut self.emulator);
+            tas.record_frame(&self.emulator);
+            if changed {
+                self.update_title();
+            }
+        }
+        self.emulator.run_frame()?;
+        self.rewind.record(&self.emulator);
+        self.load_tas_keys();
+        Ok(())
+    }
+
+    // keep the frame pace while paused, the keypad can still be edited
+    fn wait_paused(&mut self) {
+        self.emulator.wait_for_frames();
+        if let Some(tas) = &mut self.tas
+            && tas.poll_keyboard(&mut self.emulator)
+        {
+            self.update_title();
+        }
+    }
+
+    // go back one snapshot every interval frames, so the program plays backwards in real time.
+    // Once the history runs out it stays on the oldest snapshot
+    fn rewind_due_frames(&mut self) {
+        for _ in 0..self.emulator.wait_for_frames() {
+            if self.frames_until_rewind_step > 0 {
+                self.frames_until_rewind_step -= 1;
+                continue;
+            }
+            self.frames_until_rewind_step = self.rewind.interval() - 1;
+
+            if let Some(snapshot) = self.rewind.pop() {
+                self.emulator.restore(&snapshot);
+                self.load_tas_keys();
+            }
+        }
+        self.emulator.present_paused();
+    }
+
+    // after going to another frame, set the keypad to what the movie has for it
+    fn load_tas_keys(&mut self) {
+        if let Some(tas) = &self.tas {
+            tas.load_keys(&mut self.emulator);
+        }
+    }
+
+    fn save_movie(&self) {
+        if let Some(tas) = &self.tas
+            && let Err(e) = tas.save()
+        {
+            eprintln!("failed to save movie: {}", e);
+        }
+    }
+
+    fn handle_hotkey(&mut self, hotkey: Hotkey) -> Result<(), EmulatorError> {
+        if let Some(reason) = self.unavailable(hotkey) {
+            self.show_status(&format!("not available {}", reason));
+            return Ok(());
+        }
+
+        match hotkey {
+            Hotkey::SpeedUp => self.emulator.set_speed(self.emulator.speed().faster()),
+            Hotkey::SlowDown => self.emulator.set_speed(self.emulator.speed().slower()),
+            Hotkey::ResetSpeed => self.emulator.set_speed(self.initial_speed),
+            Hotkey::SaveState => {
+                let path = state_path(&self.rom_path, self.slot);
+                let status = match save_state(&self.emulator, &path) {
+                    Ok(()) => format!("saved slot {}", self.slot),
+                    Err(e) => format!("failed to save slot {}: {}", self.slot, e),
+                };
+                self.show_status(&status);
+                return Ok(());
+            }
+            Hotkey::LoadState => {
+                let path = state_path(&self.rom_path, self.slot);
+                let status = match load_state(&mut self.emulator, &path) {
+                    Ok(()) => {
+                        self.load_tas_keys();
+                        format!("loaded slot {}", self.slot)
+                    }
+                    Err(e) => format!("failed to load slot {}: {}", self.slot, e),
+                };
+                self.show_status(&status);
+                return Ok(());
+            }
+            Hotkey::PreviousSlot => self.slot = (self.slot + STATE_SLOTS - 1) % STATE_SLOTS,
+            Hotkey::NextSlot => self.slot = (self.slot + 1) % STATE_SLOTS,
+            Hotkey::Rewind => {
+                self.rewinding = true;
+                self.frames_until_rewind_step = 0;
+            }
+            Hotkey::StopRewind => self.rewinding = false,
+            Hotkey::Pause => {
+                self.paused = !self.paused;
+                if self.paused {
+                    self.emulator.present_paused();
+                    self.save_movie();
+                }
+            }
+            Hotkey::FrameAdvance => {
+                if !self.paused {
+                    self.paused = true;
+                } else {
+                    self.run_frame()?;
+                }
+                self.emulator.present_paused();
+                self.save_movie();
+            }
+        }
+        self.update_title();
+        Ok(())
+    }
+
+    // why a hotkey can't be used right now, None if it can
+    fn unavailable(&self, hotkey: Hotkey) -> Option<&'static str> {
+        let tas_hotkey = matches!(hotkey, Hotkey::Pause | Hotkey::FrameAdvance);
+        if self.tas.is_none() && tas_hotkey {
+            return Some("outside tool-assisted mode");
+        }
+        // the movie records frames rather than time, going back is how it branches off
+        if self.tas.is_some() && changes_speed(hotkey) {
+            return Some("in tool-assisted mode");
+        }
+        if self.recording_input && (changes_speed(hotkey) || goes_back(hotkey)) {
+            return Some("while recording or replaying input");
+        }
+        None
+    }
+
+    fn update_title(&self) {
+        let mut title = format!(
+            "{} - {} - slot {}",
+            self.window_title,
+            self.emulator.speed(),
+            self.slot
+        );
+        if self.rewinding {
+            title.push_str(" - rewinding");
+        }
+        if let Some(tas) = &self.tas {
+            // the frame number is only kept up to date while paused
+            if self.paused {
+                title.push_str(&format!(
+                    " - paused at frame {} of {}",
+                    self.emulator.frame(),
+                    tas.movie_len()
+                ));
+            } else {
+                title.push_str(" - running");
+            }
+            title.push_str(&format!(
+                " - keys {}",
+                keypad_description(self.emulator.pressed_keys())
+            ));
+        }
+        self.set_title(&title);
+    }
+
+    // the outcome of a hotkey, in the title until the next hotkey changes it
+    fn show_status(&self, status: &str) {
+        eprintln!("{}", status);
+        self.set_title(&format!("{} - {}", self.window_title, status));
+    }
+
+    fn set_title(&self, title: &str) {
+        // the window is gone once the event loop has exited
+        let _ = self
+            .event_loop_proxy
+            .send_event(UserEvent::SetTitle(title.to_string()));
+    }
+}
+
+fn changes_speed(hotkey: Hotkey) -> bool {
+    matches!(
+        hotkey,
+        Hotkey::SpeedUp | Hotkey::SlowDown | Hotkey::ResetSpeed
+    )
+}
+
+fn goes_back(hotkey: Hotkey) -> bool {
+    matches!(hotkey, Hotkey::LoadState | Hotkey::Rewind)
+}
+
+// slots 0 to 9, F6 and F7 cycle through them
+const STATE_SLOTS: u8 = 10;
+
+// e.g roms/pong.ch8.state1
+fn state_path(rom_path: &str, slot: u8) -> String {
+    format!("{}.state{}", rom_path, slot)
+}
+
+fn save_state(emulator: &Emulator, path: &str) -> Result<(), String> {
+    fs::write(path, emulator.snapshot().to_bytes()).map_err(|e| e.to_string())
+}
+
+// the emulator is only touched once the whole state has been read and checked
+pub fn load_state(emulator: &mut Emulator, path: &str) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| e.to_string())?;
+    let snapshot = Snapshot::from_bytes(&bytes).map_err(|e| e.to_string())?;
+    emulator.restore(&snapshot);
+    Ok(())
+}
+
+// what went wrong and the state of the machine when it did
+pub fn halt_report(emulator: &Emulator, error: &EmulatorError) -> String {
+    let registers: Vec<String> = emulator
+        .var_registers()
+        .iter()
+        .enumerate()
+        .map(|(idx, val)| format!("V{:X}={:02x}", idx, val))
+        .collect();
+    let stack: Vec<String> = emulator
+        .stack()
+        .iter()
+        .map(|address| format!("{:#05x}", address))
+        .collect();
+
+    format!(
+        "emulator halted: {}\n  pc={:#05x} I={:#05x} DT={} ST={}\n  {}\n  stack: [{}]",
+        error,
+        emulator.pc(),
+        emulator.index_register(),
+        emulator.delay_timer(),
+        emulator.sound_timer(),
+        registers.join(" "),
+        stack.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sets V0 and loops
+    fn emulator() -> Emulator {
+        Emulator::builder()
+            .seed(0)
+            .rom(vec![0x60, 0x2A, 0x12, 0x02])
+            .build()
+            .unwrap()
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("chip8-rust-{}-{}", std::process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn state_slots() {
+        assert_eq!(state_path("roms/pong.ch8", 3), "roms/pong.ch8.state3");
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let path = temp_path("state");
+        let mut saved = emulator();
+        saved.step().unwrap();
+        save_state(&saved, &path).unwrap();
+
+        let mut loaded = emulator();
+        load_state(&mut loaded, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.snapshot(), saved.snapshot());
+    }
+
+    #[test]
+    fn broken_states_leave_the_emulator_alone() {
+        let path = temp_path("broken-state");
+        let mut saved = emulator();
+        saved.step().unwrap();
+        let mut bytes = saved.snapshot().to_bytes();
+        bytes.pop();
+        fs::write(&path, bytes).unwrap();
+
+        let mut fresh = emulator();
+        assert!(load_state(&mut fresh, &path).is_err());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(fresh.pc(), 0x200);
+        assert!(load_state(&mut fresh, &temp_path("missing-state")).is_err());
+    }
+
+    #[test]
+    fn halt_reports_show_the_machine() {
+        let mut emulator = Emulator::builder()
+            .seed(0)
+            .rom(vec![0x60, 0x2A, 0x22, 0x06, 0x00, 0x00, 0xFF, 0xFF])
+            .build()
+            .unwrap();
+        emulator.step().unwrap();
+        emulator.step().unwrap();
+        let error = emulator.step().unwrap_err();
+        let report = halt_report(&emulator, &error);
+        assert!(report.starts_with("emulator halted: unknown opcode at 0x206: ffff"));
+        assert!(report.contains("V0=2a V1=00"));
+        assert!(report.contains("stack: [0x204]"));
+    }
+}
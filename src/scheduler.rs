@@ -0,0 +1,202 @@
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how many frames we are allowed to run back to back to catch up after a stall,
+// anything beyond that is dropped so a hiccup doesn't turn into a burst of fast forward
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+// the fastest speed, per second or per frame, far beyond what any program needs
+pub const MAX_CYCLES: u32 = 100_000_000;
+
+// How fast instructions are executed.
+// one cycle is defined as a full fetch/decode/execute loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    // instructions per second, spread evenly across the frames of each second
+    CyclesPerSecond(u32),
+
+    // a fixed number of instructions every frame, the way Octo sets its speed
+    CyclesPerFrame(u32),
+}
+
+impl Speed {
+    // about 25% faster, always at least one more cycle, up to MAX_CYCLES
+    pub fn faster(self) -> Self {
+        self.map(|cycles| {
+            cycles
+                .saturating_add(cycles / 4)
+                .max(cycles.saturating_add(1))
+                .min(MAX_CYCLES)
+        })
+    }
+
+    // about 20% slower, roughly undoing faster(), never below 1 cycle
+    pub fn slower(self) -> Self {
+        self.map(|cycles| (cycles - cycles / 5).min(cycles.saturating_sub(1)).max(1))
+    }
+
+    fn map(self, f: impl Fn(u32) -> u32) -> Self {
+        match self {
+            Speed::CyclesPerSecond(cycles) => Speed::CyclesPerSecond(f(cycles)),
+            Speed::CyclesPerFrame(cycles) => Speed::CyclesPerFrame(f(cycles)),
+        }
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Speed::CyclesPerSecond(cycles) => write!(f, "{} cycles/s", cycles),
+            Speed::CyclesPerFrame(cycles) => write!(f, "{} cycles/frame", cycles),
+        }
+    }
+}
+
+// Decides how many instructions run in each frame and when the next frame is due.
+//
+// With CyclesPerSecond the leftover fraction of a cycle is carried to the next frame,
+// e.g 700 cycles per second at 60 frames per second runs 11, 12, 12, 11, 12, 12, ... cycles.
+pub struct Scheduler {
+    speed: Speed,
+
+    frame_rate: u32,
+
+    // cycles per second that have been handed out but not yet added up to a whole cycle,
+    // in units of 1 / frame_rate of a cycle
+    cycle_remainder: u32,
+
+    // when the next frame should run, None until the first wait
+    next_frame: Option<Instant>,
+}
+
+impl Scheduler {
+    pub fn new(speed: Speed, frame_rate: u32) -> Self {
+        Self {
+            speed,
+            frame_rate,
+            cycle_remainder: 0,
+            next_frame: None,
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.cycle_remainder = 0;
+    }
+
+    pub fn frame_rate(&self) -> u32 {
+        self.frame_rate
+    }
+
+    // the part of a cycle carried over to the next frame, kept in snapshots so a restored
+    // machine runs the same number of instructions in each frame as the original
+    pub fn cycle_remainder(&self) -> u32 {
+        self.cycle_remainder
+    }
+
+    pub fn set_cycle_remainder(&mut self, cycle_remainder: u32) {
+        self.cycle_remainder = cycle_remainder % self.frame_rate;
+    }
+
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.frame_rate as f64)
+    }
+
+    // the number of instructions to execute in the next frame
+    pub fn cycles_for_frame(&mut self) -> u32 {
+        match self.speed {
+            Speed::CyclesPerFrame(cycles) => cycles,
+            Speed::CyclesPerSecond(cycles) => {
+                // in u64, the remainder and the speed can add up to more than a u32 holds
+                let total = self.cycle_remainder as u64 + cycles as u64;
+                self.cycle_remainder = (total % self.frame_rate as u64) as u32;
+                (total / self.frame_rate as u64) as u32
+            }
+        }
+    }
+
+    // sleep until the next frame is due and return how many frames should be run now.
+    // Normally that is 1, after a stall it is up to MAX_CATCH_UP_FRAMES
+    pub fn wait_for_frames(&mut self) -> u32 {
+        let frame_duration = self.frame_duration();
+
+        let now = Instant::now();
+        let next_frame = *self.next_frame.get_or_insert(now);
+        if now < next_frame {
+            thread::sleep(next_frame - now);
+        }
+
+        let now = Instant::now();
+        let behind = now.saturating_duration_since(next_frame);
+        let frames_due = 1 + (behind.as_nanos() / frame_duration.as_nanos()) as u32;
+
+        if frames_due > MAX_CATCH_UP_FRAMES {
+            // too far behind, drop the frames we can't make up and start counting from now
+            self.next_frame = Some(now + frame_duration);
+            return MAX_CATCH_UP_FRAMES;
+        }
+
+        self.next_frame = Some(next_frame + frame_duration * frames_due);
+        frames_due
+    }
+
+    // forget about the time that passed since the last frame, e.g after being paused
+    pub fn resync(&mut self) {
+        self.next_frame = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_cycles_across_frames() {
+        let mut scheduler = Scheduler::new(Speed::CyclesPerSecond(700), 60);
+        let cycles: Vec<u32> = (0..60).map(|_| scheduler.cycles_for_frame()).collect();
+        assert_eq!(cycles[..6], [11, 12, 12, 11, 12, 12]);
+        assert_eq!(cycles.iter().sum::<u32>(), 700);
+    }
+
+    #[test]
+    fn cycles_per_frame() {
+        let mut scheduler = Scheduler::new(Speed::CyclesPerFrame(15), 60);
+        assert_eq!(scheduler.cycles_for_frame(), 15);
+        assert_eq!(scheduler.cycles_for_frame(), 15);
+    }
+
+    #[test]
+    fn faster_and_slower() {
+        assert_eq!(
+            Speed::CyclesPerSecond(700).faster(),
+            Speed::CyclesPerSecond(875)
+        );
+        assert_eq!(
+            Speed::CyclesPerSecond(875).slower(),
+            Speed::CyclesPerSecond(700)
+        );
+        assert_eq!(Speed::CyclesPerFrame(1).faster(), Speed::CyclesPerFrame(2));
+        assert_eq!(Speed::CyclesPerFrame(1).slower(), Speed::CyclesPerFrame(1));
+        assert_eq!(
+            Speed::CyclesPerSecond(MAX_CYCLES - 1).faster(),
+            Speed::CyclesPerSecond(MAX_CYCLES)
+        );
+        assert_eq!(
+            Speed::CyclesPerSecond(u32::MAX).faster(),
+            Speed::CyclesPerSecond(MAX_CYCLES)
+        );
+    }
+
+    #[test]
+    fn huge_speeds_do_not_overflow() {
+        let mut scheduler = Scheduler::new(Speed::CyclesPerSecond(u32::MAX), u32::MAX - 1);
+        assert_eq!(scheduler.cycles_for_frame(), 1);
+        assert_eq!(scheduler.cycles_for_frame(), 1);
+        assert_eq!(scheduler.cycles_for_frame(), 1);
+    }
+}
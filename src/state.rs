@@ -0,0 +1,532 @@
+use crate::audio::{AudioPattern, PATTERN_SIZE};
+use crate::chip8::{RPL_FLAGS_SIZE, STACK_SIZE};
+use crate::display::{DisplayBuffer, MAX_HEIGHT, PLANE_COUNT, Resolution};
+use crate::error::StateError;
+use crate::quirks::{IndexIncrement, Platform, Quirks};
+use crate::random::{Random, RandomMode};
+
+// Save states. A Snapshot is a copy of everything that makes up the machine, taken with
+// Emulator::snapshot and put back with Emulator::restore. The speed, frame rate and backends
+// belong to whoever runs the emulator and are left out, only the fraction of a cycle the
+// scheduler carries between frames is kept.
+//
+// On disk a state is a 14 byte header followed by the snapshot:
+//   magic    "C8ST"
+//   version  u16, bumped whenever the layout below changes
+//   length   u32, bytes of snapshot data after the header
+//   checksum u32, CRC-32 of the snapshot data
+// Every number is little endian. States of another version are refused instead of guessed at.
+
+pub const MAGIC: &[u8; 4] = b"C8ST";
+
+pub const VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 14;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) platform: Platform,
+    pub(crate) quirks: Quirks,
+    pub(crate) memory: Vec<u8>,
+    pub(crate) pc: u16,
+    pub(crate) stack: Vec<u16>,
+    pub(crate) index_register: u16,
+    pub(crate) var_registers: [u8; 16],
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) cycle_remainder: u32,
+    pub(crate) display_buffer: DisplayBuffer,
+    pub(crate) pressed_keys: [bool; 16],
+    pub(crate) key_waiting_for_release: Option<u8>,
+    pub(crate) rpl_flags: [u8; RPL_FLAGS_SIZE],
+    pub(crate) exited: bool,
+    pub(crate) selected_planes: u8,
+    pub(crate) audio_pattern: AudioPattern,
+    pub(crate) random: Random,
+    pub(crate) frame: u64,
+}
+
+impl Snapshot {
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // the frame the snapshot was taken before
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // the snapshot in the on-disk format, header included
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data = self.to_data();
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + data.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&data).to_le_bytes());
+        bytes.extend_from_slice(&data);
+        bytes
+    }
+
+    // the snapshot without the header and checksum, for states that never leave memory
+    pub(crate) fn to_data(&self) -> Vec<u8> {
+        let mut data = Writer::default();
+
+        data.platform(self.platform);
+        data.quirks(&self.quirks);
+
+        // as long as the platform's memory
+        data.bytes(&self.memory);
+        data.u16(self.pc);
+        data.u8(self.stack.len() as u8);
+        for address in &self.stack {
+            data.u16(*address);
+        }
+        data.u16(self.index_register);
+        data.bytes(&self.var_registers);
+        data.u8(self.delay_timer);
+        data.u8(self.sound_timer);
+        data.u32(self.cycle_remainder);
+
+        data.display_buffer(&self.display_buffer);
+
+        let keys = self
+            .pressed_keys
+            .iter()
+            .enumerate()
+            .fold(0u16, |keys, (key, pressed)| keys | (*pressed as u16) << key);
+        data.u16(keys);
+        // 0xFF when FX0A isn't waiting for a key to be released
+        data.u8(self.key_waiting_for_release.unwrap_or(0xFF));
+
+        data.bytes(&self.rpl_flags);
+        data.bool(self.exited);
+        data.u8(self.selected_planes);
+        data.bytes(&self.audio_pattern.bits);
+        data.u8(self.audio_pattern.pitch);
+
+        data.random(&self.random);
+        data.u64(self.frame);
+        data.0
+    }
+
+    // read a state written by to_bytes, checking the header, the checksum and every value
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut header = Reader::new(bytes);
+        if header.bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+            return Err(StateError::NotAState);
+        }
+        let version = header.u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion { version });
+        }
+        let length = header.u32()? as usize;
+        let checksum = header.u32()?;
+        let data = header.bytes(length)?;
+        if crc32(data) != checksum {
+            return Err(StateError::ChecksumMismatch);
+        }
+        Self::from_data(data)
+    }
+
+    // read a state written by to_data, checking every value
+    pub(crate) fn from_data(data: &[u8]) -> Result<Self, StateError> {
+        let mut data = Reader::new(data);
+
+        let platform = data.platform()?;
+        let quirks = data.quirks()?;
+        let memory = data.bytes(platform.memory_size())?.to_vec();
+        let pc = data.u16()?;
+
+        let stack_size = data.u8()? as usize;
+        if stack_size > STACK_SIZE {
+            return Err(StateError::Invalid("stack too deep"));
+        }
+        let mut stack = Vec::with_capacity(stack_size);
+        for _ in 0..stack_size {
+            stack.push(data.u16()?);
+        }
+
+        let index_register = data.u16()?;
+        let var_registers = data.array()?;
+        let delay_timer = data.u8()?;
+        let sound_timer = data.u8()?;
+        let cycle_remainder = data.u32()?;
+
+        let display_buffer = data.display_buffer()?;
+
+        let keys = data.u16()?;
+        let pressed_keys = std::array::from_fn(|key| keys & (1 << key) != 0);
+        let key_waiting_for_release = match data.u8()? {
+            0xFF => None,
+            key if key < 16 => Some(key),
+            _ => return Err(StateError::Invalid("unknown key")),
+        };
+
+        let rpl_flags = data.array()?;
+        let exited = data.bool()?;
+        let selected_planes = data.u8()?;
+        if selected_planes >= 1 << PLANE_COUNT {
+            return Err(StateError::Invalid("unknown bitplanes selected"));
+        }
+        let audio_pattern = AudioPattern {
+            bits: data.array::<PATTERN_SIZE>()?,
+            pitch: data.u8()?,
+        };
+
+        let random = data.random()?;
+        let frame = data.u64()?;
+
+        if !data.is_empty() {
+            return Err(StateError::Invalid("unexpected data after the state"));
+        }
+
+        Ok(Self {
+            platform,
+            quirks,
+            memory,
+            pc,
+            stack,
+            index_register,
+            var_registers,
+            delay_timer,
+            sound_timer,
+            cycle_remainder,
+            display_buffer,
+            pressed_keys,
+            key_waiting_for_release,
+            rpl_flags,
+            exited,
+            selected_planes,
+            audio_pattern,
+            random,
+            frame,
+        })
+    }
+}
+
+// Little endian encoding shared with the replay files
+#[derive(Default)]
+pub(crate) struct Writer(pub(crate) Vec<u8>);
+
+impl Writer {
+    pub(crate) fn u8(&mut self, val: u8) {
+        self.0.push(val);
+    }
+
+    pub(crate) fn bool(&mut self, val: bool) {
+        self.u8(val as u8);
+    }
+
+    pub(crate) fn u16(&mut self, val: u16) {
+        self.bytes(&val.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, val: u32) {
+        self.bytes(&val.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, val: u64) {
+        self.bytes(&val.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn quirks(&mut self, quirks: &Quirks) {
+        self.bool(quirks.shift_uses_vy);
+        self.bool(quirks.jump_uses_vx);
+        self.u8(IndexIncrement::ALL
+            .iter()
+            .position(|increment| *increment == quirks.index_increment)
+            .unwrap() as u8);
+        self.bool(quirks.vf_reset);
+        self.bool(quirks.clip_sprites);
+        self.bool(quirks.display_wait);
+        self.bool(quirks.key_wait_release);
+        self.bool(quirks.collision_row_count);
+    }
+
+    pub(crate) fn display_buffer(&mut self, display_buffer: &DisplayBuffer) {
+        self.bool(display_buffer.resolution() == Resolution::High);
+        for plane in display_buffer.planes() {
+            for row in plane {
+                self.bytes(&row.to_le_bytes());
+            }
+        }
+    }
+
+    pub(crate) fn platform(&mut self, platform: Platform) {
+        self.u8(Platform::ALL.iter().position(|p| *p == platform).unwrap() as u8);
+    }
+
+    // the mode, the interpreter's page for the COSMAC VIP mode, the seed and the state
+    pub(crate) fn random(&mut self, random: &Random) {
+        let mode = random.mode();
+        self.u8(RandomMode::ALL.iter().position(|m| *m == mode).unwrap() as u8);
+        if let Some(page) = random.vip_page() {
+            self.bytes(page);
+        }
+        self.u64(random.seed());
+        self.u64(random.state());
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // everything that hasn't been read yet
+    pub(crate) fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("expected a boolean")),
+        }
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn quirks(&mut self) -> Result<Quirks, StateError> {
+        let shift_uses_vy = self.bool()?;
+        let jump_uses_vx = self.bool()?;
+        let index_increment = *IndexIncrement::ALL
+            .get(self.u8()? as usize)
+            .ok_or(StateError::Invalid("unknown index increment quirk"))?;
+        Ok(Quirks {
+            shift_uses_vy,
+            jump_uses_vx,
+            index_increment,
+            vf_reset: self.bool()?,
+            clip_sprites: self.bool()?,
+            display_wait: self.bool()?,
+            key_wait_release: self.bool()?,
+            collision_row_count: self.bool()?,
+        })
+    }
+
+    pub(crate) fn display_buffer(&mut self) -> Result<DisplayBuffer, StateError> {
+        let resolution = if self.bool()? {
+            Resolution::High
+        } else {
+            Resolution::Low
+        };
+        let mut planes = [[0; MAX_HEIGHT]; PLANE_COUNT];
+        for plane in planes.iter_mut() {
+            for row in plane.iter_mut() {
+                *row = u128::from_le_bytes(self.array()?);
+            }
+        }
+        Ok(DisplayBuffer::from_planes(resolution, planes))
+    }
+
+    pub(crate) fn platform(&mut self) -> Result<Platform, StateError> {
+        Platform::ALL
+            .get(self.u8()? as usize)
+            .copied()
+            .ok_or(StateError::Invalid("unknown platform"))
+    }
+
+    pub(crate) fn random(&mut self) -> Result<Random, StateError> {
+        let mode = RandomMode::ALL
+            .get(self.u8()? as usize)
+            .copied()
+            .ok_or(StateError::Invalid("unknown random mode"))?;
+        let vip_page = match mode {
+            RandomMode::Standard => None,
+            RandomMode::CosmacVip => Some(self.array()?),
+        };
+        let seed = self.u64()?;
+        let state = self.u64()?;
+        if mode == RandomMode::CosmacVip && state > 0xFFFF {
+            return Err(StateError::Invalid("COSMAC VIP random state out of range"));
+        }
+        Ok(Random::from_state(seed, state, vip_page))
+    }
+}
+
+// the CRC-32 used by zip and png
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Emulator;
+    use crate::quirks::Platform;
+    use crate::random::VIP_INTERPRETER_SIZE;
+
+    // a machine with a bit of everything in it: a return address on the stack,
+    // memory written, a sprite on the screen and a key down
+    fn snapshot() -> Snapshot {
+        let rom = vec![
+            0x22, 0x04, 0x00, 0x00, 0xA3, 0x00, 0x60, 0x2A, 0xF0, 0x55, 0xD0, 0x05,
+        ];
+        let mut emulator = Emulator::builder()
+            .platform(Platform::XoChip)
+            .seed(7)
+            .rom(rom)
+            .build()
+            .unwrap();
+        emulator.set_key(3, true);
+        for _ in 0..5 {
+            emulator.step().unwrap();
+        }
+        emulator.end_frame();
+        emulator.snapshot()
+    }
+
+    #[test]
+    fn round_trip() {
+        let snapshot = snapshot();
+        assert_eq!(
+            Snapshot::from_bytes(&snapshot.to_bytes()),
+            Ok(snapshot.clone())
+        );
+        assert_eq!(Snapshot::from_data(&snapshot.to_data()), Ok(snapshot));
+    }
+
+    #[test]
+    fn round_trip_with_the_cosmac_vip_generator() {
+        let emulator = Emulator::builder()
+            .seed(7)
+            .vip_random(vec![3; VIP_INTERPRETER_SIZE])
+            .build()
+            .unwrap();
+        let snapshot = emulator.snapshot();
+        assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()), Ok(snapshot));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(Snapshot::from_bytes(b"C8RP"), Err(StateError::NotAState));
+        assert_eq!(Snapshot::from_bytes(b""), Err(StateError::NotAState));
+
+        let mut bytes = snapshot().to_bytes();
+        bytes[4..6].copy_from_slice(&99u16.to_le_bytes());
+        assert_eq!(
+            Snapshot::from_bytes(&bytes),
+            Err(StateError::UnsupportedVersion { version: 99 })
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_states() {
+        let bytes = snapshot().to_bytes();
+        assert_eq!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(StateError::Truncated)
+        );
+
+        let mut corrupted = bytes.clone();
+        corrupted[HEADER_SIZE + 0x300] ^= 1;
+        assert_eq!(
+            Snapshot::from_bytes(&corrupted),
+            Err(StateError::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let data = snapshot().to_data();
+
+        // the selected planes come before the audio pattern, the random generator and the frame
+        let mut invalid = data.clone();
+        let selected_planes = invalid.len() - 8 - 17 - 17 - 1;
+        invalid[selected_planes] = 4;
+        assert_eq!(
+            Snapshot::from_data(&invalid),
+            Err(StateError::Invalid("unknown bitplanes selected"))
+        );
+
+        let mut invalid = data.clone();
+        let random_mode = invalid.len() - 8 - 17;
+        invalid[random_mode] = RandomMode::ALL.len() as u8;
+        assert_eq!(
+            Snapshot::from_data(&invalid),
+            Err(StateError::Invalid("unknown random mode"))
+        );
+
+        let mut invalid = data.clone();
+        invalid[0] = Platform::ALL.len() as u8;
+        assert_eq!(
+            Snapshot::from_data(&invalid),
+            Err(StateError::Invalid("unknown platform"))
+        );
+
+        let mut longer = data.clone();
+        longer.push(0);
+        assert_eq!(
+            Snapshot::from_data(&longer),
+            Err(StateError::Invalid("unexpected data after the state"))
+        );
+
+        assert_eq!(
+            Snapshot::from_data(&data[..data.len() - 1]),
+            Err(StateError::Truncated)
+        );
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+}
@@ -0,0 +1,239 @@
+use chip8_rust::Emulator;
+use chip8_rust::backend::Input;
+use chip8_rust::replay::Movie;
+use std::fs;
+
+// Tool-assisted play. The keypad isn't held down live, pressing a keypad key toggles it for the
+// frames that follow, and the emulator starts paused so a run can be built one frame at a time.
+// Every frame that runs records the keys it saw in a movie, which is written out as an input
+// recording whenever the emulator pauses.
+//
+// Loading a state or rewinding goes back to an earlier frame. Running on from there plays the
+// movie back as long as the keys are left alone, changing them branches the movie off:
+// everything that was recorded after that frame is dropped.
+pub struct Tas {
+    movie: Movie,
+
+    // where the movie is saved, None to not save it
+    path: Option<String>,
+
+    // the keyboard, whose keypad keys toggle keys instead of pressing them
+    keyboard: Box<dyn Input>,
+}
+
+impl Tas {
+    pub fn new(movie: Movie, path: Option<String>, keyboard: Box<dyn Input>) -> Self {
+        Self {
+            movie,
+            path,
+            keyboard,
+        }
+    }
+
+    // toggle the keys pressed on the keyboard, returns true if any changed
+    pub fn poll_keyboard(&mut self, emulator: &mut Emulator) -> bool {
+        let mut changed = false;
+        while let Some(event) = self.keyboard.poll() {
+            if event.pressed {
+                let pressed = emulator.pressed_keys()[event.key as usize];
+                emulator.set_key(event.key, !pressed);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // call right before the emulator runs a frame
+    pub fn record_frame(&mut self, emulator: &Emulator) {
+        self.movie
+            .record(emulator.frame() as usize, keypad(emulator.pressed_keys()));
+    }
+
+    // call after the emulator moved to another frame, by running or by going back to an earlier
+    // one. Sets the keypad to what the movie has for that frame, so running on plays it back
+    pub fn load_keys(&self, emulator: &mut Emulator) {
+        let Some(keys) = self.movie.keys(emulator.frame() as usize) else {
+            return;
+        };
+        for key in 0..16 {
+            emulator.set_key(key, keys & (1 << key) != 0);
+        }
+    }
+
+    pub fn movie_len(&self) -> usize {
+        self.movie.len()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        fs::write(path, self.movie.to_replay().to_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+// the keypad as a bit mask, bit n is set while key n is down
+fn keypad(pressed_keys: &[bool; 16]) -> u16 {
+    pressed_keys
+        .iter()
+        .enumerate()
+        .fold(0, |keys, (key, pressed)| keys | (*pressed as u16) << key)
+}
+
+// the keys that are down, e.g "1 5 A", for the window title
+pub fn keypad_description(pressed_keys: &[bool; 16]) -> String {
+    let keys: Vec<String> = (0..16)
+        .filter(|key| pressed_keys[*key])
+        .map(|key| format!("{:X}", key))
+        .collect();
+    if keys.is_empty() {
+        "none".to_string()
+    } else {
+        keys.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8_rust::backend::KeyEvent;
+    use chip8_rust::replay::{Replay, ReplayHeader};
+    use chip8_rust::{Platform, Quirks, Speed};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    // a program that loops forever
+    const ROM: [u8; 2] = [0x12, 0x00];
+
+    // a keyboard the test types on while the Tas owns it
+    #[derive(Clone, Default)]
+    struct Keyboard(Arc<Mutex<VecDeque<KeyEvent>>>);
+
+    impl Keyboard {
+        fn press(&self, key: u8) {
+            let mut events = self.0.lock().unwrap();
+            events.push_back(KeyEvent { key, pressed: true });
+            events.push_back(KeyEvent {
+                key,
+                pressed: false,
+            });
+        }
+    }
+
+    impl Input for Keyboard {
+        fn poll(&mut self) -> Option<KeyEvent> {
+            self.0.lock().unwrap().pop_front()
+        }
+    }
+
+    fn setup(path: Option<String>) -> (Tas, Keyboard, Emulator) {
+        let header = ReplayHeader::new(
+            &ROM,
+            Platform::default(),
+            Quirks::default(),
+            Speed::CyclesPerFrame(1),
+            60,
+            0,
+            None,
+        );
+        let keyboard = Keyboard::default();
+        let tas = Tas::new(Movie::new(header.clone()), path, Box::new(keyboard.clone()));
+        let emulator = header
+            .configure(Emulator::builder().rom(ROM.to_vec()))
+            .build()
+            .unwrap();
+        (tas, keyboard, emulator)
+    }
+
+    // what the runner does for every frame
+    fn run_frame(tas: &mut Tas, emulator: &mut Emulator) {
+        tas.poll_keyboard(emulator);
+        tas.record_frame(emulator);
+        emulator.run_frame().unwrap();
+        tas.load_keys(emulator);
+    }
+
+    #[test]
+    fn keypad_keys_toggle() {
+        let (mut tas, keyboard, mut emulator) = setup(None);
+        assert!(!tas.poll_keyboard(&mut emulator));
+
+        keyboard.press(5);
+        assert!(tas.poll_keyboard(&mut emulator));
+        assert!(emulator.pressed_keys()[5]);
+
+        // released on the keyboard, still down on the keypad until pressed again
+        assert!(!tas.poll_keyboard(&mut emulator));
+        assert!(emulator.pressed_keys()[5]);
+        keyboard.press(5);
+        tas.poll_keyboard(&mut emulator);
+        assert!(!emulator.pressed_keys()[5]);
+    }
+
+    #[test]
+    fn going_back_plays_the_movie() {
+        let (mut tas, keyboard, mut emulator) = setup(None);
+        let start = emulator.snapshot();
+        keyboard.press(1);
+        run_frame(&mut tas, &mut emulator);
+        keyboard.press(1);
+        run_frame(&mut tas, &mut emulator);
+        run_frame(&mut tas, &mut emulator);
+        assert_eq!(tas.movie_len(), 3);
+
+        emulator.restore(&start);
+        tas.load_keys(&mut emulator);
+        assert!(emulator.pressed_keys()[1]);
+        run_frame(&mut tas, &mut emulator);
+        assert!(!emulator.pressed_keys()[1]);
+        assert_eq!(tas.movie_len(), 3);
+    }
+
+    #[test]
+    fn changing_the_keys_branches_off() {
+        let (mut tas, keyboard, mut emulator) = setup(None);
+        let start = emulator.snapshot();
+        for _ in 0..3 {
+            run_frame(&mut tas, &mut emulator);
+        }
+
+        emulator.restore(&start);
+        tas.load_keys(&mut emulator);
+        keyboard.press(0xA);
+        run_frame(&mut tas, &mut emulator);
+        assert_eq!(tas.movie_len(), 1);
+        assert!(emulator.pressed_keys()[0xA]);
+    }
+
+    #[test]
+    fn saves_the_movie_as_a_recording() {
+        let path = std::env::temp_dir().join(format!("chip8-rust-{}-tas.c8r", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let (mut tas, keyboard, mut emulator) = setup(Some(path.clone()));
+        run_frame(&mut tas, &mut emulator);
+        keyboard.press(3);
+        run_frame(&mut tas, &mut emulator);
+        tas.save().unwrap();
+
+        let replay = Replay::from_bytes(&fs::read(&path).unwrap()).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(replay.events.len(), 1);
+        assert_eq!(replay.events[0].frame, 1);
+        assert_eq!(
+            replay.events[0].event,
+            KeyEvent {
+                key: 3,
+                pressed: true
+            }
+        );
+    }
+
+    #[test]
+    fn keypad_descriptions() {
+        let mut keys = [false; 16];
+        assert_eq!(keypad_description(&keys), "none");
+        keys[1] = true;
+        keys[0xA] = true;
+        assert_eq!(keypad_description(&keys), "1 A");
+    }
+}
@@ -0,0 +1,408 @@
+use chip8_rust::EmulatorError;
+use chip8_rust::debugger::{Debugger, Location, Stop};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::time::Duration;
+
+// The --tui debugger, the same debugger as --debug laid out on one full-screen terminal page:
+// disassembly, registers, stack, memory and the screen, driven by single keys.
+// The disassembly has a cursor for setting breakpoints and running to.
+pub struct Tui {
+    debugger: Debugger,
+
+    // the address the disassembly's cursor is on, moves to pc whenever execution stops
+    cursor: u16,
+
+    // first address of the memory view, None to follow the index register
+    memory_start: Option<u16>,
+
+    // running in real time until a breakpoint, Esc or reaching the target of a step
+    running: bool,
+
+    // what happened last, shown above the key help
+    status: String,
+}
+
+const KEY_HELP: [&str; 2] = [
+    "s step  n step over  o step out  r run to cursor  c continue  f frame  \
+S reverse step  C reverse continue",
+    "b breakpoint  \u{2191}\u{2193} cursor  PgUp/PgDn memory  i follow I  Esc break  q quit",
+];
+
+// bytes the memory view scrolls by
+const MEMORY_PAGE: u16 = 0x40;
+
+impl Tui {
+    pub fn new(debugger: Debugger) -> Self {
+        let cursor = debugger.emulator().pc();
+        Self {
+            debugger,
+            cursor,
+            memory_start: None,
+            running: false,
+            status: "stopped before the first instruction".to_string(),
+        }
+    }
+
+    // takes over the terminal until q is pressed
+    pub fn run(&mut self) -> io::Result<()> {
+        self.debugger.emulator_mut().present_paused();
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            // while running keys are picked up in between frames, otherwise wait for one
+            if !self.running {
+                if !self.handle_event(event::read()?) {
+                    return Ok(());
+                }
+                continue;
+            }
+            while event::poll(Duration::ZERO)? {
+                if !self.handle_event(event::read()?) {
+                    return Ok(());
+                }
+            }
+            if self.running {
+                match self.debugger.run_due_frames() {
+                    Ok(Stop::FrameEnd) => {}
+                    result => self.stopped(result),
+                }
+            }
+        }
+    }
+
+    // returns false to quit
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::Key(key) if key.kind == KeyEventKind::Press => self.handle_key(key.code),
+            _ => true,
+        }
+    }
+
+    // returns false to quit
+    fn handle_key(&mut self, key: KeyCode) -> bool {
+        if self.running {
+            match key {
+                KeyCode::Char('q') => return false,
+                KeyCode::Esc | KeyCode::Char(' ') => {
+                    self.debugger.clear_target();
+                    self.stopped(Ok(Stop::Step));
+                    self.status = "interrupted".to_string();
+                }
+                _ => {}
+            }
+            return true;
+        }
+
+        match key {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('s') => {
+                let result = self.debugger.step();
+                self.stopped(result);
+            }
+            KeyCode::Char('n') => {
+                if self.debugger.step_over() {
+                    self.start_running("stepping over the call");
+                } else {
+                    let result = self.debugger.step();
+                    self.stopped(result);
+                }
+            }
+            KeyCode::Char('o') => {
+                if self.debugger.step_out() {
+                    self.start_running("stepping out of the subroutine");
+                } else {
+                    self.status = "not in a subroutine".to_string();
+                }
+            }
+            KeyCode::Char('r') => {
+                self.debugger.run_to(self.cursor);
+                self.start_running(&format!("running to {:#05x}", self.cursor));
+            }
+            KeyCode::Char('c') => {
+                self.debugger.clear_target();
+                self.start_running("running");
+            }
+            KeyCode::Char('f') => {
+                let result = self.debugger.finish_frame();
+                self.stopped(result);
+            }
+            KeyCode::Char('S') => {
+                let stop = self.debugger.reverse_step();
+                self.stopped(Ok(stop));
+            }
+            KeyCode::Char('C') => {
+                let result = self.debugger.reverse_continue();
+                self.stopped(result);
+            }
+            KeyCode::Char('b') => {
+                let cursor = self.cursor;
+                self.status = match self.debugger.breakpoint_at(cursor) {
+                    Some(id) => {
+                        self.debugger.remove_breakpoint(id);
+                        format!("deleted the breakpoint at {:#05x}", cursor)
+                    }
+                    None => {
+                        self.debugger
+                            .add_breakpoint(Location::Address(cursor), None);
+                        format!("breakpoint at {:#05x}", cursor)
+                    }
+                };
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.cursor = self.cursor.saturating_sub(2),
+            KeyCode::Down | KeyCode::Char('j') => {
+                let size = self
+                    .debugger
+                    .emulator()
+                    .instruction_at(self.cursor)
+                    .map_or(2, |instruction| instruction.size());
+                self.cursor = self.cursor.saturating_add(size);
+            }
+            KeyCode::PageUp => {
+                self.memory_start = Some(self.memory_start().saturating_sub(MEMORY_PAGE));
+            }
+            KeyCode::PageDown => {
+                self.memory_start = Some(self.memory_start().saturating_add(MEMORY_PAGE));
+            }
+            KeyCode::Char('i') => self.memory_start = None,
+            _ => {}
+        }
+        true
+    }
+
+    fn start_running(&mut self, status: &str) {
+        self.debugger.emulator_mut().resync();
+        self.running = true;
+        self.status = format!("{}, Esc to break", status);
+    }
+
+    fn stopped(&mut self, result: Result<Stop, EmulatorError>) {
+        self.running = false;
+        self.debugger.emulator_mut().present_paused();
+        self.cursor = self.debugger.emulator().pc();
+        self.status = match result {
+            Ok(Stop::Step) => String::new(),
+            Ok(Stop::FrameEnd) => format!("end of frame {}", self.debugger.emulator().frame() - 1),
+            Ok(Stop::Breakpoint(id)) => match self.debugger.breakpoint(id) {
+                Some(breakpoint) => format!("breakpoint {}: {}", id, breakpoint),
+                None => format!("breakpoint {}", id),
+            },
+            Ok(Stop::Watchpoint { id, pc, access }) => format!(
+                "watchpoint {}: {} of {:#05x} by the instruction at {:#05x}",
+                id, access.kind, access.address, pc
+            ),
+            Ok(Stop::Reached(address)) => format!("stopped at {:#05x}", address),
+            Ok(Stop::Exited) => "the program exited".to_string(),
+            Ok(Stop::StartOfHistory) => "no more history to go back through".to_string(),
+            Err(e) => format!("halted: {}", e),
+        };
+    }
+
+    fn memory_start(&self) -> u16 {
+        self.memory_start
+            .unwrap_or(self.debugger.emulator().index_register())
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let display = self.debugger.emulator().display_buffer();
+        // two rows of pixels to a line of text
+        let screen_height = display.height() as u16 / 2 + 2;
+        let screen_width = display.width() as u16 + 2;
+
+        let [top, middle, bottom] = Layout::vertical([
+            Constraint::Min(10),
+            Constraint::Length(screen_height),
+            Constraint::Length(3),
+        ])
+        .areas(frame.area());
+        let [disassembly, registers, stack] = Layout::horizontal([
+            Constraint::Min(36),
+            Constraint::Length(24),
+            Constraint::Length(16),
+        ])
+        .areas(top);
+        let [screen, memory] =
+            Layout::horizontal([Constraint::Length(screen_width), Constraint::Min(20)])
+                .areas(middle);
+
+        self.draw_disassembly(frame, disassembly);
+        self.draw_registers(frame, registers);
+        self.draw_stack(frame, stack);
+        self.draw_screen(frame, screen);
+        self.draw_memory(frame, memory);
+
+        let mut status = vec![Line::from(self.status.as_str())];
+        for help in KEY_HELP {
+            status.push(Line::styled(help, Style::new().add_modifier(Modifier::DIM)));
+        }
+        frame.render_widget(Paragraph::new(status), bottom);
+    }
+
+    // a third of the lines above the cursor, pc is marked with => and breakpoints with *
+    fn draw_disassembly(&self, frame: &mut Frame, area: Rect) {
+        let emulator = self.debugger.emulator();
+        let rows = area.height.saturating_sub(2);
+        let mut address = self.cursor.saturating_sub(rows / 3 * 2);
+
+        let mut lines = Vec::new();
+        for _ in 0..rows {
+            let Some(instruction) = emulator.instruction_at(address) else {
+                break;
+            };
+            let marker = if address == emulator.pc() { "=>" } else { "  " };
+            let breakpoint = if self.debugger.breakpoint_at(address).is_some() {
+                "*"
+            } else {
+                " "
+            };
+            let text = format!(
+                "{}{} {:#05x}  {:04x}  {}",
+                marker,
+                breakpoint,
+                address,
+                instruction.opcode(),
+                instruction
+            );
+            let style = if address == self.cursor {
+                Style::new().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::new()
+            };
+            lines.push(Line::styled(text, style));
+
+            // lines above the cursor step by 2 so the cursor's line is never skipped
+            let size = if address < self.cursor {
+                2
+            } else {
+                instruction.size()
+            };
+            address = match address.checked_add(size) {
+                Some(address) => address,
+                None => break,
+            };
+        }
+
+        let block = Block::bordered().title(" Disassembly ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    fn draw_registers(&self, frame: &mut Frame, area: Rect) {
+        let emulator = self.debugger.emulator();
+        let v = emulator.var_registers();
+        let mut lines = vec![
+            Line::from(format!(
+                "PC {:#06x}  I  {:#06x}",
+                emulator.pc(),
+                emulator.index_register()
+            )),
+            Line::from(format!(
+                "DT {:<6}  ST {}",
+                emulator.delay_timer(),
+                emulator.sound_timer()
+            )),
+            Line::from(format!("frame {}", emulator.frame())),
+            Line::from(""),
+        ];
+        for x in 0..8 {
+            lines.push(Line::from(format!(
+                "V{:X} {:02x}      V{:X} {:02x}",
+                x,
+                v[x],
+                x + 8,
+                v[x + 8]
+            )));
+        }
+
+        let block = Block::bordered().title(" Registers ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    // innermost call first, the addresses the calls return to
+    fn draw_stack(&self, frame: &mut Frame, area: Rect) {
+        let lines: Vec<Line> = self
+            .debugger
+            .emulator()
+            .stack()
+            .iter()
+            .rev()
+            .map(|address| Line::from(format!("{:#05x}", address)))
+            .collect();
+
+        let block = Block::bordered().title(" Stack ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    // pixels of any plane are drawn lit, two rows to a line with half blocks
+    fn draw_screen(&self, frame: &mut Frame, area: Rect) {
+        let display = self.debugger.emulator().display_buffer();
+        let lines: Vec<Line> = (0..display.height())
+            .step_by(2)
+            .map(|y| {
+                let text: String = (0..display.width())
+                    .map(
+                        |x| match (display.pixel(x, y) != 0, display.pixel(x, y + 1) != 0) {
+                            (true, true) => '\u{2588}',
+                            (true, false) => '\u{2580}',
+                            (false, true) => '\u{2584}',
+                            (false, false) => ' ',
+                        },
+                    )
+                    .collect();
+                Line::from(text)
+            })
+            .collect();
+
+        let block = Block::bordered().title(" Screen ");
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+
+    // as many bytes to a line as fit in a power of two, the byte I points at is highlighted
+    fn draw_memory(&self, frame: &mut Frame, area: Rect) {
+        let emulator = self.debugger.emulator();
+        let memory = emulator.memory();
+        let index = emulator.index_register() as usize;
+
+        let width = (area.width.saturating_sub(2 + 8) / 3).max(1);
+        let per_line = (1 << width.ilog2()).clamp(4, 32) as usize;
+        let start = self.memory_start() as usize / per_line * per_line;
+
+        let mut lines = Vec::new();
+        for line_start in (start..memory.len())
+            .step_by(per_line)
+            .take(area.height.saturating_sub(2) as usize)
+        {
+            let mut spans = vec![format!("{:#06x}  ", line_start).into()];
+            let end = (line_start + per_line).min(memory.len());
+            for (address, byte) in memory.iter().enumerate().take(end).skip(line_start) {
+                let style = if address == index {
+                    Style::new().add_modifier(Modifier::REVERSED)
+                } else {
+                    Style::new()
+                };
+                spans.push(Span::styled(format!("{:02x}", byte), style));
+                spans.push(" ".into());
+            }
+            lines.push(Line::from(spans));
+        }
+
+        let title = if self.memory_start.is_none() {
+            " Memory at I "
+        } else {
+            " Memory "
+        };
+        let block = Block::bordered().title(title);
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::audio::{AudioPattern, Buzzer};
+use crate::backend::Audio;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+// Records what the emulator plays to a 16-bit mono WAV file.
+//
+// Every frame renders exactly one frame's worth of samples from the sound timer state,
+// so the recording only depends on the program and its input, not on how the audio device
+// or the host happened to be keeping time. Calls are passed on to another backend so the
+// sound can still be heard while recording.
+
+const HEADER_SIZE: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+const BYTES_PER_SAMPLE: u64 = BITS_PER_SAMPLE as u64 / 8;
+
+// the sizes in the header are 32 bits, which caps the samples at a little under 4 GiB
+const MAX_DATA_SIZE: u64 = u32::MAX as u64 - (HEADER_SIZE as u64 - 8);
+
+pub struct WavRecorder {
+    inner: Box<dyn Audio>,
+
+    buzzer: Buzzer,
+
+    // None once writing failed, the emulator carries on without recording
+    writer: Option<BufWriter<File>>,
+
+    frame_rate: u32,
+
+    // samples that have been handed out but not yet added up to a whole sample,
+    // in units of 1 / frame_rate of a sample
+    sample_remainder: u32,
+
+    samples_written: u64,
+
+    // frames recorded since the header was last patched with the size
+    frames_since_patch: u32,
+}
+
+impl WavRecorder {
+    pub fn create(
+        path: &str,
+        buzzer: Buzzer,
+        frame_rate: u32,
+        inner: Box<dyn Audio>,
+    ) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_header(&mut writer, buzzer.sample_rate(), 0)?;
+
+        Ok(Self {
+            inner,
+            buzzer,
+            writer: Some(writer),
+            frame_rate,
+            sample_remainder: 0,
+            samples_written: 0,
+            frames_since_patch: 0,
+        })
+    }
+
+    // render one frame of sound. The header is patched once a second, so the file is valid
+    // and only misses the last second even if the process is killed without dropping the recorder
+    fn record_frame(&mut self, playing: bool) -> io::Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+
+        self.sample_remainder += self.buzzer.sample_rate();
+        let samples = self.sample_remainder / self.frame_rate;
+        self.sample_remainder %= self.frame_rate;
+
+        if (self.samples_written + samples as u64) * BYTES_PER_SAMPLE > MAX_DATA_SIZE {
+            return Err(io::Error::other("WAV files can't hold more than 4 GiB"));
+        }
+
+        for _ in 0..samples {
+            let sample = self.buzzer.next_sample(playing);
+            let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            writer.write_all(&pcm.to_le_bytes())?;
+        }
+        self.samples_written += samples as u64;
+
+        self.frames_since_patch += 1;
+        if self.frames_since_patch >= self.frame_rate {
+            self.patch_header()?;
+        }
+
+        Ok(())
+    }
+
+    // write the size of everything recorded so far into the header
+    fn patch_header(&mut self) -> io::Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+
+        // record_frame never lets the size go past MAX_DATA_SIZE
+        let data_size = (self.samples_written * BYTES_PER_SAMPLE) as u32;
+        writer.seek(SeekFrom::Start(0))?;
+        write_header(writer, self.buzzer.sample_rate(), data_size)?;
+        writer.seek(SeekFrom::End(0))?;
+        writer.flush()?;
+
+        self.frames_since_patch = 0;
+        Ok(())
+    }
+
+    // stop recording after an error, keeping the samples that made it into the file
+    fn stop_recording(&mut self, e: io::Error) {
+        eprintln!("failed to record audio, recording stopped: {}", e);
+        let _ = self.patch_header();
+        self.writer = None;
+    }
+}
+
+impl Audio for WavRecorder {
+    fn set_playing(&mut self, playing: bool) {
+        self.inner.set_playing(playing);
+
+        if let Err(e) = self.record_frame(playing) {
+            self.stop_recording(e);
+        }
+    }
+
+    // no time passes for the program while it's stopped, so nothing is recorded
+    fn stop(&mut self) {
+        self.inner.stop();
+    }
+
+    fn set_pattern(&mut self, pattern: &AudioPattern) {
+        self.inner.set_pattern(pattern);
+        self.buzzer.set_pattern(*pattern);
+    }
+}
+
+impl Drop for WavRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.patch_header() {
+            eprintln!("failed to finish the audio recording: {}", e);
+        }
+    }
+}
+
+// canonical 44 byte header of a mono PCM WAV file holding data_size bytes of samples
+fn write_header(writer: &mut impl Write, sample_rate: u32, data_size: u32) -> io::Result<()> {
+    let block_align = BITS_PER_SAMPLE / 8;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // mono
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::Beep;
+    use crate::backend::NullAudio;
+    use crate::chip8::Emulator;
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    const FRAME_RATE: u32 = 600;
+
+    fn recording_path(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("chip8-rust-{}-{}.wav", std::process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    // an emulator that beeps for 8 frames out of every 15, recording to path
+    fn beeping_emulator(path: &str) -> Emulator {
+        let rom = [
+            0x6008, 0xF018, 0x610F, 0xF115, 0xF107, 0x3100, 0x1208, 0x1200,
+        ]
+        .iter()
+        .flat_map(|opcode: &u16| opcode.to_be_bytes())
+        .collect();
+        let buzzer = Buzzer::new(Beep::default(), 1.0, 44100);
+        let recorder = WavRecorder::create(path, buzzer, FRAME_RATE, Box::new(NullAudio)).unwrap();
+        Emulator::builder()
+            .frame_rate(FRAME_RATE)
+            .audio(recorder)
+            .rom(rom)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn recording_does_not_depend_on_real_time() {
+        let fast_path = recording_path("fast");
+        let mut fast = beeping_emulator(&fast_path);
+        for _ in 0..120 {
+            fast.run_frame().unwrap();
+        }
+        drop(fast);
+
+        // a host that stalls now and then and catches up with several frames at once
+        let slow_path = recording_path("slow");
+        let mut slow = beeping_emulator(&slow_path);
+        while slow.frame() < 120 {
+            thread::sleep(Duration::from_millis(5));
+            let frames = slow.wait_for_frames().min((120 - slow.frame()) as u32);
+            for _ in 0..frames {
+                slow.run_frame().unwrap();
+            }
+        }
+        drop(slow);
+
+        let fast = fs::read(&fast_path).unwrap();
+        let slow = fs::read(&slow_path).unwrap();
+        fs::remove_file(fast_path).unwrap();
+        fs::remove_file(slow_path).unwrap();
+
+        // 120 frames at 600 per second is a fifth of a second of samples
+        let data_size = 44100 / 5 * 2;
+        assert_eq!(fast.len(), HEADER_SIZE as usize + data_size);
+        assert_eq!(fast[40..44], (data_size as u32).to_le_bytes());
+        assert!(fast[44..].iter().any(|byte| *byte != 0));
+        assert!(fast == slow);
+    }
+
+    #[test]
+    fn paused_time_is_not_recorded() {
+        let path = recording_path("paused");
+        let mut emulator = beeping_emulator(&path);
+        emulator.run_frame().unwrap();
+        for _ in 0..10 {
+            emulator.present_paused();
+        }
+        emulator.run_frame().unwrap();
+        drop(emulator);
+
+        let recording = fs::read(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        // two frames at 600 per second are 147 samples
+        assert_eq!(recording.len(), HEADER_SIZE as usize + 147 * 2);
+    }
+
+    #[test]
+    fn stops_at_the_size_limit() {
+        let path = recording_path("limit");
+        let buzzer = Buzzer::new(Beep::default(), 1.0, 44100);
+        let mut recorder = WavRecorder::create(&path, buzzer, 60, Box::new(NullAudio)).unwrap();
+        recorder.set_playing(true);
+        assert!(recorder.writer.is_some());
+
+        recorder.samples_written = MAX_DATA_SIZE / BYTES_PER_SAMPLE - 100;
+        recorder.set_playing(true);
+        assert!(recorder.writer.is_none());
+
+        drop(recorder);
+        fs::remove_file(path).unwrap();
+    }
+}